@@ -14,6 +14,9 @@ class Mod {
   version: string;
 }
 
+// state of /get-game-details request, hostAddress and mods are present only if 'fetched'
+type DetailsStatus = 'notFetched' | 'fetched' | 'unavailable' | 'skipped';
+
 class Game {
   gameId: GameId;
   serverId: ServerId;
@@ -29,13 +32,40 @@ class Game {
   description: string;
   maxPlayers: number;
   gameVersion: string;
+  buildVersion?: number;
+  buildMode?: string;
+  platform?: string;
   gameTimeElapsed: number;
   hasPassword: boolean;
   tags: string[];
   modCount: number;
 
-  hostAddress: string;
+  detailsStatus: DetailsStatus;
+  hostAddress?: string;
   mods?: Mod[];
+  detailsRevisions: DetailsRevision[];
+  steamId?: string;
+  modsCrc?: string;
+  requireUserVerification?: boolean;
+  lastHeartbeat?: TimeMinutes;
+}
+
+// change of mods or host address during the game, pairs are [old value, new value]
+class DetailsRevision {
+  time: TimeMinutes;
+  hostAddress?: [string, string];
+  mods?: [[string, string][], [string, string][]];
+}
+
+// [begin, end) minutes without data
+class ObservationGap {
+  begin: TimeMinutes;
+  end: TimeMinutes;
+}
+
+class ServerInfo {
+  games: Game[];
+  observationGaps: ObservationGap[];
 }
 
 class GameSearchInfo {
@@ -58,7 +88,129 @@ class TopGamesByNumberPlayersMax {
   time: TimeMinutes;
 }
 
+class TopGamesByGameTimeElapsed {
+  serverId: ServerId;
+  name: string;
+  // in minutes
+  gameTimeElapsed: number;
+}
+
+class TopServersByPlayerMinutes {
+  serverId: ServerId;
+  name: string;
+  playerMinutes: number;
+}
+
+class TopServersByUniquePlayers {
+  serverId: ServerId;
+  name: string;
+  numberPlayers: number;
+}
+
 class MainPageInfo {
   topGamesByNumberPlayersNow: TopGamesByNumberPlayersNow[];
   topGamesByNumberPlayersMax: TopGamesByNumberPlayersMax[];
+  topGamesByGameTimeElapsed: TopGamesByGameTimeElapsed[];
+  topServersByPlayerMinutesDay: TopServersByPlayerMinutes[];
+  topServersByPlayerMinutesWeek: TopServersByPlayerMinutes[];
+  topServersByPlayerMinutesMonth: TopServersByPlayerMinutes[];
+  topServersByPlayerMinutesAllTime: TopServersByPlayerMinutes[];
+  topServersByUniquePlayersWeek: TopServersByUniquePlayers[];
+}
+
+// aggregated number of online players for minutes [time, time + resolution)
+class OnlinePoint {
+  time: TimeMinutes;
+  min: number;
+  max: number;
+  avg: number;
+}
+
+// points without observations are omitted
+class OnlineSeries {
+  resolution: number;
+  points: OnlinePoint[];
+}
+
+// averages for minutes [time, time + resolution) across all public games
+class GlobalOnlinePoint {
+  time: TimeMinutes;
+  numberGames: number;
+  numberPlayers: number;
+  numberGamesWithPassword: number;
+  numberPlayersWithPassword: number;
+  // indexes correspond to `GlobalOnlineSeries.majorVersions`
+  numberGamesByVersion: number[];
+  numberPlayersByVersion: number[];
+}
+
+class GlobalOnlineSeries {
+  resolution: number;
+  // e.g. "0.18", "1.0"
+  majorVersions: string[];
+  points: GlobalOnlinePoint[];
+}
+
+// average number of online players for each (weekday, hour), weekdays starting from Monday
+class Heatmap {
+  weeks: number;
+  // in minutes, e.g. 180 for UTC+3
+  tzOffset: number;
+  average: (number | null)[][];
+}
+
+class PlayerServer {
+  serverId?: ServerId;
+  name: string;
+  // in minutes
+  playtime: number;
+  lastSeen: TimeMinutes;
+}
+
+class PlayerSession {
+  serverId?: ServerId;
+  gameId: GameId;
+  serverName: string;
+  begin: TimeMinutes;
+  end?: TimeMinutes;
+}
+
+class Player {
+  name: string;
+  // in minutes
+  totalPlaytime: number;
+  servers: PlayerServer[];
+  recentSessions: PlayerSession[];
+}
+
+class ModInfo {
+  name: string;
+  numberServersNow: number;
+  numberServersAll: number;
+  // on games with this mod
+  playerMinutes: number;
+}
+
+class ModsInfo {
+  total: number;
+  mods: ModInfo[];
+}
+
+class ModVersionsWeek {
+  weekBegin: TimeMinutes;
+  // [version, number servers]
+  versions: [string, number][];
+}
+
+class ModServerInfo {
+  serverId: ServerId;
+  name: string;
+  isOnline: boolean;
+  // on games with this mod
+  playerMinutes: number;
+}
+
+class ModPageInfo extends ModInfo {
+  versionsByWeek: ModVersionsWeek[];
+  topServers: ModServerInfo[];
 }
@@ -6,24 +6,68 @@ export default class Api {
     return Object.freeze(info) as MainPageInfo;
   }
 
-  static async searchServers(query: string): Promise<GameSearchInfo[]> {
+  static async searchServers(query: string, limit = 20, offset = 0): Promise<GameSearchInfo[]> {
     // todo
     //  RFC says that symbols []{} must be encoded in query params
     //  browsers (at least chrome and firefox) don't encode these symbols (because it may break existing apps)
     //  rocket follows the RFC, so it not work well with existing browsers
     //  https://github.com/SergioBenitez/Rocket/issues/924
 
-    const params = { query };
+    const params = { query, limit, offset };
     return (await axios.get('/search-servers', { params })).data;
   }
 
-  static async getServerInfo(serverId: ServerId, time_begin: TimeMinutes, time_end: TimeMinutes): Promise<Game[]> {
+  static async getServerInfo(serverId: ServerId, time_begin: TimeMinutes, time_end: TimeMinutes): Promise<ServerInfo> {
     const params = { time_end, time_begin };
-    const { games } = (await axios.get(`/server/${serverId}`, { params })).data;
+    const { games, observationGaps } = (await axios.get(`/server/${serverId}`, { params })).data;
+    let prevGameMods: Mod[] | undefined = undefined;
     for (const game of games) {
       game.playersIntervals = game.playersIntervals
           .map(([name, begin, end]) => ({ name, begin, end } as PlayerInterval));
+
+      // backend omits mods which are same as mods of previous game
+      if (game.modsSameAsPrevGame) {
+        game.mods = prevGameMods;
+      } else if (game.mods) {
+        game.mods = game.mods.map(([name, version]) => ({ name, version } as Mod));
+      }
+      delete game.modsSameAsPrevGame;
+      prevGameMods = game.mods;
     }
-    return games;
+    return { games, observationGaps };
+  }
+
+  static async getPlayer(name: string): Promise<Player> {
+    return (await axios.get(`/player/${encodeURIComponent(name)}`)).data;
+  }
+
+  // sort: 'servers' (number servers running the mod now) or 'playerHours'
+  static async getMods(sort = 'servers', limit?: number, offset?: number): Promise<ModsInfo> {
+    const params = { sort, limit, offset };
+    return (await axios.get('/mods', { params })).data;
+  }
+
+  static async getMod(name: string): Promise<ModPageInfo> {
+    return (await axios.get(`/mod/${encodeURIComponent(name)}`)).data;
+  }
+
+  static async getServerHeatmap(serverId: ServerId, weeks?: number, tz_offset?: number): Promise<Heatmap> {
+    const params = { weeks, tz_offset };
+    return (await axios.get(`/server/${serverId}/heatmap`, { params })).data;
+  }
+
+  static async getGlobalOnline(time_begin?: TimeMinutes, time_end?: TimeMinutes, resolution?: number): Promise<GlobalOnlineSeries> {
+    const params = { time_begin, time_end, resolution };
+    const series = (await axios.get('/global/online', { params })).data;
+    return Object.freeze(series) as GlobalOnlineSeries;
+  }
+
+  static async getServerOnline(serverId: ServerId, time_begin?: TimeMinutes, time_end?: TimeMinutes, resolution?: number): Promise<OnlineSeries> {
+    const params = { time_begin, time_end, resolution };
+    const { resolution: seriesResolution, points } = (await axios.get(`/server/${serverId}/online`, { params })).data;
+    return {
+      resolution: seriesResolution,
+      points: points.map(([time, min, max, avg]) => ({ time, min, max, avg } as OnlinePoint)),
+    };
   }
 }
@@ -4,7 +4,7 @@ use hashbrown::{HashMap, HashSet};
 use itertools::Itertools;
 
 use crate::external_storage::WholeState;
-use crate::state::{Game, Mod, PlayerInterval, State};
+use crate::state::{DetailsStatus, Game, GameBuild, Mod, PlayerInterval, State};
 
 pub fn analytics(whole_state: WholeState) {
     let state = whole_state.state;
@@ -12,11 +12,14 @@ pub fn analytics(whole_state: WholeState) {
 
     println!("\tБазовая статистика:");
     println!("число наблюдаемых game_id: {}", state.games.len());
-    println!("число наблюдаемых серверов: {}", state.game_ids.len());
+    println!("число наблюдаемых серверов: {}", state.get_server_ids().count());
     println!("game_ids_in_last_get_games_response.len(): {}", state.current_game_ids.len());
 
     println!("Игр с полученными details: {}",
              state.games.values().filter(|game| game.are_details_fetched()).count());
+    // игры, которые исчезли раньше чем через GET_GAME_DETAILS_DELAY минут
+    println!("Игр с пропущенными details (сэкономлено запросов /get-game-details): {}",
+             state.games.values().filter(|game| game.details_status == DetailsStatus::Skipped).count());
     println!("Игр с prev_game_id != None: {}",
              state.games.values().filter(|game| game.prev_game_id.is_some()).count());
     println!("Игр с server_id != None: {}",
@@ -34,6 +37,27 @@ pub fn analytics(whole_state: WholeState) {
         println!("scheduled to merge game_ids: {:?}", number_game_ids_to_merge);
     }
 
+    // хостинг: headless/steam, платформы, проверка пользователей
+    {
+        let count_by = |get_key: &dyn Fn(&Game) -> String| {
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            for game in state.games.values() {
+                *counts.entry(get_key(game)).or_insert(0) += 1;
+            }
+            counts.into_iter().sorted_by_key(|(_, count)| std::cmp::Reverse(*count)).collect::<Vec<_>>()
+        };
+        // сборка неизвестна у игр, сохранённых до того как она стала запоминаться
+        let get_build_field = |game: &Game, get_field: fn(&GameBuild) -> &String| {
+            game.build.map_or_else(|| "<unknown>".to_owned(), |build| get_field(state.get_build(build)).clone())
+        };
+        println!("games by build_mode: {:?}", count_by(&|game| get_build_field(game, |build| &build.build_mode)));
+        println!("games by platform: {:?}", count_by(&|game| get_build_field(game, |build| &build.platform)));
+        println!("games with steam_id: {}",
+                 state.details_extra.values().filter(|details_extra| details_extra.steam_id.is_some()).count());
+        println!("games with require_user_verification: {}",
+                 state.details_extra.values().filter(|details_extra| details_extra.require_user_verification == Some(true)).count());
+    }
+
     // mods duplication (when game has same mods as prev_game)
     {
         let mut number_matched_games = 0;
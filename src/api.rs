@@ -1,39 +1,130 @@
 use std::{env, fmt, fs};
 use std::error::Error;
 use std::num::NonZeroU32;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Mutex;
 
+use hashbrown::HashMap;
+use itertools::Itertools;
+use lazy_static::lazy_static;
 use reqwest::StatusCode;
 use serde::{de, Deserialize, Serialize};
 
 use crate::global_config::GLOBAL_CONFIG;
+use crate::state::TimeMinutes;
 use crate::util;
 
 pub type GetGamesResponse = Vec<Game>;
 pub type GetGameDetailsResponse = Game;
 
-const MOCK_API: bool = false;
+pub const DEFAULT_API_BASE_URL: &str = "https://multiplayer.factorio.com";
+
+#[derive(Debug)]
+pub enum ApiError {
+    // connection error, timeout, failure during reading body, etc
+    Transport(reqwest::Error),
+    // response has non-success status code
+    HttpStatus { status: StatusCode, body: String },
+    // response is not a valid json or doesn't match `Game` schema
+    Json(serde_json::Error),
+    // response is a valid json, but violates our assumptions about api (see `check_response`)
+    Invariant(String),
+}
+
+impl ApiError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ApiError::Transport(_) => "transport",
+            ApiError::HttpStatus { .. } => "http_status",
+            ApiError::Json(_) => "json",
+            ApiError::Invariant(_) => "invariant",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::Transport(err) => write!(f, "transport error: {}", err),
+            ApiError::HttpStatus { status, body } => write!(f, "http status {}, response text is `{}`", status, body),
+            ApiError::Json(err) => write!(f, "json error: {}", err),
+            ApiError::Invariant(message) => write!(f, "invariant violation: {}", message),
+        }
+    }
+}
+
+impl Error for ApiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ApiError::Transport(err) => Some(err),
+            ApiError::Json(err) => Some(err),
+            ApiError::HttpStatus { .. } | ApiError::Invariant(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(err: reqwest::Error) -> Self {
+        ApiError::Transport(err)
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(err: serde_json::Error) -> Self {
+        ApiError::Json(err)
+    }
+}
+
+lazy_static! {
+    // number of errors of each kind (see `ApiError::kind`) since process start
+    static ref ERROR_COUNTS: Mutex<HashMap<&'static str, u64>> = Mutex::new(HashMap::new());
+}
+
+/// should be called by fetchers for each failed request, returns number of errors of the same kind
+pub fn count_error(error: &ApiError) -> u64 {
+    let mut error_counts = ERROR_COUNTS.lock().unwrap();
+    let count = error_counts.entry(error.kind()).or_insert(0);
+    *count += 1;
+    *count
+}
+
+pub fn get_error_counts() -> Vec<(&'static str, u64)> {
+    ERROR_COUNTS.lock().unwrap().iter()
+        .map(|(&kind, &count)| (kind, count))
+        .sorted()
+        .collect()
+}
+
+pub mod unknown_fields;
+
+// fields which are not described in structs below are collected in `unknown_fields`
+// (see `check_unknown_fields`), so that new fields in api don't stop ingestion
+pub type UnknownFields = HashMap<String, serde_json::Value>;
 
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
 pub struct ApplicationVersion {
     pub game_version: String,
     #[serde(deserialize_with = "convert_from_string")]
     pub build_version: u32,
     pub build_mode: String,
     pub platform: String,
+
+    #[serde(flatten)]
+    pub unknown_fields: UnknownFields,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Mod {
     pub name: String,
     pub version: String,
+
+    #[serde(flatten)]
+    pub unknown_fields: UnknownFields,
 }
 
 // более правильным было бы назвать этот класс GameSnapshot
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
 pub struct Game {
     // common fields
     pub game_id: NonZeroU32,
@@ -68,6 +159,9 @@ pub struct Game {
     pub mods_crc: Option<u64>,
     pub steam_id: Option<String>,
     pub require_user_verification: Option<String>,
+
+    #[serde(flatten)]
+    pub unknown_fields: UnknownFields,
 }
 
 pub fn convert_from_string<'de, T, D>(deserializer: D) -> Result<T, D::Error>
@@ -84,35 +178,91 @@ pub fn convert_from_string<'de, T, D>(deserializer: D) -> Result<T, D::Error>
     T::from_str(&s).map_err(de::Error::custom)
 }
 
-fn check_response(game: &Game, is_get_games_response: bool) {
-    assert_eq!(game.has_mods.is_some(), is_get_games_response);
-    assert_eq!(game.mod_count.is_some(), is_get_games_response);
+fn check_response(game: &Game, is_get_games_response: bool) -> Result<(), ApiError> {
+    let check_field = |field_name: &str, is_present: bool, should_be_present: bool| {
+        if is_present == should_be_present {
+            return Ok(());
+        }
+        Err(ApiError::Invariant(format!(
+            "field `{}` of game {} is expected to be {}",
+            field_name, game.game_id, if should_be_present { "present" } else { "absent" }
+        )))
+    };
+
+    check_field("has_mods", game.has_mods.is_some(), is_get_games_response)?;
+    check_field("mod_count", game.mod_count.is_some(), is_get_games_response)?;
 
-    assert_eq!(game.last_heartbeat.is_none(), is_get_games_response);
-    assert_eq!(game.host_address.is_none(), is_get_games_response);
-    assert_eq!(game.mods.is_none(), is_get_games_response);
-    assert_eq!(game.mods_crc.is_none(), is_get_games_response);
+    check_field("last_heartbeat", game.last_heartbeat.is_some(), !is_get_games_response)?;
+    check_field("host_address", game.host_address.is_some(), !is_get_games_response)?;
+    check_field("mods", game.mods.is_some(), !is_get_games_response)?;
+    check_field("mods_crc", game.mods_crc.is_some(), !is_get_games_response)?;
     if is_get_games_response {
-        assert!(game.steam_id.is_none());
+        check_field("steam_id", game.steam_id.is_some(), false)?;
+
+        // updater relies on it when converting host_id to `state::HostId`
+        if let Some(host_id) = &game.host_id {
+            match base64::decode(host_id) {
+                Ok(host_id) if host_id.len() == 32 => {}
+                _ => return Err(ApiError::Invariant(format!(
+                    "host_id `{}` of game {} is not base64 of 32 bytes", host_id, game.game_id
+                ))),
+            }
+        }
     }
+    Ok(())
 }
 
-const API_BASE_URL: &str = "https://multiplayer.factorio.com";
+// (path, value), path is relative to game object, e.g. `application_version.some_field`
+fn collect_unknown_fields(game: &Game) -> Vec<(String, &serde_json::Value)> {
+    let mut unknown_fields: Vec<(String, &serde_json::Value)> = game.unknown_fields.iter()
+        .map(|(name, value)| (name.clone(), value))
+        .collect();
+    for (name, value) in &game.application_version.unknown_fields {
+        unknown_fields.push((format!("application_version.{}", name), value));
+    }
+    for mod_ in game.mods.iter().flatten() {
+        for (name, value) in &mod_.unknown_fields {
+            unknown_fields.push((format!("mods[].{}", name), value));
+        }
+    }
+    unknown_fields
+}
 
-fn reqwest_get(url: &str) -> Result<String, impl Error> {
-    let response = reqwest::blocking::get(url)?;
+// in strict mode unknown fields are treated as schema error (as `#[serde(deny_unknown_fields)]` would do)
+// in lenient mode each new field is reported once, see [unknown_fields::report]
+fn check_unknown_fields(game: &Game, endpoint: &'static str, strict: bool) -> Result<(), ApiError> {
+    let unknown_fields = collect_unknown_fields(game);
+    if unknown_fields.is_empty() {
+        return Ok(());
+    }
 
-    if let Err(err) = response.error_for_status_ref() {
-        let response_text = response.text()?;
-        eprintln!("[error] [api] request failed: response text is `{}`", response_text);
-        return Err(err);
+    if strict {
+        let names = unknown_fields.iter().map(|(name, _)| name).join(", ");
+        let message = format!("unknown fields in game {}: {}", game.game_id, names);
+        return Err(ApiError::Json(de::Error::custom(message)));
     }
+    for (path, value) in unknown_fields {
+        unknown_fields::report(endpoint, &path, value);
+    }
+    Ok(())
+}
 
+fn get_api_base_url() -> String {
+    GLOBAL_CONFIG.lock().unwrap().api_base_url.clone()
+}
+
+fn reqwest_get(url: &str) -> Result<String, ApiError> {
+    let response = reqwest::blocking::get(url)?;
+    let status = response.status();
     let response_text = response.text()?;
+
+    if !status.is_success() {
+        return Err(ApiError::HttpStatus { status, body: response_text });
+    }
     Ok(response_text)
 }
 
-fn reqwest_get_with_retries(url: &str, number_retries: usize) -> Result<String, impl Error> {
+fn reqwest_get_with_retries(url: &str, number_retries: usize) -> Result<String, ApiError> {
     util::run_with_retries(
         number_retries,
         || reqwest_get(url),
@@ -123,24 +273,38 @@ fn reqwest_get_with_retries(url: &str, number_retries: usize) -> Result<String,
     )
 }
 
-pub fn get_games() -> GetGamesResponse {
-    let factorio_username: String = env::var("FACTORIO_USERNAME")
-        .expect("Missing FACTORIO_USERNAME env variable");
-    let factorio_token: String = env::var("FACTORIO_TOKEN")
-        .expect("Missing FACTORIO_TOKEN env variable");
-    let api_url: String = format!("{}/get-games?username={}&token={}", API_BASE_URL, factorio_username, factorio_token);
+// credentials are required only by the real matchmaking server (mock server ignores them)
+fn get_env_credential(name: &str, api_base_url: &str) -> String {
+    match env::var(name) {
+        Ok(value) => value,
+        Err(_) if api_base_url != DEFAULT_API_BASE_URL => String::new(),
+        Err(_) => panic!("Missing {} env variable", name),
+    }
+}
 
-    let response = if !MOCK_API {
-        reqwest_get_with_retries(&api_url, 10).unwrap()
-    } else {
-        fs::read_to_string("temp/cached-data/get-games.json").unwrap()
-    };
-    let mut games: Vec<Game> = parse_json_with_logging(&response).unwrap();
+pub fn get_games() -> Result<GetGamesResponse, ApiError> {
+    let response = get_games_raw()?;
+    parse_get_games_response(&response)
+}
+
+pub fn get_games_raw() -> Result<String, ApiError> {
+    let api_base_url = get_api_base_url();
+    let factorio_username = get_env_credential("FACTORIO_USERNAME", &api_base_url);
+    let factorio_token = get_env_credential("FACTORIO_TOKEN", &api_base_url);
+    let api_url: String = format!("{}/get-games?username={}&token={}", api_base_url, factorio_username, factorio_token);
+
+    reqwest_get_with_retries(&api_url, 10)
+}
+
+pub fn parse_get_games_response(response: &str) -> Result<GetGamesResponse, ApiError> {
+    let mut games: Vec<Game> = parse_json_with_logging(response)?;
     clean_get_games_response(&mut games);
+    let strict = GLOBAL_CONFIG.lock().unwrap().api_strict_schema;
     for game in games.iter() {
-        check_response(game, true);
+        check_unknown_fields(game, "/get-games", strict)?;
+        check_response(game, true)?;
     }
-    games
+    Ok(games)
 }
 
 pub fn clean_get_games_response(games: &mut Vec<Game>) {
@@ -151,13 +315,16 @@ pub fn clean_get_games_response(games: &mut Vec<Game>) {
 }
 
 // Ok(None) означает ошибку 404
-fn reqwest_get_and_check_for_404(url: &str) -> Result<Option<String>, Box<dyn Error>> {
+fn reqwest_get_and_check_for_404(url: &str) -> Result<Option<String>, ApiError> {
     let response = reqwest::blocking::get(url)?;
-    if response.status() == StatusCode::NOT_FOUND {
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
         return Ok(None);
     }
-    let response = response.error_for_status()?;
     let response_text = response.text()?;
+    if !status.is_success() {
+        return Err(ApiError::HttpStatus { status, body: response_text });
+    }
     Ok(Some(response_text))
 }
 
@@ -170,28 +337,41 @@ fn parse_json_with_logging<'a, T: de::Deserialize<'a>>(s: &'a str) -> serde_json
 }
 
 // Ok(None) означает что api вернул 404 первый раз
-pub fn get_game_details(game_id: u64) -> Result<Option<GetGameDetailsResponse>, Box<dyn Error>> {
-    let api_url: String = format!("{}/get-game-details/{}", API_BASE_URL, game_id);
+pub fn get_game_details(game_id: u64) -> Result<Option<GetGameDetailsResponse>, ApiError> {
+    match get_game_details_raw(game_id, None)? {
+        Some(response) => parse_get_game_details_response(&response).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Ok(None) означает что api вернул 404 первый раз
+pub fn get_game_details_raw(game_id: u64, replay_cache: Option<ReplayCache>) -> Result<Option<String>, ApiError> {
+    let api_url: String = format!("{}/get-game-details/{}", get_api_base_url(), game_id);
 
     // todo: && cfg!(not(debug_assertions))
-    let response = if !GLOBAL_CONFIG.lock().unwrap().use_cache_for_get_game_details {
+    let response = if let Some(replay_cache) = replay_cache {
+        get_game_details_replayed(game_id, &api_url, replay_cache)
+    } else if !GLOBAL_CONFIG.lock().unwrap().use_cache_for_get_game_details {
         reqwest_get_and_check_for_404(&api_url)
     } else {
         get_game_details_cached(game_id, &api_url)
     };
-    let response = match response {
-        Ok(None) => return Ok(None),
-        Ok(Some(response)) => response,
-        Err(_) => reqwest_get_with_retries(&api_url, 4)?,
-    };
+    match response {
+        Ok(response) => Ok(response),
+        Err(_) => reqwest_get_with_retries(&api_url, 4).map(Some),
+    }
+}
 
-    let mut game: Game = parse_json_with_logging(&response).unwrap();
-    check_response(&game, false);
+pub fn parse_get_game_details_response(response: &str) -> Result<GetGameDetailsResponse, ApiError> {
+    let mut game: Game = parse_json_with_logging(response)?;
+    let strict = GLOBAL_CONFIG.lock().unwrap().api_strict_schema;
+    check_unknown_fields(&game, "/get-game-details", strict)?;
+    check_response(&game, false)?;
     game.mods.as_mut().unwrap().retain(|mod_| mod_.name != "base");
-    Ok(Some(game))
+    Ok(game)
 }
 
-pub fn get_game_details_cached(game_id: u64, api_url: &str) -> Result<Option<String>, Box<dyn Error>> {
+pub fn get_game_details_cached(game_id: u64, api_url: &str) -> Result<Option<String>, ApiError> {
     let path = PathBuf::from(format!("temp/cache-get-game-details/{}.json", game_id));
     if path.exists() {
         let response = fs::read_to_string(path).unwrap();
@@ -209,3 +389,100 @@ pub fn get_game_details_cached(game_id: u64, api_url: &str) -> Result<Option<Str
         response
     }
 }
+
+/// ответы /get-game-details из архива для replay (см. `fetcher_get_games_offline::prepare_get_game_details_cache`)
+pub struct ReplayCache<'a> {
+    pub directory: &'a Path,
+    // время (в минутах) последнего ответа /get-games на момент запроса
+    pub time: TimeMinutes,
+    pub refetch: bool,
+}
+
+/// строка файла `<ReplayCache::directory>/<game_id>.jsonl`, ответы упорядочены по времени
+#[derive(Serialize, Deserialize)]
+pub struct CachedGameDetails {
+    pub time: TimeMinutes,
+    // None означает 404
+    pub response: Option<String>,
+}
+
+pub fn get_game_details_replay_cache_path(directory: &Path, game_id: u64) -> PathBuf {
+    directory.join(format!("{}.jsonl", game_id))
+}
+
+/// первый запрос игры получает первый ответ из архива,
+/// повторный (`refetch`) — последний ответ, полученный не позже `time` (так при replay воспроизводятся изменения details)
+fn get_game_details_replayed(game_id: u64, api_url: &str, replay_cache: ReplayCache) -> Result<Option<String>, ApiError> {
+    let path = get_game_details_replay_cache_path(replay_cache.directory, game_id);
+    if path.exists() {
+        let content = fs::read_to_string(path).unwrap();
+        let responses: Vec<CachedGameDetails> = content.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let first_response = responses.first().expect("empty /get-game-details replay cache file");
+        let response = if replay_cache.refetch {
+            responses.iter().rev().find(|response| response.time <= replay_cache.time).unwrap_or(first_response)
+        } else {
+            first_response
+        };
+        Ok(response.response.clone())
+    } else {
+        // игры нет в архиве
+        let response = reqwest_get_and_check_for_404(&api_url);
+        if let Ok(ref response) = response {
+            let cached_response = CachedGameDetails { time: replay_cache.time, response: response.clone() };
+            fs::write(path, serde_json::to_string(&cached_response).unwrap() + "\n").unwrap();
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GET_GAMES_GAME: &str = r#"{
+        "game_id": 1,
+        "name": "name",
+        "description": "",
+        "max_players": "10",
+        "application_version": {"game_version": "0.18.24", "build_version": "52411", "build_mode": "headless", "platform": "linux64"},
+        "game_time_elapsed": "10",
+        "has_password": "false",
+        "server_id": "AQAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+        "has_mods": false,
+        "mod_count": 0
+    }"#;
+
+    #[test]
+    fn check_response_invariants() {
+        let mut game: Game = serde_json::from_str(GET_GAMES_GAME).unwrap();
+        assert!(check_response(&game, true).is_ok());
+        assert_eq!(check_response(&game, false).unwrap_err().kind(), "invariant");
+
+        game.host_id = Some("AQAA".to_owned());
+        assert_eq!(check_response(&game, true).unwrap_err().kind(), "invariant");
+    }
+
+    #[test]
+    fn parse_unknown_fields() {
+        let json = GET_GAMES_GAME
+            .replace(r#""platform": "linux64""#, r#""platform": "linux64", "new_version_field": 1"#)
+            .replace(r#""mod_count": 0"#, r#""mod_count": 0, "new_game_field": "value""#);
+        let game: Game = serde_json::from_str(&json).unwrap();
+        assert_eq!(game.unknown_fields.get("new_game_field"), Some(&serde_json::json!("value")));
+        assert_eq!(game.max_players, 10);
+
+        let mut paths: Vec<String> = collect_unknown_fields(&game).into_iter().map(|(path, _)| path).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["application_version.new_version_field", "new_game_field"]);
+
+        assert_eq!(check_unknown_fields(&game, "/get-games", true).unwrap_err().kind(), "json");
+    }
+
+    #[test]
+    fn parse_error_kind() {
+        let error: ApiError = parse_json_with_logging::<Game>("{}").unwrap_err().into();
+        assert_eq!(error.kind(), "json");
+    }
+}
@@ -0,0 +1,56 @@
+//! Registry of fields which are present in api responses, but not described in `api::Game`
+//! Each new field is logged once, full list is available at `/admin/unknown-fields`
+
+use std::sync::Mutex;
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use crate::state::TimeMinutes;
+
+const MAXIMUM_SAMPLE_VALUE_LENGTH: usize = 200;
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnknownField {
+    // "/get-games" or "/get-game-details"
+    pub endpoint: &'static str,
+    // relative to game object, e.g. `application_version.some_field`
+    pub path: String,
+    pub sample_value: String,
+    pub time_first_seen: TimeMinutes,
+    pub number_occurrences: u64,
+}
+
+lazy_static! {
+    // key is (endpoint, path)
+    static ref UNKNOWN_FIELDS: Mutex<HashMap<(&'static str, String), UnknownField>> = Mutex::new(HashMap::new());
+}
+
+pub fn report(endpoint: &'static str, path: &str, value: &serde_json::Value) {
+    let mut unknown_fields = UNKNOWN_FIELDS.lock().unwrap();
+    if let Some(unknown_field) = unknown_fields.get_mut(&(endpoint, path.to_owned())) {
+        unknown_field.number_occurrences += 1;
+        return;
+    }
+
+    let sample_value: String = value.to_string().chars().take(MAXIMUM_SAMPLE_VALUE_LENGTH).collect();
+    eprintln!("[warn]  [api] new unknown field `{}` in {} response, sample value: {}", path, endpoint, sample_value);
+    let unknown_field = UnknownField {
+        endpoint,
+        path: path.to_owned(),
+        sample_value,
+        time_first_seen: TimeMinutes::now(),
+        number_occurrences: 1,
+    };
+    unknown_fields.insert((endpoint, path.to_owned()), unknown_field);
+}
+
+pub fn get_unknown_fields() -> Vec<UnknownField> {
+    UNKNOWN_FIELDS.lock().unwrap().values()
+        .cloned()
+        .sorted_by_key(|unknown_field| unknown_field.time_first_seen)
+        .collect()
+}
@@ -0,0 +1,318 @@
+//! Archive of raw responses of matchmaking api, is intended to be the source of truth for rebuilding `State`
+//!
+//! Each response is appended as json line (see [Record]) to the local file of the current period (hour or day).
+//! When the period is over, the file is uploaded to Yandex.Cloud and deleted locally.
+//! Files are named `<endpoint>/<time_begin>-<time_end>.jsonl.lz4`,
+//! where [time_begin, time_end) (in minutes, see `TimeMinutes`) contains times of all records in the file
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use hashbrown::hash_map::{Entry, HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::external_storage::compression;
+use crate::global_config::GLOBAL_CONFIG;
+use crate::state::{GameId, TimeMinutes};
+use crate::util::{basename, new_buf_reader, new_buf_writer};
+use crate::yandex_cloud_storage;
+
+pub const LOCAL_ARCHIVE_DIRECTORY: &str = "temp/archive";
+const CLOUD_ARCHIVE_DIRECTORY: &str = "archive";
+// not xz, because xz compressor requires too much memory (see `external_storage::compression`)
+const ARCHIVE_FILE_EXTENSION: &str = "jsonl.lz4";
+const CONTENT_TYPE: &str = "application/octet-stream";
+// each flush finishes compression block, so flushing after each record would spoil compression ratio
+// records appended after the last flush are lost if process is killed
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Endpoint {
+    GetGames,
+    GetGameDetails,
+}
+
+impl Endpoint {
+    pub const ALL: [Endpoint; 2] = [Endpoint::GetGames, Endpoint::GetGameDetails];
+
+    pub fn directory_name(self) -> &'static str {
+        match self {
+            Endpoint::GetGames => "get-games",
+            Endpoint::GetGameDetails => "get-game-details",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum ArchivePeriod {
+    Hour,
+    Day,
+}
+
+impl ArchivePeriod {
+    pub fn parse(period: &str) -> Option<Self> {
+        match period {
+            "hour" => Some(ArchivePeriod::Hour),
+            "day" => Some(ArchivePeriod::Day),
+            _ => None,
+        }
+    }
+
+    pub fn minutes(self) -> u32 {
+        match self {
+            ArchivePeriod::Hour => 60,
+            ArchivePeriod::Day => 24 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    pub endpoint: Endpoint,
+    // for /get-games it is exactly the time which is passed to updater
+    // for /get-game-details it is the time of the last /get-games response at the moment of the request
+    pub time: TimeMinutes,
+    // only for /get-game-details
+    pub game_id: Option<GameId>,
+    // raw response body, None means 404
+    pub response: Option<String>,
+}
+
+/// None for pipelines which don't archive responses
+pub type ArchiverSender = Option<mpsc::Sender<Record>>;
+
+pub fn archive(sender: &ArchiverSender, endpoint: Endpoint, time: TimeMinutes, game_id: Option<GameId>, response: Option<&str>) {
+    if let Some(sender) = sender {
+        let record = Record {
+            endpoint,
+            time,
+            game_id,
+            response: response.map(ToOwned::to_owned),
+        };
+        if sender.send(record).is_err() {
+            eprintln!("[error] [archiver] can't archive {:?} response: archiver is not running", endpoint);
+        }
+    }
+}
+
+pub fn get_cloud_directory(endpoint: Endpoint) -> String {
+    format!("{}/{}", CLOUD_ARCHIVE_DIRECTORY, endpoint.directory_name())
+}
+
+fn get_local_directory(endpoint: Endpoint) -> PathBuf {
+    Path::new(LOCAL_ARCHIVE_DIRECTORY).join(endpoint.directory_name())
+}
+
+/// `<time_begin>-<time_end>.jsonl.lz4` -> (time_begin, time_end)
+pub fn parse_archive_file_name(name: &str) -> Option<(u32, u32)> {
+    let name = name.strip_suffix(ARCHIVE_FILE_EXTENSION)?.strip_suffix('.')?;
+    let (time_begin, time_end) = name.split_at(name.find('-')?);
+    Some((time_begin.parse().ok()?, time_end[1..].parse().ok()?))
+}
+
+/// returns archive files of `endpoint` in `directory`, which may contain records with time in [time_begin, time_end),
+/// sorted by time of the first record
+pub fn get_archive_files(directory: &Path, endpoint: Endpoint, time_begin: u32, time_end: u32) -> Vec<PathBuf> {
+    let directory = directory.join(endpoint.directory_name());
+    let entries = match fs::read_dir(&directory) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut files: Vec<(u32, PathBuf)> = entries
+        .map(|entry| entry.unwrap().path())
+        .filter_map(|path| {
+            let (file_time_begin, file_time_end) = parse_archive_file_name(path.file_name()?.to_str()?)?;
+            if file_time_begin < time_end && time_begin < file_time_end {
+                Some((file_time_begin, path))
+            } else {
+                None
+            }
+        })
+        .collect();
+    files.sort_by_key(|(file_time_begin, _)| *file_time_begin);
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+/// file may be truncated (if process was killed while writing it),
+/// in such case all records before the truncated one are returned
+pub fn read_archive_file(path: &Path) -> Vec<Record> {
+    let file = File::open(path).unwrap();
+    let reader = compression::new_decoder(new_buf_reader(file), path.to_str().unwrap());
+    let mut records = Vec::new();
+    for line in new_buf_reader(reader).lines() {
+        let record = line
+            .map_err(|err| err.to_string())
+            .and_then(|line| serde_json::from_str(&line).map_err(|err| err.to_string()));
+        match record {
+            Ok(record) => records.push(record),
+            Err(err) => {
+                eprintln!("[warn]  [archiver] file {:?} is truncated after {} records: {}", path, records.len(), err);
+                break;
+            }
+        }
+    }
+    records
+}
+
+/// downloads archive files which may contain records with time in [time_begin, time_end) and which are absent locally
+pub fn download_archive(directory: &Path, time_begin: u32, time_end: u32) {
+    for &endpoint in Endpoint::ALL.iter() {
+        let local_directory = directory.join(endpoint.directory_name());
+        if let Err(err) = fs::create_dir_all(&local_directory) {
+            eprintln!("[error] [archiver] can't create directory {:?}: {}", local_directory, err);
+            continue;
+        }
+        for cloud_path in yandex_cloud_storage::list_bucket(&get_cloud_directory(endpoint)) {
+            let name = basename(&cloud_path);
+            let is_in_range = match parse_archive_file_name(name) {
+                Some((file_time_begin, file_time_end)) => file_time_begin < time_end && time_begin < file_time_end,
+                None => false,
+            };
+            let path = local_directory.join(name);
+            if is_in_range && !path.exists() {
+                println!("[info]  [archiver] download `{}`", cloud_path);
+                if let Err(err) = yandex_cloud_storage::download_to_file(&cloud_path, &path) {
+                    eprintln!("[error] [archiver] failed to download `{}`: {}", cloud_path, err);
+                    // partially downloaded file would be treated as truncated archive file
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+    }
+}
+
+struct ArchiveFile {
+    endpoint: Endpoint,
+    path: PathBuf,
+    time_end: TimeMinutes,
+    writer: Box<dyn Write>,
+    last_flush: Instant,
+}
+
+impl ArchiveFile {
+    fn create(endpoint: Endpoint, time: TimeMinutes, period: ArchivePeriod) -> io::Result<Self> {
+        let period_minutes = period.minutes();
+        let time_end = TimeMinutes::new((time.get() / period_minutes + 1) * period_minutes).unwrap();
+
+        // file name contains time of the first record (not time of the period begin),
+        // so that after restart we will not overwrite file uploaded by previous process
+        let directory = get_local_directory(endpoint);
+        fs::create_dir_all(&directory)?;
+        let path = directory.join(format!("{}-{}.{}", time.get(), time_end.get(), ARCHIVE_FILE_EXTENSION));
+        println!("[info]  [archiver] create file {:?}", path);
+
+        let writer = File::create(&path)?;
+        let writer = compression::new_encoder(new_buf_writer(writer), ARCHIVE_FILE_EXTENSION);
+        Ok(ArchiveFile { endpoint, path, time_end, writer, last_flush: Instant::now() })
+    }
+
+    fn append(&mut self, record: &Record) {
+        let result = serde_json::to_writer(&mut self.writer, record)
+            .map_err(Into::into)
+            .and_then(|()| self.writer.write_all(b"\n"));
+        if let Err(err) = result {
+            eprintln!("[error] [archiver] failed to append record to {:?}: {}", self.path, err);
+        }
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(err) = self.writer.flush() {
+            eprintln!("[error] [archiver] failed to flush {:?}: {}", self.path, err);
+        }
+        self.last_flush = Instant::now();
+    }
+
+    fn finish(mut self) {
+        self.flush();
+        let ArchiveFile { endpoint, path, writer, .. } = self;
+        drop(writer);  // to finish compression
+        upload_and_remove(endpoint, &path);
+    }
+}
+
+fn upload_and_remove(endpoint: Endpoint, path: &Path) {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return,
+    };
+    let cloud_path = format!("{}/{}", get_cloud_directory(endpoint), name);
+    println!("[info]  [archiver] start uploading `{}`", cloud_path);
+    match yandex_cloud_storage::try_upload_with_retries(&cloud_path, path, CONTENT_TYPE, 5) {
+        Ok(()) => {
+            if let Err(err) = fs::remove_file(path) {
+                eprintln!("[error] [archiver] failed to remove uploaded file {:?}: {}", path, err);
+            }
+        }
+        // will try again on next start, see [upload_leftover_files]
+        Err(err) => eprintln!("[error] [archiver] failed to upload `{}`: {}", cloud_path, err),
+    }
+}
+
+// files which were not uploaded by previous process (it was killed or upload failed)
+fn upload_leftover_files() {
+    for &endpoint in Endpoint::ALL.iter() {
+        let entries = match fs::read_dir(get_local_directory(endpoint)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            println!("[info]  [archiver] found file from previous run: {:?}", path);
+            upload_and_remove(endpoint, &path);
+        }
+    }
+}
+
+pub fn archiver(receiver: mpsc::Receiver<Record>) {
+    upload_leftover_files();
+
+    let period = GLOBAL_CONFIG.lock().unwrap().archive_period;
+    let mut files: HashMap<Endpoint, ArchiveFile> = HashMap::new();
+    for record in receiver {
+        let finished_endpoints: Vec<Endpoint> = files.values()
+            .filter(|file| record.time >= file.time_end)
+            .map(|file| file.endpoint)
+            .collect();
+        for endpoint in finished_endpoints {
+            files.remove(&endpoint).unwrap().finish();
+        }
+
+        let file = match files.entry(record.endpoint) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            // record is lost, file creation will be retried for the next record
+            Entry::Vacant(entry) => match ArchiveFile::create(record.endpoint, record.time, period) {
+                Ok(file) => entry.insert(file),
+                Err(err) => {
+                    eprintln!("[error] [archiver] failed to create {:?} file: {}", record.endpoint, err);
+                    continue;
+                }
+            },
+        };
+        file.append(&record);
+    }
+
+    for (_, file) in files.drain() {
+        file.finish();
+    }
+    eprintln!("[error] [archiver] exit");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_file_name() {
+        assert_eq!(parse_archive_file_name("26400123-26400180.jsonl.lz4"), Some((26400123, 26400180)));
+        assert_eq!(parse_archive_file_name("26400123-26400180.json"), None);
+        assert_eq!(parse_archive_file_name("26400123.jsonl.lz4"), None);
+    }
+}
@@ -2,11 +2,14 @@ use std::cmp::Reverse;
 use std::sync::Arc;
 use std::time::Duration;
 
+use hashbrown::{HashMap, HashSet};
 use itertools::Itertools;
 use parking_lot::RwLock;
 use serde::Serialize;
 
-use crate::state::{ServerId, State, StateLock, TimeMinutes};
+use crate::mods_statistics::{ModsStatistics, ModsStatisticsBuilder};
+use crate::search::{SearchIndex, SearchIndexBuilder};
+use crate::state::{Game, GameId, ServerId, State, StateLock, TimeMinutes};
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -25,16 +28,49 @@ pub struct TopGameByNumberPlayersMax {
     pub time: TimeMinutes,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopGameByGameTimeElapsed {
+    pub server_id: ServerId,
+    pub name: String,
+    // in minutes
+    pub game_time_elapsed: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopServerByPlayerMinutes {
+    pub server_id: ServerId,
+    pub name: String,
+    pub player_minutes: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopServerByUniquePlayers {
+    pub server_id: ServerId,
+    pub name: String,
+    pub number_players: usize,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct MainPageInfo {
     top_games_by_number_players_now: Vec<TopGameByNumberPlayersNow>,
     top_games_by_number_players_max: Vec<TopGameByNumberPlayersMax>,
+    top_games_by_game_time_elapsed: Vec<TopGameByGameTimeElapsed>,
+    top_servers_by_player_minutes_day: Vec<TopServerByPlayerMinutes>,
+    top_servers_by_player_minutes_week: Vec<TopServerByPlayerMinutes>,
+    top_servers_by_player_minutes_month: Vec<TopServerByPlayerMinutes>,
+    top_servers_by_player_minutes_all_time: Vec<TopServerByPlayerMinutes>,
+    top_servers_by_unique_players_week: Vec<TopServerByUniquePlayers>,
 }
 
 pub struct CacherState {
     main_page: MainPageInfo,
     pub main_page_serialized: Arc<String>,
+    pub search_index: Arc<SearchIndex>,
+    pub mods_statistics: Arc<ModsStatistics>,
 }
 
 impl CacherState {
@@ -42,10 +78,18 @@ impl CacherState {
         let main_page = MainPageInfo {
             top_games_by_number_players_now: Vec::new(),
             top_games_by_number_players_max: Vec::new(),
+            top_games_by_game_time_elapsed: Vec::new(),
+            top_servers_by_player_minutes_day: Vec::new(),
+            top_servers_by_player_minutes_week: Vec::new(),
+            top_servers_by_player_minutes_month: Vec::new(),
+            top_servers_by_player_minutes_all_time: Vec::new(),
+            top_servers_by_unique_players_week: Vec::new(),
         };
         Self {
             main_page,
             main_page_serialized: Arc::new("{}".to_owned()),
+            search_index: Arc::new(SearchIndex::default()),
+            mods_statistics: Arc::new(ModsStatistics::default()),
         }
     }
 }
@@ -53,6 +97,7 @@ impl CacherState {
 pub type CacherStateLock = Arc<RwLock<CacherState>>;
 
 const INTERVAL: Duration = Duration::from_secs(10 * 60);  // 10 minutes
+const GAMES_CHUNK_SIZE: usize = 10_000;
 
 /// переодически рассчитывает различные статистики (в основном для главной страницы) на основе State
 pub fn cacher(cacher_state_lock: CacherStateLock, state_lock: StateLock) {
@@ -61,6 +106,20 @@ pub fn cacher(cacher_state_lock: CacherStateLock, state_lock: StateLock) {
 
         update_top_games_by_number_players_current(&state_lock.read(), &cacher_state_lock);
         update_top_games_by_number_players_maximum(&state_lock.read(), &cacher_state_lock);
+        update_top_games_by_game_time_elapsed(&state_lock.read(), &cacher_state_lock);
+
+        let (mut search_index, mut mods_statistics, mut player_activity) = {
+            let state = state_lock.read();
+            (SearchIndexBuilder::new(&state), ModsStatisticsBuilder::new(&state), PlayerActivityBuilder::new(&state))
+        };
+        for_each_game(&state_lock, |state, game| {
+            search_index.add_game(state, game);
+            mods_statistics.add_game(state, game);
+            player_activity.add_game(state, game);
+        });
+        player_activity.build(&state_lock.read(), &mut cacher_state_lock.write().main_page);
+        cacher_state_lock.write().search_index = Arc::new(search_index.build());
+        cacher_state_lock.write().mods_statistics = Arc::new(mods_statistics.build());
 
         {
             let mut cacher_state = cacher_state_lock.write();
@@ -73,6 +132,18 @@ pub fn cacher(cacher_state_lock: CacherStateLock, state_lock: StateLock) {
     println!("[info]  [cacher] exit");
 }
 
+/// обходит все игры, отпуская блокировку State после каждых GAMES_CHUNK_SIZE игр, чтобы не задерживать updater
+/// (игры не удаляются, но между частями могут измениться, например объединиться в сервер)
+fn for_each_game(state_lock: &StateLock, mut f: impl FnMut(&State, &Game)) {
+    let game_ids: Vec<GameId> = state_lock.read().games.keys().copied().collect();
+    for chunk in game_ids.chunks(GAMES_CHUNK_SIZE) {
+        let state = state_lock.read();
+        for &game_id in chunk {
+            f(&state, state.get_game(game_id));
+        }
+    }
+}
+
 fn get_top_n<T, K>(mut values: Vec<T>, n: usize, get_key: impl Fn(&T) -> K) -> Vec<T>
     where K: Ord
 {
@@ -109,10 +180,10 @@ fn update_top_games_by_number_players_current(state: &State, cacher_state_lock:
 fn update_top_games_by_number_players_maximum(state: &State, cacher_state_lock: &CacherStateLock) {
     const TOP_SIZE: usize = 10;
 
-    let pairs = state.game_ids.iter().skip(1)
-        .map(|&game_id| {
-            let game = state.get_game(game_id);
-            (game.server_id.unwrap(), game.maximum_number_players())
+    let pairs = state.get_server_ids()
+        .map(|server_id| {
+            let game = state.get_game(state.get_server_last_game_id(server_id));
+            (server_id, game.maximum_number_players())
         })
         .into_group_map();
     let pairs = pairs.into_iter()
@@ -130,3 +201,123 @@ fn update_top_games_by_number_players_maximum(state: &State, cacher_state_lock:
         .collect();
     cacher_state_lock.write().main_page.top_games_by_number_players_max = top_games;
 }
+
+fn update_top_games_by_game_time_elapsed(state: &State, cacher_state_lock: &CacherStateLock) {
+    const TOP_SIZE: usize = 10;
+
+    let games = state.current_game_ids.iter()
+        .map(|&game_id| state.get_game(game_id))
+        .filter(|game| game.server_id.is_some())
+        .collect();
+    let games = get_top_n(games, TOP_SIZE, |game| Reverse(game.game_time_elapsed));
+
+    let top_games = games.into_iter()
+        .map(|game| TopGameByGameTimeElapsed {
+            server_id: game.server_id.unwrap(),
+            name: game.get_name(&state).to_owned(),
+            game_time_elapsed: game.game_time_elapsed,
+        })
+        .collect();
+    cacher_state_lock.write().main_page.top_games_by_game_time_elapsed = top_games;
+}
+
+fn get_top_servers_by_player_minutes(state: &State, player_minutes: HashMap<ServerId, u64>) -> Vec<TopServerByPlayerMinutes> {
+    const TOP_SIZE: usize = 10;
+
+    let pairs = player_minutes.into_iter()
+        .filter(|(_, player_minutes)| *player_minutes > 0)
+        .collect();
+    get_top_n(pairs, TOP_SIZE, |(_, player_minutes)| Reverse(*player_minutes))
+        .into_iter()
+        .map(|(server_id, player_minutes)| TopServerByPlayerMinutes {
+            server_id,
+            name: state.get_server_name(server_id).to_owned(),
+            player_minutes,
+        })
+        .collect()
+}
+
+/// человеко-часы за последние день/неделю/месяц/всё время и число уникальных игроков за последнюю неделю
+/// (суммарно по всем играм сервера), игры добавляются по частям (см. [for_each_game])
+struct PlayerActivityBuilder {
+    // незавершённые интервалы игроков закрываем минутой, следующей за последним наблюдением
+    end: TimeMinutes,
+    period_begins: Vec<TimeMinutes>,
+    player_minutes_by_period: Vec<HashMap<ServerId, u64>>,
+    player_minutes_all_time: HashMap<ServerId, u64>,
+    // игроки различаются по ключу в `State::players_index` (одно имя может храниться в all_player_names несколько раз)
+    unique_players_week: HashMap<ServerId, HashSet<u64>>,
+}
+
+impl PlayerActivityBuilder {
+    const DAY: u32 = 24 * 60;
+    const PERIODS: [u32; 3] = [Self::DAY, TimeMinutes::WEEK, 30 * Self::DAY];
+    const WEEK_INDEX: usize = 1;
+
+    fn new(state: &State) -> Self {
+        let end = state.last_observed_time.unwrap_or_else(TimeMinutes::now).get() + 1;
+        let end = TimeMinutes::new(end).unwrap();
+        let period_begins = Self::PERIODS.iter()
+            .map(|&period| TimeMinutes::new(end.get().saturating_sub(period).max(1)).unwrap())
+            .collect();
+        PlayerActivityBuilder {
+            end,
+            period_begins,
+            player_minutes_by_period: vec![HashMap::new(); Self::PERIODS.len()],
+            player_minutes_all_time: HashMap::new(),
+            unique_players_week: HashMap::new(),
+        }
+    }
+
+    fn add_game(&mut self, state: &State, game: &Game) {
+        let server_id = match game.server_id {
+            Some(server_id) => server_id,
+            None => return,
+        };
+        let end = self.end;
+        let all_time_begin = TimeMinutes::new(1).unwrap();
+        *self.player_minutes_all_time.entry(server_id).or_insert(0) += game.player_minutes_in_interval(all_time_begin, end);
+
+        let game_end = game.time_end.unwrap_or(end);
+        for (&period_begin, player_minutes) in self.period_begins.iter().zip(self.player_minutes_by_period.iter_mut()) {
+            if game_end > period_begin {
+                *player_minutes.entry(server_id).or_insert(0) += game.player_minutes_in_interval(period_begin, end);
+            }
+        }
+        let week_begin = self.period_begins[Self::WEEK_INDEX];
+        if game_end > week_begin {
+            let players = self.unique_players_week.entry(server_id).or_default();
+            for interval in &game.players_intervals {
+                if interval.end.map_or(true, |interval_end| interval_end > week_begin) {
+                    if let Some(player_key) = state.players_index.get_player_key(&state.all_player_names, interval.player_index) {
+                        players.insert(player_key);
+                    }
+                }
+            }
+        }
+    }
+
+    fn build(self, state: &State, main_page: &mut MainPageInfo) {
+        const TOP_SIZE: usize = 10;
+
+        let mut player_minutes_by_period = self.player_minutes_by_period.into_iter()
+            .map(|player_minutes| get_top_servers_by_player_minutes(state, player_minutes));
+        main_page.top_servers_by_player_minutes_day = player_minutes_by_period.next().unwrap();
+        main_page.top_servers_by_player_minutes_week = player_minutes_by_period.next().unwrap();
+        main_page.top_servers_by_player_minutes_month = player_minutes_by_period.next().unwrap();
+        main_page.top_servers_by_player_minutes_all_time = get_top_servers_by_player_minutes(state, self.player_minutes_all_time);
+
+        let pairs = self.unique_players_week.into_iter()
+            .map(|(server_id, players)| (server_id, players.len()))
+            .filter(|(_, number_players)| *number_players > 0)
+            .collect();
+        main_page.top_servers_by_unique_players_week = get_top_n(pairs, TOP_SIZE, |(_, number_players)| Reverse(*number_players))
+            .into_iter()
+            .map(|(server_id, number_players)| TopServerByUniquePlayers {
+                server_id,
+                name: state.get_server_name(server_id).to_owned(),
+                number_players,
+            })
+            .collect();
+    }
+}
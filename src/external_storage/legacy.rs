@@ -0,0 +1,242 @@
+//! Состояние, сохранённое до появления заголовка с версией формата (версия 0)
+//! bincode не хранит названия и типы полей, поэтому такое состояние десериализуется в копии структур того времени,
+//! а затем конвертируется в текущие структуры
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Read;
+use std::marker::PhantomData;
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::{MapAccess, Visitor};
+
+use crate::fetcher_get_game_details;
+use crate::state::{BigString, BigStringPart, DetailsStatus, Game, GameId, GamesMap, HostId, Mod, PlayerInterval, ServerId, State, TimeMinutes};
+use crate::state::updater::{self, HostIdMergeInfo, UpdaterState};
+
+use super::{get_empty_state, WholeState};
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdaterStateV0 {
+    pub scheduled_to_merge_host_ids: HashMap<HostId, HostIdMergeInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameV0 {
+    pub game_id: GameId,
+    pub server_id: Option<ServerId>,
+    pub prev_game_id: Option<GameId>,
+    pub next_game_id: Option<GameId>,
+    pub time_begin: TimeMinutes,
+    pub time_end: Option<TimeMinutes>,
+    pub players_intervals: Vec<PlayerInterval>,
+    pub host_id: HostId,
+    pub name: BigStringPart,
+    pub description: BigStringPart,
+    pub max_players: u32,
+    pub game_version: BigStringPart,
+    pub game_time_elapsed: u32,
+    pub has_password: bool,
+    pub tags: BigStringPart,
+    pub mod_count: u16,
+    // None означает что /get-game-details ещё не запрошен, для 404 сохранялся адрес "unknown"
+    pub host_address: Option<BigStringPart>,
+    pub mods: Option<Vec<Mod>>,
+}
+
+/// `Games` — `GamesV0` при загрузке, в тестах — любой map из GameId в GameV0
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateV0<Games> {
+    pub games: Games,
+    pub game_ids: Vec<GameId>,
+    pub current_game_ids: Vec<GameId>,
+
+    pub all_game_names: BigString,
+    pub all_game_descriptions: BigString,
+    pub all_versions: BigString,
+    pub all_tags: BigString,
+    pub all_host_addresses: BigString,
+    pub all_mod_names: BigString,
+    pub all_player_names: BigString,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FetcherStateV0 {
+    pub game_ids: VecDeque<GameId>,
+}
+
+/// в версии 0 details получены тогда и только тогда, когда есть host_address
+/// (Unavailable для "unknown" выставляется после загрузки всех строк, см. `migrate_unknown_game_details`)
+/// игры без details стоят в очереди fetcher_get_game_details, который выставит Skipped для коротких игр
+fn get_details_status(game: &GameV0) -> DetailsStatus {
+    if game.host_address.is_some() {
+        DetailsStatus::Fetched
+    } else {
+        DetailsStatus::NotFetched
+    }
+}
+
+impl From<GameV0> for Game {
+    fn from(game: GameV0) -> Self {
+        Game {
+            game_id: game.game_id,
+            server_id: game.server_id,
+            prev_game_id: game.prev_game_id,
+            next_game_id: game.next_game_id,
+            time_begin: game.time_begin,
+            time_end: game.time_end,
+            players_intervals: game.players_intervals,
+            host_id: game.host_id,
+            name: game.name,
+            description: game.description,
+            max_players: game.max_players,
+            game_version: game.game_version,
+            build: None,
+            game_time_elapsed: game.game_time_elapsed,
+            has_password: game.has_password,
+            tags: game.tags,
+            mod_count: game.mod_count,
+            details_status: get_details_status(&game),
+            host_address: game.host_address,
+            mods: game.mods,
+        }
+    }
+}
+
+/// конвертирует игры во время десериализации, чтобы не хранить в памяти одновременно старые и новые игры
+pub struct GamesV0(GamesMap);
+
+impl<'de> Deserialize<'de> for GamesV0 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct GamesV0Visitor(PhantomData<fn() -> GamesV0>);
+
+        impl<'de> Visitor<'de> for GamesV0Visitor {
+            type Value = GamesV0;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("map of games in format version 0")
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<Self::Value, M::Error> {
+                let capacity = access.size_hint().unwrap_or(0);
+                let mut games = GamesMap::with_capacity(capacity);
+                while let Some((game_id, game)) = access.next_entry::<GameId, GameV0>()? {
+                    games.insert(game_id, game.into());
+                }
+                Ok(GamesV0(games))
+            }
+        }
+
+        deserializer.deserialize_map(GamesV0Visitor(PhantomData))
+    }
+}
+
+/// время последнего ответа /get-games не сохранялось, оцениваем его по последнему событию игр
+/// (в версии 0 `time_end` — время первого ответа, в котором игры уже нет)
+fn estimate_last_observed_time(games: &GamesMap) -> Option<TimeMinutes> {
+    games.values()
+        .flat_map(|game| {
+            let players_times = game.players_intervals.iter()
+                .flat_map(|player_interval| std::iter::once(player_interval.begin).chain(player_interval.end));
+            std::iter::once(game.time_begin)
+                .chain(game.time_end)
+                .chain(players_times)
+        })
+        .max()
+}
+
+/// в версии 0 для 404 от /get-game-details сохранялся адрес "unknown" и мод "unknown"
+fn migrate_unknown_game_details(state: &mut State) {
+    let all_host_addresses = &state.all_host_addresses;
+    let mut number_migrated_games = 0;
+    for game in state.games.values_mut() {
+        let is_unknown = game.host_address
+            .map_or(false, |host_address| all_host_addresses.get(host_address).0 == b"unknown");
+        if is_unknown {
+            game.details_status = DetailsStatus::Unavailable;
+            game.host_address = None;
+            game.mods = None;
+            number_migrated_games += 1;
+        }
+    }
+    if number_migrated_games != 0 {
+        println!("[info]  [external_storage] migrated {} games with \"unknown\" game details", number_migrated_games);
+    }
+}
+
+fn convert_state(state: StateV0<GamesV0>, empty_state: State) -> State {
+    let games = state.games.0;
+    let last_observed_time = estimate_last_observed_time(&games);
+    let mut state = State {
+        games,
+        game_ids: state.game_ids,
+        current_game_ids: state.current_game_ids,
+        last_observed_time,
+        // первый ответ после загрузки запишет пропуск начиная с last_observed_time
+        observation_gaps: Vec::new(),
+        // ручных объединений и разделений серверов в версии 0 не было
+        server_overrides: Vec::new(),
+        // в версии 0 метаинформация игры не обновлялась после её появления,
+        // поэтому изменения за время до загрузки будут записаны первым ответом /get-games после неё
+        metadata_changes: HashMap::new(),
+        // повторных запросов /get-game-details в версии 0 не было
+        details_revisions: HashMap::new(),
+        // сборка и остальные поля /get-game-details в версии 0 не сохранялись (`Game::build` == None)
+        details_extra: HashMap::new(),
+        builds: Vec::new(),
+        all_steam_ids: BigString::new(),
+        all_game_names: state.all_game_names,
+        all_game_descriptions: state.all_game_descriptions,
+        all_versions: state.all_versions,
+        all_tags: state.all_tags,
+        all_host_addresses: state.all_host_addresses,
+        all_mod_names: state.all_mod_names,
+        all_player_names: state.all_player_names,
+        ..empty_state
+    };
+    state.fix_cyclic_prev_game_id();
+    migrate_unknown_game_details(&mut state);
+    state.global_online = state.rebuild_global_online();
+    state
+}
+
+/// в версии 0 одна очередь без приоритетов, а игры, которые в момент сохранения были в mpsc-канале, в неё не попадали
+/// поэтому в очередь ставятся все игры, для которых /get-game-details ещё не выполнен (в порядке появления)
+fn convert_fetcher_state(fetcher_state: FetcherStateV0, state: &State) -> fetcher_get_game_details::State {
+    let game_ids: VecDeque<GameId> = state.games.values()
+        .filter(|game| !game.are_details_resolved())
+        .map(|game| game.game_id)
+        .collect();
+    let number_lost_game_ids = game_ids.len().saturating_sub(fetcher_state.game_ids.len());
+    if number_lost_game_ids != 0 {
+        println!("[info]  [external_storage] requeue {} game_ids missing in fetcher_get_game_details queue", number_lost_game_ids);
+    }
+    fetcher_get_game_details::State {
+        merge_waiting_game_ids: VecDeque::new(),
+        game_ids,
+        refetch_game_ids: VecDeque::new(),
+        in_progress: Vec::new(),
+        failures: HashMap::new(),
+    }
+}
+
+pub fn load_state_v0(reader: impl Read) -> WholeState {
+    let (updater_state, state, fetcher_get_game_details_state): (UpdaterStateV0, StateV0<GamesV0>, FetcherStateV0) =
+        bincode::deserialize_from(reader).unwrap();
+    let empty_state = get_empty_state();
+
+    let state = convert_state(state, empty_state.state);
+    let updater_state = UpdaterState {
+        scheduled_to_merge_host_ids: updater_state.scheduled_to_merge_host_ids,
+        hosts_history: updater::rebuild_hosts_history(&state),
+        // для текущих игр заполняется первым ответом /get-games (см. `updater::schedule_details_refetch`)
+        details_refetch_times: HashMap::new(),
+        ..empty_state.updater_state
+    };
+    let fetcher_get_game_details_state = convert_fetcher_state(fetcher_get_game_details_state, &state);
+    println!("[info]  [external_storage] converted state from format version 0");
+    WholeState { updater_state, state, fetcher_get_game_details_state }
+}
@@ -1,7 +1,7 @@
 use std::collections::VecDeque;
 use std::error::Error;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::num::NonZeroU32;
 use std::path::Path;
 use std::sync::{Arc, mpsc};
@@ -14,11 +14,14 @@ use parking_lot::RwLock;
 
 use crate::{fetcher_get_game_details, state, yandex_cloud_storage};
 use crate::state::{BigString, State, StateLock};
+use crate::state::global_online::GlobalOnline;
+use crate::state::players_index::PlayersIndex;
 use crate::state::updater::UpdaterState;
 use crate::util::{new_buf_reader, new_buf_writer};
 
 mod backups;
-mod compression;
+pub(crate) mod compression;
+pub(crate) mod legacy;
 
 const PRIMARY_STATES_DIRECTORY: &str = "states-hourly";
 const TEMPORARY_STATE_FILE: &str = "state.bin.lz4";
@@ -26,6 +29,13 @@ const TEMPORARY_LZ4_FILE_FOR_RECOMPRESS: &str = "state-recompress.bin.lz4";
 const TEMPORARY_XZ_FILE_FOR_RECOMPRESS: &str = "state-recompress.bin.xz";
 const CONTENT_TYPE: &str = "application/octet-stream";
 
+// сохранённое состояние начинается с FORMAT_MAGIC и версии формата (u32 little endian), затем bincode
+// bincode не хранит схему, поэтому при любом изменении сохраняемых структур нужно увеличить FORMAT_VERSION
+// и добавить конвертацию из предыдущей версии (состояния без заголовка — версия 0, см. `legacy`)
+const FORMAT_MAGIC: &[u8; 8] = b"FSSSTATE";
+const FORMAT_VERSION: u32 = 1;
+const FORMAT_HEADER_LENGTH: usize = 12;
+
 #[derive(Eq, PartialEq)]
 pub struct WholeState {
     pub updater_state: UpdaterState,
@@ -44,6 +54,8 @@ impl WholeState {
 pub fn get_empty_state() -> WholeState {
     let updater_state = UpdaterState {
         scheduled_to_merge_host_ids: HashMap::new(),
+        hosts_history: HashMap::new(),
+        details_refetch_times: HashMap::new(),
     };
 
     // нужно так как ServerId это NonZeroU32
@@ -52,6 +64,15 @@ pub fn get_empty_state() -> WholeState {
         games: state::GamesMap::new(),
         game_ids: vec![dummy_first_game_id],
         current_game_ids: vec![],
+        last_observed_time: None,
+        observation_gaps: vec![],
+        server_overrides: vec![],
+        metadata_changes: HashMap::new(),
+        details_revisions: HashMap::new(),
+        details_extra: HashMap::new(),
+        builds: Vec::new(),
+        global_online: GlobalOnline::default(),
+        players_index: PlayersIndex::default(),
         all_game_names: BigString::new(),
         all_game_descriptions: BigString::new(),
         all_versions: BigString::new(),
@@ -59,10 +80,15 @@ pub fn get_empty_state() -> WholeState {
         all_host_addresses: BigString::new(),
         all_mod_names: BigString::new(),
         all_player_names: BigString::new(),
+        all_steam_ids: BigString::new(),
     };
 
     let fetcher_get_game_details_state = fetcher_get_game_details::State {
-        game_ids: VecDeque::new()
+        merge_waiting_game_ids: VecDeque::new(),
+        game_ids: VecDeque::new(),
+        refetch_game_ids: VecDeque::new(),
+        in_progress: Vec::new(),
+        failures: HashMap::new(),
     };
 
     WholeState {
@@ -81,12 +107,28 @@ pub fn get_last_state_path() -> Option<String> {
     paths.into_iter().max()
 }
 
-fn load_state_from_reader(reader: impl Read) -> WholeState {
-    let (updater_state, state, fetcher_get_game_details_state) = bincode::deserialize_from(reader).unwrap();
-    let mut state: State = state;
-    state.fix_cyclic_prev_game_id();
+pub(crate) fn load_state_from_reader(mut reader: impl Read) -> WholeState {
+    let mut header = [0u8; FORMAT_HEADER_LENGTH];
+    reader.read_exact(&mut header).unwrap();
+    let mut whole_state = if header.starts_with(FORMAT_MAGIC) {
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&header[FORMAT_MAGIC.len()..]);
+        match u32::from_le_bytes(version) {
+            FORMAT_VERSION => {
+                let (updater_state, state, fetcher_get_game_details_state) = bincode::deserialize_from(reader).unwrap();
+                WholeState { updater_state, state, fetcher_get_game_details_state }
+            }
+            version => panic!("Unsupported state format version {}", version),
+        }
+    } else {
+        // старый формат без заголовка: прочитанные байты — начало bincode
+        legacy::load_state_v0((&header[..]).chain(reader))
+    };
+
+    let state = &mut whole_state.state;
+    state.rebuild_players_index();
     state.validate_state();
-    WholeState { updater_state, state, fetcher_get_game_details_state }
+    whole_state
 }
 
 pub fn load_state_from_cloud() -> WholeState {
@@ -112,8 +154,10 @@ pub fn save_state_to_file(whole_state: WholeStateRef, filename: &str) {
 
     let writer = File::create(filename).unwrap();
     let mut writer = new_buf_writer(writer);
-    let writer = compression::new_encoder(&mut writer, filename);
+    let mut writer = compression::new_encoder(&mut writer, filename);
 
+    writer.write_all(FORMAT_MAGIC).unwrap();
+    writer.write_all(&FORMAT_VERSION.to_le_bytes()).unwrap();
     bincode::serialize_into(writer, &data).unwrap();
 }
 
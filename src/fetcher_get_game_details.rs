@@ -1,102 +1,630 @@
+//! Запросы /get-game-details выполняются несколькими worker-потоками с общим ограничением на число запросов в секунду
+//! Очередь запросов разделена по приоритетам (см. [Priority]) и сохраняется в WholeState
+//! Порядок блокировок как у saver: сначала State, затем fetcher_get_game_details::State
+
 use std::collections::VecDeque;
 use std::sync::{Arc, mpsc};
-use std::sync::mpsc::TryRecvError;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use chrono::Utc;
-use parking_lot::RwLock;
+use hashbrown::HashMap;
+use itertools::Itertools;
+use parking_lot::{Condvar, Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 
 use crate::api;
+use crate::archiver::{self, ArchiverSender, Endpoint};
 use crate::global_config::GLOBAL_CONFIG;
-use crate::state::{GameId, Mod, StateLock};
+use crate::state::{self, DetailsStatus, GameDetailsExtra, GameId, Mod, StateLock, TimeMinutes};
+use crate::state::metadata::DetailsRevision;
+
+// через сколько worker повторно проверяет очередь, в которой все запросы отложены (см. [Decision::Defer])
+const DEFERRED_RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+// запрос, завершившийся ошибкой, повторяется через 10с, 20с, 40с, ...
+const RETRY_INITIAL_DELAY: Duration = Duration::from_secs(10);
+// после стольких ошибок подряд details игры считаются недоступными (иначе ожидающее их объединение хоста не произойдёт)
+const MAX_NUMBER_FAILURES: u32 = 5;
+
+// в порядке уменьшения приоритета
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Priority {
+    // новая игра, объединение хоста которой ожидает получения game details (см. `updater::try_merge_host`)
+    MergeWaiting,
+    // первый запрос для новой игры
+    New,
+    // периодический повторный запрос для долгих игр (моды и адрес могут измениться)
+    Refetch,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub game_id: GameId,
+    pub priority: Priority,
+}
 
 #[derive(Eq, PartialEq, Serialize, Deserialize)]
 pub struct State {
+    pub merge_waiting_game_ids: VecDeque<GameId>,
     pub game_ids: VecDeque<GameId>,
+    pub refetch_game_ids: VecDeque<GameId>,
+    // запросы, которые выполняются прямо сейчас
+    // (при загрузке WholeState возвращаются в очередь, см. [State::requeue_in_progress])
+    pub in_progress: Vec<Request>,
+    // запросы, завершившиеся ошибкой (после перезапуска счётчики начинаются заново)
+    #[serde(skip)]
+    pub failures: HashMap<GameId, Failure>,
 }
 
-pub fn fetcher(receiver: mpsc::Receiver<GameId>, fetcher_state_lock: Arc<RwLock<State>>, state_lock: StateLock) {
-    for iteration in 0.. {
-        if !GLOBAL_CONFIG.lock().unwrap().use_cache_for_get_game_details {
-            thread::sleep(Duration::SECOND);
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Failure {
+    number_failures: u32,
+    // до этого момента запрос остаётся в очереди, но не выполняется
+    retry_time: Instant,
+}
+
+impl State {
+    fn get_queue(&mut self, priority: Priority) -> &mut VecDeque<GameId> {
+        match priority {
+            Priority::MergeWaiting => &mut self.merge_waiting_game_ids,
+            Priority::New => &mut self.game_ids,
+            Priority::Refetch => &mut self.refetch_game_ids,
         }
+    }
+
+    pub fn len(&self) -> usize {
+        self.merge_waiting_game_ids.len() + self.game_ids.len() + self.refetch_game_ids.len()
+    }
 
-        // если game_ids пусто, то блокирующе получаем один game_id из mpsc
-        // если game_ids не пусто, то неблокирующе получаем все game_id из mpsc, затем обрабатываем один /get-game-details
-        let number_game_ids = fetcher_state_lock.read().game_ids.len();
-        if number_game_ids == 0 {
-            let game_id = receiver.recv();
-            if game_id.is_err() && GLOBAL_CONFIG.lock().unwrap().fetcher_get_game_details_exit_after_fetch_all {
-                break;
+    fn push(&mut self, request: Request) {
+        let game_id = request.game_id;
+        if request.priority == Priority::MergeWaiting {
+            // updater отправляет такой запрос каждую минуту, пока объединение ожидает game details
+            let is_in_progress = self.in_progress.iter().any(|request| request.game_id == game_id);
+            if is_in_progress || self.merge_waiting_game_ids.contains(&game_id) {
+                return;
+            }
+            let number_game_ids = self.game_ids.len();
+            self.game_ids.retain(|&id| id != game_id);
+            if self.game_ids.len() == number_game_ids {
+                // запрос уже выполнен (updater ещё не успел это увидеть)
+                return;
             }
+        }
+        self.get_queue(request.priority).push_back(game_id);
+    }
+
+    fn is_waiting_retry(&self, game_id: GameId, now: Instant) -> bool {
+        self.failures.get(&game_id).map_or(false, |failure| now < failure.retry_time)
+    }
 
-            let game_id = game_id.unwrap();
-            fetcher_state_lock.write().game_ids.push_back(game_id);
-        } else {
-            loop {
-                match receiver.try_recv() {
-                    Ok(game_id) => fetcher_state_lock.write().game_ids.push_back(game_id),
-                    Err(TryRecvError::Empty) => break,
-                    Err(TryRecvError::Disconnected) => {
-                        if GLOBAL_CONFIG.lock().unwrap().fetcher_get_game_details_exit_after_fetch_all {
-                            break;
-                        }
-                        panic!("[error] [fetcher_get_game_details] channel disconnected")
+    fn start_next(&mut self, mut decide: impl FnMut(Request) -> Decision) -> Option<Request> {
+        let priorities = [Priority::MergeWaiting, Priority::New, Priority::Refetch];
+        let now = Instant::now();
+        for &priority in priorities.iter() {
+            // запросы, ожидающие повтора, перемещаются в конец очереди (но не больше одного раза каждый)
+            let mut number_waiting_retry = 0;
+            while let Some(&game_id) = self.get_queue(priority).front() {
+                if self.is_waiting_retry(game_id, now) {
+                    if number_waiting_retry == self.get_queue(priority).len() {
+                        break;
                     }
+                    number_waiting_retry += 1;
+                    let queue = self.get_queue(priority);
+                    queue.pop_front();
+                    queue.push_back(game_id);
+                    continue;
+                }
+                let request = Request { game_id, priority };
+                match decide(request) {
+                    Decision::Fetch => {
+                        self.get_queue(priority).pop_front();
+                        self.in_progress.push(request);
+                        return Some(request);
+                    }
+                    Decision::Skip => {
+                        self.get_queue(priority).pop_front();
+                        self.failures.remove(&game_id);
+                    }
+                    // игры в очереди упорядочены по времени появления, поэтому остальные тоже откладываются
+                    Decision::Defer => break,
                 }
             }
+        }
+        None
+    }
 
-            fetch_one_game_details(&fetcher_state_lock, &state_lock);
+    fn finish(&mut self, request: Request) {
+        let index = self.in_progress.iter().position(|&r| r == request).unwrap();
+        self.in_progress.swap_remove(index);
+        self.failures.remove(&request.game_id);
+    }
+
+    // запрос завершился ошибкой, повторим его после остальных запросов с таким же приоритетом (с экспоненциальной задержкой)
+    // возвращает false, если попытки закончились (см. MAX_NUMBER_FAILURES)
+    fn requeue(&mut self, request: Request) -> bool {
+        let number_failures = self.failures.get(&request.game_id).map_or(0, |failure| failure.number_failures) + 1;
+        self.finish(request);
+        if number_failures >= MAX_NUMBER_FAILURES {
+            return false;
+        }
+        let retry_time = Instant::now() + RETRY_INITIAL_DELAY * 2u32.pow(number_failures - 1);
+        self.failures.insert(request.game_id, Failure { number_failures, retry_time });
+        self.get_queue(request.priority).push_back(request.game_id);
+        true
+    }
 
-            if number_game_ids > 100 && iteration % 200 == 1 {
-                eprintln!("[warn]  [fetcher_get_game_details] number game_ids to fetch is too big: {}", number_game_ids);
+    pub fn requeue_in_progress(&mut self) {
+        for request in std::mem::take(&mut self.in_progress) {
+            self.get_queue(request.priority).push_front(request.game_id);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Decision {
+    Fetch,
+    // запрос остаётся в очереди
+    Defer,
+    // запрос удаляется из очереди без выполнения
+    Skip,
+}
+
+/// многие игры исчезают через несколько минут после появления, для них не запрашиваем /get-game-details
+/// (запрос откладывается пока игра не будет наблюдаться `delay` минут)
+/// `current_time` — время последнего ответа /get-games
+fn get_new_game_decision(game: &state::Game, priority: Priority, current_time: TimeMinutes, delay: u32) -> Decision {
+    // объединение хоста не откладываем, даже если игра уже завершилась (для объединения нужен адрес)
+    if priority == Priority::Refetch || priority == Priority::MergeWaiting {
+        return Decision::Fetch;
+    }
+    match game.time_end {
+        Some(time_end) if time_end.get() - game.time_begin.get() < delay => Decision::Skip,
+        Some(_) => Decision::Fetch,
+        None if current_time.get() - game.time_begin.get() < delay => Decision::Defer,
+        None => Decision::Fetch,
+    }
+}
+
+fn decide(request: Request, state: &mut state::State, delay: u32, channel_disconnected: bool) -> Decision {
+    let current_time = state.last_observed_time.unwrap_or_else(TimeMinutes::now);
+    let decision = match get_new_game_decision(state.get_game(request.game_id), request.priority, current_time, delay) {
+        // время больше не идёт (см. `fetcher_get_game_details_exit_after_fetch_all`)
+        Decision::Defer if channel_disconnected => Decision::Fetch,
+        decision => decision,
+    };
+    if decision == Decision::Skip {
+        println!("[info]  [fetcher_get_game_details] skip game_id {:8}: game is too short", request.game_id);
+        state.get_game_mut(request.game_id).details_status = DetailsStatus::Skipped;
+    }
+    decision
+}
+
+/// общий для всех worker-ов лимит на число запросов в секунду
+struct RateLimiter {
+    interval: Duration,
+    next_request_time: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: f64) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next_request_time: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn wait(&self) {
+        let sleep_duration = {
+            let mut next_request_time = self.next_request_time.lock();
+            let now = Instant::now();
+            let request_time = std::cmp::max(*next_request_time, now);
+            *next_request_time = request_time + self.interval;
+            request_time - now
+        };
+        thread::sleep(sleep_duration);
+    }
+}
+
+/// будит ожидающих worker-ов при изменении очереди
+/// (изменение очереди должно происходить до вызова notify, а проверка очереди — под `mutex`, тогда уведомление не теряется)
+#[derive(Default)]
+struct QueueEvents {
+    mutex: Mutex<()>,
+    condvar: Condvar,
+}
+
+impl QueueEvents {
+    fn notify_one(&self) {
+        let _guard = self.mutex.lock();
+        self.condvar.notify_one();
+    }
+
+    fn notify_all(&self) {
+        let _guard = self.mutex.lock();
+        self.condvar.notify_all();
+    }
+
+    /// ждёт пока в очереди появятся запросы
+    /// возвращает false, если канал закрыт и все запросы выполнены
+    fn wait_for_requests(&self, fetcher_state_lock: &RwLock<State>, channel_disconnected: &AtomicBool) -> bool {
+        let mut guard = self.mutex.lock();
+        loop {
+            {
+                let fetcher_state = fetcher_state_lock.read();
+                if fetcher_state.len() != 0 {
+                    return true;
+                }
+                if channel_disconnected.load(Ordering::SeqCst) && fetcher_state.in_progress.is_empty() {
+                    return false;
+                }
             }
+            self.condvar.wait(&mut guard);
         }
     }
-    println!("[info]  [fetcher_get_game_details] exit");
+
+    fn wait_timeout(&self, timeout: Duration) {
+        let mut guard = self.mutex.lock();
+        self.condvar.wait_for(&mut guard, timeout);
+    }
 }
 
-fn fetch_one_game_details(fetcher_state_lock: &Arc<RwLock<State>>, state_lock: &StateLock) {
-    let (game_id, number_game_ids): (GameId, usize) = {
-        let game_ids = &fetcher_state_lock.read().game_ids;
-        (*game_ids.front().unwrap(), game_ids.len())
+pub fn fetcher(receiver: mpsc::Receiver<Request>, fetcher_state_lock: Arc<RwLock<State>>, state_lock: StateLock, archiver_sender: ArchiverSender) {
+    fetcher_state_lock.write().requeue_in_progress();
+
+    let (number_workers, requests_per_second, use_cache, delay) = {
+        let global_config = GLOBAL_CONFIG.lock().unwrap();
+        (
+            global_config.get_game_details_number_workers,
+            global_config.get_game_details_requests_per_second,
+            global_config.use_cache_for_get_game_details,
+            global_config.get_game_details_delay,
+        )
     };
-    println!("[info]  [fetcher_get_game_details] fetch game_id {:8} at {}    (game ids queue length = {})",
-             game_id, Utc::now(), number_game_ids);
-    let game_snapshot = api::get_game_details(game_id.get() as u64);
+    // для кешированных ответов ограничение не нужно
+    let rate_limiter = Arc::new(if use_cache { None } else { Some(RateLimiter::new(requests_per_second)) });
+    let channel_disconnected = Arc::new(AtomicBool::new(false));
+    let queue_events = Arc::new(QueueEvents::default());
+
+    let workers: Vec<_> = (0..number_workers)
+        .map(|worker_index| {
+            let fetcher_state_lock = fetcher_state_lock.clone();
+            let state_lock = state_lock.clone();
+            let archiver_sender = archiver_sender.clone();
+            let rate_limiter = rate_limiter.clone();
+            let channel_disconnected = channel_disconnected.clone();
+            let queue_events = queue_events.clone();
+            thread::Builder::new()
+                .name(format!("fetcher_get_game_details_worker_{}", worker_index))
+                .spawn(move || worker(fetcher_state_lock, state_lock, archiver_sender, rate_limiter, channel_disconnected, queue_events, delay))
+                .unwrap()
+        })
+        .collect();
+
+    for request in receiver {
+        fetcher_state_lock.write().push(request);
+        queue_events.notify_one();
+    }
+    if !GLOBAL_CONFIG.lock().unwrap().fetcher_get_game_details_exit_after_fetch_all {
+        panic!("[error] [fetcher_get_game_details] channel disconnected")
+    }
+
+    // workers завершатся когда очередь станет пустой
+    channel_disconnected.store(true, Ordering::SeqCst);
+    queue_events.notify_all();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    println!("[info]  [fetcher_get_game_details] exit");
+}
+
+fn worker(
+    fetcher_state_lock: Arc<RwLock<State>>,
+    state_lock: StateLock,
+    archiver_sender: ArchiverSender,
+    rate_limiter: Arc<Option<RateLimiter>>,
+    channel_disconnected: Arc<AtomicBool>,
+    queue_events: Arc<QueueEvents>,
+    delay: u32,
+) {
+    for iteration in 0.. {
+        // пустая очередь проверяется без блокировки State
+        if !queue_events.wait_for_requests(&fetcher_state_lock, &channel_disconnected) {
+            break;
+        }
+
+        let (request, number_game_ids) = {
+            let mut state = state_lock.write();
+            let mut fetcher_state = fetcher_state_lock.write();
+            let is_channel_disconnected = channel_disconnected.load(Ordering::SeqCst);
+            let request = fetcher_state.start_next(|request| decide(request, &mut state, delay, is_channel_disconnected));
+            (request, fetcher_state.len())
+        };
+        let request = match request {
+            Some(request) => request,
+            None => {
+                // если очередь не пуста, то все запросы в ней отложены (иначе их забрали другие worker-ы)
+                if number_game_ids != 0 {
+                    queue_events.wait_timeout(DEFERRED_RECHECK_INTERVAL);
+                }
+                continue;
+            }
+        };
+
+        if let Some(rate_limiter) = rate_limiter.as_ref() {
+            rate_limiter.wait();
+        }
+        fetch_one_game_details(request, number_game_ids, &fetcher_state_lock, &state_lock, &archiver_sender);
+        // запрос мог вернуться в очередь, или это был последний выполняющийся запрос
+        queue_events.notify_all();
+
+        if number_game_ids > 100 && iteration % 200 == 1 {
+            eprintln!("[warn]  [fetcher_get_game_details] number game_ids to fetch is too big: {}", number_game_ids);
+        }
+    }
+}
+
+fn fetch_one_game_details(
+    request: Request,
+    number_game_ids: usize,
+    fetcher_state_lock: &Arc<RwLock<State>>,
+    state_lock: &StateLock,
+    archiver_sender: &ArchiverSender,
+) {
+    let game_id = request.game_id;
+    println!("[info]  [fetcher_get_game_details] fetch game_id {:8} ({:?}) at {}    (game ids queue length = {})",
+             game_id, request.priority, Utc::now(), number_game_ids);
+    // время последнего ответа /get-games, при replay оно отстаёт от текущего
+    let time = state_lock.read().last_observed_time.unwrap_or_else(TimeMinutes::now);
+    let replay_cache_directory = GLOBAL_CONFIG.lock().unwrap().get_game_details_replay_cache_directory.clone();
+    let replay_cache = replay_cache_directory.as_deref().map(|directory| api::ReplayCache {
+        directory,
+        time,
+        refetch: request.priority == Priority::Refetch,
+    });
+    let game_snapshot = api::get_game_details_raw(game_id.get() as u64, replay_cache)
+        .and_then(|response| {
+            archiver::archive(archiver_sender, Endpoint::GetGameDetails, time, Some(game_id), response.as_deref());
+            response.map(|response| api::parse_get_game_details_response(&response)).transpose()
+        });
 
     match game_snapshot {
-        Err(_) => eprintln!("[error] [fetcher_get_game_details] failed to fetch /get-game-details for game_id {}", game_id),
-        Ok(game_snapshot) => {
+        Err(err) => {
+            api::count_error(&err);
+            eprintln!("[error] [fetcher_get_game_details] failed to fetch /get-game-details for game_id {}: {}", game_id, err);
+            let mut state = state_lock.write();
             let mut fetcher_state = fetcher_state_lock.write();
+            if !fetcher_state.requeue(request) {
+                eprintln!("[error] [fetcher_get_game_details] give up fetching /get-game-details for game_id {} after {} failures",
+                          game_id, MAX_NUMBER_FAILURES);
+                // для повторного запроса details уже получены
+                if request.priority != Priority::Refetch {
+                    state.get_game_mut(game_id).details_status = DetailsStatus::Unavailable;
+                }
+            }
+        }
+        Ok(game_snapshot) => {
             let mut state = state_lock.write();
+            let mut fetcher_state = fetcher_state_lock.write();
+            apply_game_details(&mut state, request, game_snapshot, time);
+            fetcher_state.finish(request);
+        }
+    }
+}
+
+/// `time` — время последнего ответа /get-games на момент запроса
+pub fn apply_game_details(state: &mut state::State, request: Request, game_snapshot: Option<api::Game>, time: TimeMinutes) {
+    match (request.priority, game_snapshot) {
+        (Priority::MergeWaiting, game_snapshot) | (Priority::New, game_snapshot) =>
+            set_game_details(state, request.game_id, game_snapshot),
+        (Priority::Refetch, Some(game_snapshot)) => update_game_details(state, request.game_id, game_snapshot, time),
+        // игра уже завершилась
+        (Priority::Refetch, None) => {}
+    }
+}
+
+fn add_mods(state: &mut state::State, mods: &[api::Mod]) -> Vec<Mod> {
+    mods.iter().map(|mod_| {
+        let name = state.all_mod_names.add(&mod_.name);
+        let version = state.all_versions.add(&mod_.version);
+        Mod { name, version }
+    }).collect()
+}
+
+fn set_game_details(state: &mut state::State, game_id: GameId, game_snapshot: Option<api::Game>) {
+    let game_snapshot = match game_snapshot {
+        Some(game_snapshot) => game_snapshot,
+        None => {
+            state.get_game_mut(game_id).details_status = DetailsStatus::Unavailable;
+            return;
+        }
+    };
+
+    set_other_details(state, game_id, &game_snapshot);
+    let game_host_address = state.all_host_addresses.add(&game_snapshot.host_address.unwrap());
+    let mods = add_mods(state, &game_snapshot.mods.unwrap());
+
+    let game = state.get_game_mut(game_id);
+    game.details_status = DetailsStatus::Fetched;
+    game.host_address = Some(game_host_address);
+    game.mods = Some(mods);
+}
+
+// поля, изменения которых не сохраняются в истории
+fn set_other_details(state: &mut state::State, game_id: GameId, game_snapshot: &api::Game) {
+    let details_extra = GameDetailsExtra {
+        steam_id: game_snapshot.steam_id.as_ref().map(|steam_id| state.all_steam_ids.add(steam_id)),
+        mods_crc: game_snapshot.mods_crc,
+        require_user_verification: game_snapshot.require_user_verification.as_ref()
+            .map(|require_user_verification| require_user_verification == "true"),
+        last_heartbeat: game_snapshot.last_heartbeat
+            .and_then(|last_heartbeat| TimeMinutes::new((last_heartbeat / 60.0) as u32)),
+    };
+    if details_extra == GameDetailsExtra::default() {
+        state.details_extra.remove(&game_id);
+    } else {
+        state.details_extra.insert(game_id, details_extra);
+    }
+}
+
+fn are_mods_same(mods: &[Mod], mods_snapshot: &[api::Mod], state: &state::State) -> bool {
+    let mods: Vec<(&str, &str)> = mods.iter()
+        .map(|mod_| {
+            let name: &str = state.all_mod_names.get(mod_.name).into();
+            let version: &str = state.all_versions.get(mod_.version).into();
+            (name, version)
+        })
+        .sorted()
+        .collect();
+    let mods_snapshot: Vec<(&str, &str)> = mods_snapshot.iter()
+        .map(|mod_| (mod_.name.as_str(), mod_.version.as_str()))
+        .sorted()
+        .collect();
+    mods == mods_snapshot
+}
+
+/// повторный запрос: если моды или адрес изменились, то запоминаем изменение
+fn update_game_details(state: &mut state::State, game_id: GameId, game_snapshot: api::Game, time: TimeMinutes) {
+    let game = state.get_game(game_id);
+    let old_host_address = match game.host_address {
+        Some(host_address) => host_address,
+        // первый запрос ещё не выполнен
+        None => return,
+    };
+    set_other_details(state, game_id, &game_snapshot);
+    let game = state.get_game(game_id);
+    let old_mods = game.get_mods(state).clone().unwrap_or_default();
+
+    let host_address = game_snapshot.host_address.unwrap();
+    let mods = game_snapshot.mods.unwrap();
+    let host_address_changed = state.all_host_addresses.get(old_host_address).0 != host_address.as_bytes();
+    let mods_changed = !are_mods_same(&old_mods, &mods, state);
+    if !host_address_changed && !mods_changed {
+        return;
+    }
+    println!("[info]  [fetcher_get_game_details] details of game {} changed (host address: {}, mods: {})",
+             game_id, host_address_changed, mods_changed);
+
+    let host_address = if host_address_changed {
+        Some((old_host_address, state.all_host_addresses.add(&host_address)))
+    } else {
+        None
+    };
+    let mods = if mods_changed {
+        Some((old_mods, add_mods(state, &mods)))
+    } else {
+        None
+    };
 
-            let (host_address, mods) = match game_snapshot {
-                Some(game_snapshot) => (
-                    game_snapshot.host_address.unwrap(),
-                    game_snapshot.mods.unwrap(),
-                ),
-                None => (
-                    // todo подумать действительно ли это хороший план
-                    "unknown".to_owned(),
-                    vec![api::Mod { name: "unknown".to_owned(), version: "unknown".to_owned() }]
-                ),
-            };
-
-            let game_host_address = state.all_host_addresses.add(&host_address);
-            let mods = mods.iter().map(|mod_| {
-                let name = state.all_mod_names.add(&mod_.name);
-                let version = state.all_versions.add(&mod_.version);
-                Mod { name, version }
-            }).collect();
-
-            let game = state.get_game_mut(game_id);
-            game.host_address = Some(game_host_address);
-            game.mods = Some(mods);
-
-            fetcher_state.game_ids.pop_front();
+    let game = state.get_game_mut(game_id);
+    if let Some((_, new_host_address)) = host_address {
+        game.host_address = Some(new_host_address);
+    }
+    if let Some((_, new_mods)) = &mods {
+        game.mods = Some(new_mods.clone());
+    }
+    let revision = DetailsRevision { time, host_address, mods };
+    state.details_revisions.entry(game_id).or_default().push(revision);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::*;
+
+    fn request(game_id: u32, priority: Priority) -> Request {
+        Request { game_id: NonZeroU32::new(game_id).unwrap(), priority }
+    }
+
+    #[test]
+    fn queue_priorities() {
+        let mut state = State {
+            merge_waiting_game_ids: VecDeque::new(),
+            game_ids: VecDeque::new(),
+            refetch_game_ids: VecDeque::new(),
+            in_progress: Vec::new(),
+            failures: HashMap::new(),
+        };
+        state.push(request(1, Priority::Refetch));
+        state.push(request(2, Priority::New));
+        state.push(request(3, Priority::New));
+        state.push(request(3, Priority::MergeWaiting));
+        state.push(request(3, Priority::MergeWaiting));
+        // уже не в очереди
+        state.push(request(4, Priority::MergeWaiting));
+        assert_eq!(state.len(), 3);
+
+        let fetch_all = |_| Decision::Fetch;
+        let first = state.start_next(fetch_all).unwrap();
+        assert_eq!(first, request(3, Priority::MergeWaiting));
+        state.push(request(3, Priority::MergeWaiting));
+        assert_eq!(state.start_next(fetch_all), Some(request(2, Priority::New)));
+        assert!(state.requeue(first));
+        state.requeue_in_progress();
+        // 3 ожидает повтора после ошибки
+        assert_eq!(state.start_next(fetch_all), Some(request(2, Priority::New)));
+        assert_eq!(state.start_next(fetch_all), Some(request(1, Priority::Refetch)));
+        assert_eq!(state.start_next(fetch_all), None);
+        state.failures.get_mut(&first.game_id).unwrap().retry_time = Instant::now();
+        assert_eq!(state.start_next(fetch_all), Some(request(3, Priority::MergeWaiting)));
+        assert_eq!(state.start_next(fetch_all), None);
+    }
+
+    #[test]
+    fn queue_defer_and_skip() {
+        let mut state = State {
+            merge_waiting_game_ids: VecDeque::new(),
+            game_ids: VecDeque::new(),
+            refetch_game_ids: VecDeque::new(),
+            in_progress: Vec::new(),
+            failures: HashMap::new(),
+        };
+        state.push(request(1, Priority::New));
+        state.push(request(2, Priority::New));
+        state.push(request(3, Priority::New));
+        state.push(request(4, Priority::Refetch));
+
+        let decide = |request: Request| match request.game_id.get() {
+            1 => Decision::Skip,
+            2 => Decision::Defer,
+            _ => Decision::Fetch,
+        };
+        assert_eq!(state.start_next(decide), Some(request(4, Priority::Refetch)));
+        assert_eq!(state.start_next(decide), None);
+        assert_eq!(state.len(), 2);
+        assert_eq!(state.start_next(|_| Decision::Fetch), Some(request(2, Priority::New)));
+    }
+
+    #[test]
+    fn queue_retry_limit() {
+        let mut state = State {
+            merge_waiting_game_ids: VecDeque::new(),
+            game_ids: VecDeque::new(),
+            refetch_game_ids: VecDeque::new(),
+            in_progress: Vec::new(),
+            failures: HashMap::new(),
+        };
+        state.push(request(1, Priority::New));
+        state.push(request(2, Priority::New));
+
+        let fetch_all = |_| Decision::Fetch;
+        let first = state.start_next(fetch_all).unwrap();
+        for number_failures in 1..MAX_NUMBER_FAILURES {
+            assert!(state.requeue(first));
+            // остальные запросы не ждут повтора
+            if number_failures == 1 {
+                let second = state.start_next(fetch_all).unwrap();
+                assert_eq!(second, request(2, Priority::New));
+                state.finish(second);
+            }
+            assert_eq!(state.start_next(fetch_all), None);
+            state.failures.get_mut(&first.game_id).unwrap().retry_time = Instant::now();
+            assert_eq!(state.start_next(fetch_all), Some(first));
         }
+        assert!(!state.requeue(first));
+        assert_eq!(state.len(), 0);
+        assert!(state.in_progress.is_empty() && state.failures.is_empty());
     }
 }
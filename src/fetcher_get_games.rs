@@ -6,13 +6,14 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use chrono::Utc;
 
 use crate::api;
+use crate::archiver::{self, ArchiverSender, Endpoint};
 use crate::global_config::GLOBAL_CONFIG;
 use crate::state::TimeMinutes;
 use crate::util::duration_since;
 
 pub type FetcherOutput = (api::GetGamesResponse, TimeMinutes);
 
-pub fn fetcher(sender: mpsc::Sender<FetcherOutput>) {
+pub fn fetcher(sender: mpsc::Sender<FetcherOutput>, archiver_sender: ArchiverSender) {
     let minute = 60;  // в секундах
 
     let sleep_to_nearest_minute = |first_time: bool| {
@@ -49,7 +50,21 @@ pub fn fetcher(sender: mpsc::Sender<FetcherOutput>) {
                  Utc::now()
         );
 
-        let get_games_response = api::get_games();
+        let get_games_response = api::get_games_raw()
+            .and_then(|response| {
+                // archive before parsing, so that responses which we failed to parse are archived too
+                archiver::archive(&archiver_sender, Endpoint::GetGames, response_time, None, Some(&response));
+                api::parse_get_games_response(&response)
+            });
+        let get_games_response = match get_games_response {
+            Ok(get_games_response) => get_games_response,
+            Err(err) => {
+                let number_errors = api::count_error(&err);
+                eprintln!("[error] [fetcher_get_games] skip minute {}: /get-games failed (kind={}, number errors of this kind={}): {}",
+                          response_time.get(), err.kind(), number_errors, err);
+                continue;
+            }
+        };
         sender.send((get_games_response, response_time)).unwrap();
     }
 }
@@ -1,23 +1,123 @@
-use std::fs::File;
+//! Replays recorded /get-games responses (instead of fetching them), so that `State` can be rebuilt from raw history
+//! Responses are sent to updater as fast as possible, in order of their time
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 
 use crate::api;
+use crate::archiver::{self, Endpoint};
 use crate::fetcher_get_games::FetcherOutput;
 use crate::state::TimeMinutes;
 
-pub fn fetcher(sender: mpsc::Sender<FetcherOutput>, number_responses: u32) {
-    assert!(number_responses <= 2880);
+/// owned by replay, so it can be removed before each replay (unlike `api::get_game_details_cached`)
+pub const GET_GAME_DETAILS_REPLAY_CACHE_DIRECTORY: &str = "temp/replay-get-game-details";
+
+pub enum ReplaySource {
+    /// `<directory>/{:04}.json` files (old format of `temp/cache-get-games`), time is faked as `1 + i`
+    Cache { directory: PathBuf, number_responses: u32 },
+    /// directory with files created by `archiver`, responses have their real times
+    Archive { directory: PathBuf },
+}
+
+pub fn fetcher(
+    sender: mpsc::SyncSender<FetcherOutput>,
+    source: &ReplaySource,
+    time_begin: Option<TimeMinutes>,
+    time_end: Option<TimeMinutes>,
+) {
+    let time_begin = time_begin.map_or(0, |time| time.get());
+    let time_end = time_end.map_or(u32::MAX, |time| time.get());
+    match source {
+        ReplaySource::Cache { directory, number_responses } =>
+            replay_cache(&sender, directory, *number_responses, time_begin, time_end),
+        ReplaySource::Archive { directory } => {
+            prepare_get_game_details_cache(directory, time_end);
+            replay_archive(&sender, directory, time_begin, time_end);
+        }
+    }
+}
+
+fn send_response(sender: &mpsc::SyncSender<FetcherOutput>, response: &str, response_time: TimeMinutes) {
+    match api::parse_get_games_response(response) {
+        Ok(get_games_response) => sender.send((get_games_response, response_time)).unwrap(),
+        // as in `fetcher_get_games`, minute with bad response is skipped
+        Err(err) => eprintln!("[error] [fetcher_get_games_offline] skip minute {}: {}", response_time.get(), err),
+    }
+}
+
+fn replay_cache(sender: &mpsc::SyncSender<FetcherOutput>, directory: &Path, number_responses: u32, time_begin: u32, time_end: u32) {
     for i in 0..number_responses {
+        let response_time = TimeMinutes::new(1 + i).unwrap();
+        if response_time.get() < time_begin || response_time.get() >= time_end {
+            continue;
+        }
         if i % 10 == 0 {
             println!("[info]  [fetcher_get_games_offline] iteration: {:4}", i);
         }
-        let file = File::open(format!("temp/cache-get-games/{:04}.json", i)).unwrap();
-        let games: Vec<api::Game> = serde_json::from_reader(file).unwrap();
-        let mut games = games.into_iter().map(|game| game.into()).collect();
-        api::clean_get_games_response(&mut games);
 
-        let get_games_response = games;
-        let response_time = TimeMinutes::new(1 + i).unwrap();
-        sender.send((get_games_response, response_time)).unwrap()
+        let path = directory.join(format!("{:04}.json", i));
+        match fs::read_to_string(&path) {
+            Ok(response) => send_response(sender, &response, response_time),
+            // missing file is a gap in observations
+            Err(_) => eprintln!("[warn]  [fetcher_get_games_offline] skip minute {}: no file {:?}", response_time.get(), path),
+        }
+    }
+}
+
+fn replay_archive(sender: &mpsc::SyncSender<FetcherOutput>, directory: &Path, time_begin: u32, time_end: u32) {
+    let mut last_time: Option<TimeMinutes> = None;
+    for path in archiver::get_archive_files(directory, Endpoint::GetGames, time_begin, time_end) {
+        println!("[info]  [fetcher_get_games_offline] replay {:?}", path);
+        for record in archiver::read_archive_file(&path) {
+            let time = record.time;
+            if time.get() < time_begin || time.get() >= time_end {
+                continue;
+            }
+            // updater expects strictly increasing times
+            if last_time.map_or(false, |last_time| time <= last_time) {
+                eprintln!("[warn]  [fetcher_get_games_offline] skip record with non-increasing time {}", time.get());
+                continue;
+            }
+            last_time = Some(time);
+
+            match record.response {
+                Some(response) => send_response(sender, &response, time),
+                None => eprintln!("[warn]  [fetcher_get_games_offline] skip minute {}: /get-games returned 404", time.get()),
+            }
+        }
+    }
+}
+
+/// `fetcher_get_game_details` (with `get_game_details_replay_cache_directory`) will read archived responses from it
+/// all responses of the game are kept, so that refetches get the same responses as in production
+fn prepare_get_game_details_cache(directory: &Path, time_end: u32) {
+    let cache_directory = Path::new(GET_GAME_DETAILS_REPLAY_CACHE_DIRECTORY);
+    // cache from the previous replay may be built from other archive or time range
+    if cache_directory.exists() {
+        fs::remove_dir_all(cache_directory).unwrap();
+    }
+    fs::create_dir_all(cache_directory).unwrap();
+    // games which are alive at time_begin may be created (and their details fetched) long before time_begin,
+    // and details of game created just before time_end may be fetched a bit later than time_end
+    let time_end_details = time_end.saturating_add(60);
+    let files = archiver::get_archive_files(directory, Endpoint::GetGameDetails, 0, time_end_details);
+    for path in files {
+        println!("[info]  [fetcher_get_games_offline] prepare /get-game-details cache from {:?}", path);
+        for record in archiver::read_archive_file(&path) {
+            let game_id = match record.game_id {
+                Some(game_id) => game_id,
+                None => continue,
+            };
+            // format of `api::get_game_details_replayed`, records are appended in order of their time
+            let cached_response = api::CachedGameDetails { time: record.time, response: record.response };
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(api::get_game_details_replay_cache_path(cache_directory, game_id.get() as u64))
+                .unwrap();
+            writeln!(file, "{}", serde_json::to_string(&cached_response).unwrap()).unwrap();
+        }
     }
 }
@@ -1,7 +1,12 @@
+use std::env;
+use std::path::PathBuf;
 use std::sync::Mutex;
 
 use lazy_static::lazy_static;
 
+use crate::api;
+use crate::archiver::ArchivePeriod;
+
 lazy_static! {
     pub static ref GLOBAL_CONFIG: Mutex<GlobalConfig> = Mutex::new(GlobalConfig::new());
 }
@@ -9,8 +14,23 @@ lazy_static! {
 pub struct GlobalConfig {
     pub fetcher_get_games_skip_first_sleep: bool,
     pub use_cache_for_get_game_details: bool,
+    // archived /get-game-details responses for replay (see `fetcher_get_games_offline::prepare_get_game_details_cache`)
+    pub get_game_details_replay_cache_directory: Option<PathBuf>,
     pub fetcher_get_game_details_exit_after_fetch_all: bool,
     pub pipeline: String,
+    // can be overridden to point fetchers to the mock server (see `mock_api` pipeline)
+    pub api_base_url: String,
+    // if false, unknown fields in api responses are only reported (see `api::unknown_fields`)
+    pub api_strict_schema: bool,
+    // how often archived api responses are uploaded to Yandex.Cloud (see `archiver`), ARCHIVE_PERIOD=hour or day
+    pub archive_period: ArchivePeriod,
+    // in minutes, None means that /get-game-details is fetched only once for each game
+    pub get_game_details_refetch_interval: Option<u32>,
+    // shared by all workers of `fetcher_get_game_details`
+    pub get_game_details_requests_per_second: f64,
+    pub get_game_details_number_workers: usize,
+    // in minutes, /get-game-details is not fetched for games which disappeared earlier (unless merge is waiting for them)
+    pub get_game_details_delay: u32,
 }
 
 impl GlobalConfig {
@@ -18,8 +38,31 @@ impl GlobalConfig {
         GlobalConfig {
             fetcher_get_games_skip_first_sleep: false,
             use_cache_for_get_game_details: false,
+            get_game_details_replay_cache_directory: None,
             fetcher_get_game_details_exit_after_fetch_all: false,
             pipeline: "unknown".to_owned(),
+            api_base_url: env::var("FACTORIO_API_BASE_URL")
+                .unwrap_or_else(|_| api::DEFAULT_API_BASE_URL.to_owned()),
+            api_strict_schema: env::var("FACTORIO_API_STRICT_SCHEMA")
+                .map(|value| value.parse().expect("FACTORIO_API_STRICT_SCHEMA must be true or false"))
+                .unwrap_or(false),
+            archive_period: env::var("ARCHIVE_PERIOD")
+                .map(|value| ArchivePeriod::parse(&value).expect("ARCHIVE_PERIOD must be hour or day"))
+                .unwrap_or(ArchivePeriod::Day),
+            get_game_details_refetch_interval: match env::var("GET_GAME_DETAILS_REFETCH_INTERVAL") {
+                Ok(interval) => Some(interval.parse().expect("GET_GAME_DETAILS_REFETCH_INTERVAL must be number of minutes"))
+                    .filter(|&interval| interval != 0),
+                Err(_) => Some(6 * 60),
+            },
+            get_game_details_requests_per_second: env::var("GET_GAME_DETAILS_REQUESTS_PER_SECOND")
+                .map(|value| value.parse().expect("GET_GAME_DETAILS_REQUESTS_PER_SECOND must be number"))
+                .unwrap_or(1.0),
+            get_game_details_number_workers: env::var("GET_GAME_DETAILS_NUMBER_WORKERS")
+                .map(|value| value.parse().expect("GET_GAME_DETAILS_NUMBER_WORKERS must be number"))
+                .unwrap_or(4),
+            get_game_details_delay: env::var("GET_GAME_DETAILS_DELAY")
+                .map(|value| value.parse().expect("GET_GAME_DETAILS_DELAY must be number of minutes"))
+                .unwrap_or(10),
         }
     }
 }
@@ -4,6 +4,7 @@
 #![feature(type_alias_impl_trait)]
 
 pub mod api;
+pub mod archiver;
 pub mod external_storage;
 pub mod fetcher_get_games;
 pub mod fetcher_get_game_details;
@@ -13,6 +14,8 @@ pub mod util;
 pub mod analytics;
 pub mod global_config;
 pub mod cacher;
+pub mod search;
+pub mod mods_statistics;
 pub mod yandex_cloud_storage;
 
 #[cfg(test)]
@@ -3,7 +3,7 @@
 #![feature(decl_macro)]
 
 use std::{fs, thread};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, mpsc};
 use std::thread::JoinHandle;
 use std::time::Duration;
@@ -12,16 +12,21 @@ use clap::{App, value_t};
 use parking_lot::RwLock;
 
 use cacher::CacherState;
-use fss::{analytics, api, cacher, external_storage, fetcher_get_game_details, fetcher_get_games, fetcher_get_games_offline, state, util, yandex_cloud_storage};
+use fss::{analytics, api, archiver, cacher, external_storage, fetcher_get_game_details, fetcher_get_games, fetcher_get_games_offline, state, util, yandex_cloud_storage};
 use fss::global_config::GLOBAL_CONFIG;
-use fss::state::StateLock;
+use fss::fetcher_get_games_offline::ReplaySource;
+use fss::state::{StateLock, TimeMinutes};
+use fss::state::overrides::ServerOverride;
 use fss::util::basename;
 
 mod server;
+mod mock_api;
 
 const DEBUG_STATE_FILE: &str = "temp/state/state.bin";
 //const DEBUG_STATE_FILE: &str = "temp/state/state.bin.xz";
 //const DEBUG_STATE_FILE: &str = "temp/state/state.bin.lz4";
+// число ответов /get-games, которые fetcher_get_games_offline может прочитать раньше updater
+const REPLAY_CHANNEL_CAPACITY: usize = 16;
 
 fn main() {
     dotenv::dotenv().ok();
@@ -29,9 +34,21 @@ fn main() {
     let arguments = App::new("Factorio servers statistics")
         .arg_from_usage("<TYPE>")
         .arg_from_usage("--number_responses [val], 'only for TYPE = create_state_from_saved_data or create_state'")
+        .arg_from_usage("--api_base_url [val], 'matchmaking server url, e.g. http://localhost:8001 for mock_api'")
+        .arg_from_usage("--script [val], 'only for TYPE = mock_api'")
+        .arg_from_usage("--port [val], 'only for TYPE = mock_api'")
+        .arg_from_usage("--archive [val], 'only for TYPE = replay, directory with archived api responses'")
+        .arg_from_usage("--time_begin [val], 'only for TYPE = replay, in minutes since UNIX_EPOCH'")
+        .arg_from_usage("--time_end [val], 'only for TYPE = replay, in minutes since UNIX_EPOCH'")
+        .arg_from_usage("--download, 'only for TYPE = replay, download archive from Yandex.Cloud'")
+        .arg_from_usage("--overrides [val], 'only for TYPE = replay, json file with server overrides (see /admin/server-overrides), by default they are taken from the last saved state'")
         .get_matches();
     let pipeline = arguments.value_of("TYPE").unwrap();
 
+    if let Some(api_base_url) = arguments.value_of("api_base_url") {
+        GLOBAL_CONFIG.lock().unwrap().api_base_url = api_base_url.to_owned();
+    }
+
     if pipeline != "production" {
         GLOBAL_CONFIG.lock().unwrap().use_cache_for_get_game_details = true;
     }
@@ -52,6 +69,17 @@ fn main() {
             let number_responses = value_t!(arguments, "number_responses", u32).unwrap_or_else(|e| e.exit());
             create_state_from_saved_data(number_responses);
         }
+        "replay" => {
+            GLOBAL_CONFIG.lock().unwrap().fetcher_get_game_details_exit_after_fetch_all = true;
+            let parse_time = |name: &str| value_t!(arguments, name, u32).ok().map(|time| TimeMinutes::new(time).unwrap());
+            run_replay(
+                arguments.value_of("archive"),
+                parse_time("time_begin"),
+                parse_time("time_end"),
+                arguments.is_present("download"),
+                arguments.value_of("overrides"),
+            );
+        }
         "create_state" => {
             let number_responses = value_t!(arguments, "number_responses", u32).unwrap_or_else(|e| e.exit());
             create_state(number_responses);
@@ -67,6 +95,11 @@ fn main() {
         "recompress_backups" => external_storage::recompress_backups().unwrap(),
         "compress_state" => compress_state(),
         "print_state_heap_size" => print_state_heap_size(),
+        "mock_api" => {
+            let script = arguments.value_of("script").expect("--script is required for TYPE = mock_api");
+            let port = value_t!(arguments, "port", u16).unwrap_or(mock_api::DEFAULT_PORT);
+            mock_api::run(script, port);
+        }
         "temp" => temp(),
         _ => panic!("unknown <TYPE> option"),
     };
@@ -102,10 +135,17 @@ fn run_production_pipeline() {
         spawn_thread_with_name("rocket", || server::init(state_lock, cacher_state_lock))
     };
 
+    // archiver
+    let (sender_archiver, receiver_archiver) = mpsc::channel();
+    spawn_thread_with_name("archiver", move || archiver::archiver(receiver_archiver));
+
     // todo убедиться что capacity(channel) == infinity, чтобы fetcher не блокировался на время подготовки данных для updater
     // fetcher_get_games
     let (sender_fetcher_get_games, receiver_fetcher_get_games) = mpsc::channel();
-    spawn_thread_with_name("fetcher_get_games", move || fetcher_get_games::fetcher(sender_fetcher_get_games));
+    {
+        let sender_archiver = sender_archiver.clone();
+        spawn_thread_with_name("fetcher_get_games", move || fetcher_get_games::fetcher(sender_fetcher_get_games, Some(sender_archiver)));
+    }
 
     // state
     println!("[info]  [startup] starting fetching state");
@@ -122,7 +162,7 @@ fn run_production_pipeline() {
     {
         let state_lock = state_lock.clone();
         let fetcher_get_game_details_state_lock = fetcher_get_game_details_state_lock.clone();
-        spawn_thread_with_name("fetcher_get_game_details", move || fetcher_get_game_details::fetcher(receiver_fetcher_get_game_details, fetcher_get_game_details_state_lock, state_lock));
+        spawn_thread_with_name("fetcher_get_game_details", move || fetcher_get_game_details::fetcher(receiver_fetcher_get_game_details, fetcher_get_game_details_state_lock, state_lock, Some(sender_archiver)));
     }
 
     // updater
@@ -176,14 +216,14 @@ fn run_analytics() {
 
 fn debug_fetcher_get_games() {
     let (sender, _receiver) = mpsc::channel();
-    let fetcher_thread = spawn_thread_with_name("fetcher_get_games", move || fetcher_get_games::fetcher(sender));
+    let fetcher_thread = spawn_thread_with_name("fetcher_get_games", move || fetcher_get_games::fetcher(sender, None));
     fetcher_thread.join().unwrap()
 }
 
 fn debug_fetcher_get_game_details() {
     // fetcher_get_games
     let (sender_fetcher_get_games, receiver_fetcher_get_games) = mpsc::channel();
-    spawn_thread_with_name("fetcher_get_games", move || fetcher_get_games::fetcher(sender_fetcher_get_games));
+    spawn_thread_with_name("fetcher_get_games", move || fetcher_get_games::fetcher(sender_fetcher_get_games, None));
 
     // state
     let whole_state = external_storage::get_empty_state();
@@ -195,7 +235,7 @@ fn debug_fetcher_get_game_details() {
     let (sender_fetcher_get_game_details, receiver_fetcher_get_game_details) = mpsc::channel();
     let fetcher_get_game_details_thread = {
         let state_lock = state_lock.clone();
-        spawn_thread_with_name("fetcher_get_game_details", move || fetcher_get_game_details::fetcher(receiver_fetcher_get_game_details, fetcher_get_game_details_state_lock, state_lock))
+        spawn_thread_with_name("fetcher_get_game_details", move || fetcher_get_game_details::fetcher(receiver_fetcher_get_game_details, fetcher_get_game_details_state_lock, state_lock, None))
     };
 
     // updater
@@ -210,7 +250,7 @@ fn debug_fetcher_get_game_details() {
 fn debug_updater() {
     // fetcher_get_games
     let (sender_fetcher_get_games, receiver_fetcher_get_games) = mpsc::channel();
-    let fetcher_thread = spawn_thread_with_name("fetcher_get_games", move || fetcher_get_games::fetcher(sender_fetcher_get_games));
+    let fetcher_thread = spawn_thread_with_name("fetcher_get_games", move || fetcher_get_games::fetcher(sender_fetcher_get_games, None));
 
     // state
     let whole_state = external_storage::get_empty_state();
@@ -228,10 +268,52 @@ fn debug_updater() {
 
 fn create_state_from_saved_data(number_responses: u32) {
     assert!(number_responses <= 2880);
+    let source = ReplaySource::Cache { directory: PathBuf::from("temp/cache-get-games"), number_responses };
+    let filename = format!("temp/state-offline/{}/state.bin.xz", number_responses);
+    replay(source, None, None, Vec::new(), &filename);
+}
 
+fn run_replay(
+    directory: Option<&str>,
+    time_begin: Option<TimeMinutes>,
+    time_end: Option<TimeMinutes>,
+    download: bool,
+    overrides_path: Option<&str>,
+) {
+    let server_overrides = match overrides_path {
+        Some(overrides_path) => {
+            let server_overrides = fs::read_to_string(overrides_path).unwrap();
+            serde_json::from_str(&server_overrides).unwrap()
+        }
+        // исправления хранятся в State, поэтому переносим их из текущего состояния в построенное заново
+        None => external_storage::load_state_from_cloud().state.server_overrides,
+    };
+
+    let directory = PathBuf::from(directory.unwrap_or(archiver::LOCAL_ARCHIVE_DIRECTORY));
+    if download {
+        archiver::download_archive(
+            &directory,
+            time_begin.map_or(0, |time| time.get()),
+            time_end.map_or(u32::MAX, |time| time.get()),
+        );
+    }
+    let source = ReplaySource::Archive { directory };
+    GLOBAL_CONFIG.lock().unwrap().get_game_details_replay_cache_directory =
+        Some(PathBuf::from(fetcher_get_games_offline::GET_GAME_DETAILS_REPLAY_CACHE_DIRECTORY));
+    replay(source, time_begin, time_end, server_overrides, "temp/state-replay/state.bin.xz");
+}
+
+fn replay(
+    source: ReplaySource,
+    time_begin: Option<TimeMinutes>,
+    time_end: Option<TimeMinutes>,
+    server_overrides: Vec<ServerOverride>,
+    filename: &str,
+) {
     // fetcher_get_games
-    let (sender_fetcher_get_games, receiver_fetcher_get_games) = mpsc::channel();
-    fetcher_get_games_offline::fetcher(sender_fetcher_get_games, number_responses);
+    // канал ограничен, чтобы не держать в памяти все ответы, которые updater ещё не обработал
+    let (sender_fetcher_get_games, receiver_fetcher_get_games) = mpsc::sync_channel(REPLAY_CHANNEL_CAPACITY);
+    let fetcher_get_games_thread = spawn_thread_with_name("fetcher_get_games_offline", move || fetcher_get_games_offline::fetcher(sender_fetcher_get_games, &source, time_begin, time_end));
 
     // state
     let whole_state = external_storage::get_empty_state();
@@ -246,7 +328,7 @@ fn create_state_from_saved_data(number_responses: u32) {
         let fetcher_get_game_details_state_lock = fetcher_get_game_details_state_lock.clone();
         // fetcher_get_game_details обязательно должен быть в отдельном потоке и работать параллельно с updater
         // иначе updater будет бесконечно откладывать merge и любые перезапуски серверов не будут учтены
-        spawn_thread_with_name("fetcher_get_game_details", move || fetcher_get_game_details::fetcher(receiver_fetcher_get_game_details, fetcher_get_game_details_state_lock, state_lock))
+        spawn_thread_with_name("fetcher_get_game_details", move || fetcher_get_game_details::fetcher(receiver_fetcher_get_game_details, fetcher_get_game_details_state_lock, state_lock, None))
     };
 
     // updater
@@ -256,8 +338,11 @@ fn create_state_from_saved_data(number_responses: u32) {
         state::updater::updater(updater_state_lock, state_lock, receiver_fetcher_get_games, sender_fetcher_get_game_details);
     }
 
+    fetcher_get_games_thread.join().unwrap();
     fetcher_get_game_details_thread.join().unwrap();
 
+    state_lock.write().add_server_overrides(server_overrides);
+
     let updater_state = updater_state_lock.read();
     let state = state_lock.read();
 
@@ -266,15 +351,14 @@ fn create_state_from_saved_data(number_responses: u32) {
     assert_ne!(number_games_with_prev_game_id, 0);
 
     let fetcher_get_game_details_state = fetcher_get_game_details_state_lock.read();
-    let filename = format!("temp/state-offline/{}/state.bin.xz", number_responses);
-    fs::create_dir_all(Path::new(&filename).parent().unwrap()).unwrap();
-    external_storage::save_state_to_file((&updater_state, &state, &fetcher_get_game_details_state), &filename);
+    fs::create_dir_all(Path::new(filename).parent().unwrap()).unwrap();
+    external_storage::save_state_to_file((&updater_state, &state, &fetcher_get_game_details_state), filename);
 }
 
 fn create_state(number_responses: u32) {
     // fetcher_get_games
     let (sender_fetcher_get_games, receiver_fetcher_get_games) = mpsc::channel();
-    spawn_thread_with_name("fetcher_get_games", move || fetcher_get_games::fetcher(sender_fetcher_get_games));
+    spawn_thread_with_name("fetcher_get_games", move || fetcher_get_games::fetcher(sender_fetcher_get_games, None));
 
     // state
     let whole_state = external_storage::get_empty_state();
@@ -287,7 +371,7 @@ fn create_state(number_responses: u32) {
     {
         let state_lock = state_lock.clone();
         let fetcher_get_game_details_state_lock = fetcher_get_game_details_state_lock.clone();
-        spawn_thread_with_name("fetcher_get_game_details", move || fetcher_get_game_details::fetcher(receiver_fetcher_get_game_details, fetcher_get_game_details_state_lock, state_lock));
+        spawn_thread_with_name("fetcher_get_game_details", move || fetcher_get_game_details::fetcher(receiver_fetcher_get_game_details, fetcher_get_game_details_state_lock, state_lock, None));
     }
 
     // updater
@@ -0,0 +1,132 @@
+//! Stand-in for the matchmaking server (https://multiplayer.factorio.com)
+//! Serves scripted responses, so that pipelines can be run locally or in CI without Factorio credentials
+//!
+//! Script is a json file:
+//! ```json
+//! {
+//!     "get_games": [
+//!         { "file": "temp/cache-get-games/0000.json" },
+//!         { "status": 503, "body": "Service Unavailable" },
+//!         { "body": [] }
+//!     ],
+//!     "get_game_details": {
+//!         "123": [ { "status": 500 }, { "file": "temp/cache-get-game-details/123.json" } ]
+//!     }
+//! }
+//! ```
+//! Responses for each endpoint are served in order, after the end of the list the last response is repeated
+//! `/get-game-details/<game_id>` for game_id which is not in the script returns 404
+
+use std::fs;
+use std::sync::Mutex;
+
+use hashbrown::HashMap;
+use rocket::{get, routes, State};
+use rocket::config::{Config, Environment};
+use rocket::http::Status;
+use rocket::response::{content, status};
+use serde::Deserialize;
+
+pub const DEFAULT_PORT: u16 = 8001;
+
+#[derive(Deserialize)]
+struct MockResponse {
+    #[serde(default = "default_status")]
+    status: u16,
+    // string is returned as is, any other json value is serialized
+    body: Option<serde_json::Value>,
+    // path to file with response body (has priority over `body`)
+    file: Option<String>,
+}
+
+fn default_status() -> u16 { 200 }
+
+#[derive(Deserialize)]
+struct MockScript {
+    #[serde(default)]
+    get_games: Vec<MockResponse>,
+    // key is game_id
+    #[serde(default)]
+    get_game_details: HashMap<u32, Vec<MockResponse>>,
+}
+
+struct MockApiState {
+    script: MockScript,
+    // number of already served requests
+    number_get_games_requests: usize,
+    number_get_game_details_requests: HashMap<u32, usize>,
+}
+
+type MockApiStateLock = Mutex<MockApiState>;
+
+type MockApiResponse = status::Custom<content::Json<String>>;
+
+impl MockResponse {
+    fn respond(&self) -> MockApiResponse {
+        let body = match (&self.file, &self.body) {
+            (Some(file), _) => fs::read_to_string(file)
+                .unwrap_or_else(|err| panic!("Can't read mock response file `{}`: {}", file, err)),
+            (None, Some(serde_json::Value::String(body))) => body.clone(),
+            (None, Some(body)) => body.to_string(),
+            (None, None) => String::new(),
+        };
+        status::Custom(Status::raw(self.status), content::Json(body))
+    }
+}
+
+fn select_response(responses: &[MockResponse], request_index: usize) -> Option<&MockResponse> {
+    responses.get(request_index).or_else(|| responses.last())
+}
+
+fn respond_not_found() -> MockApiResponse {
+    status::Custom(Status::NotFound, content::Json("{\"message\": \"Not found\"}".to_owned()))
+}
+
+#[get("/get-games")]
+fn get_games(state: State<MockApiStateLock>) -> MockApiResponse {
+    let mut state = state.lock().unwrap();
+    let request_index = state.number_get_games_requests;
+    state.number_get_games_requests += 1;
+    println!("[info]  [mock_api] /get-games request #{}", request_index);
+
+    match select_response(&state.script.get_games, request_index) {
+        Some(response) => response.respond(),
+        None => respond_not_found(),
+    }
+}
+
+#[get("/get-game-details/<game_id>")]
+fn get_game_details(game_id: u32, state: State<MockApiStateLock>) -> MockApiResponse {
+    let mut state = state.lock().unwrap();
+    let number_requests = state.number_get_game_details_requests.entry(game_id).or_insert(0);
+    let request_index = *number_requests;
+    *number_requests += 1;
+    println!("[info]  [mock_api] /get-game-details/{} request #{}", game_id, request_index);
+
+    let responses = state.script.get_game_details.get(&game_id);
+    match responses.and_then(|responses| select_response(responses, request_index)) {
+        Some(response) => response.respond(),
+        None => respond_not_found(),
+    }
+}
+
+pub fn run(script_path: &str, port: u16) {
+    let script = fs::read_to_string(script_path)
+        .unwrap_or_else(|err| panic!("Can't read mock api script `{}`: {}", script_path, err));
+    let script: MockScript = serde_json::from_str(&script)
+        .unwrap_or_else(|err| panic!("Can't parse mock api script `{}`: {}", script_path, err));
+    let state = MockApiState {
+        script,
+        number_get_games_requests: 0,
+        number_get_game_details_requests: HashMap::new(),
+    };
+
+    let config = Config::build(Environment::Development)
+        .port(port)
+        .finalize()
+        .unwrap();
+    rocket::custom(config)
+        .manage(Mutex::new(state))
+        .mount("/", routes![get_games, get_game_details])
+        .launch();
+}
@@ -0,0 +1,179 @@
+//! Популярность модов: число серверов, на которых мод установлен, человеко-часы на таких серверах
+//! и распространённость версий мода по неделям
+//! Перестраивается cacher'ом (см. `cacher::INTERVAL`)
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::state::{Game, ServerId, State, TimeMinutes};
+
+const TOP_SERVERS_SIZE: usize = 20;
+const DAY: u32 = 24 * 60;
+// 1 января 1970 — четверг, сдвигаем чтобы недели начинались с понедельника
+const WEEK_OFFSET: u32 = 3 * DAY;
+// есть почти на всех серверах, не интересен
+const BASE_MOD_NAME: &str = "base";
+
+pub struct ModStatistics {
+    pub name: String,
+    // серверы, на которых мод установлен сейчас
+    pub number_servers_now: usize,
+    // серверы, на которых мод был установлен когда-либо
+    pub number_servers_all: usize,
+    // суммарное время игроков в играх с этим модом, в минутах
+    pub player_minutes: u64,
+    // (начало недели, [(версия, число серверов)]), упорядочено по времени
+    pub versions_by_week: Vec<(TimeMinutes, Vec<(String, usize)>)>,
+    // (сервер, player_minutes в играх с этим модом), по убыванию player_minutes
+    pub top_servers: Vec<(ServerId, u64)>,
+}
+
+#[derive(Default)]
+struct ModStatisticsBuilder {
+    servers_now: HashSet<ServerId>,
+    servers_all: HashSet<ServerId>,
+    player_minutes: u64,
+    // номер недели → версия → серверы
+    versions_by_week: HashMap<u32, HashMap<String, HashSet<ServerId>>>,
+    player_minutes_by_server: HashMap<ServerId, u64>,
+}
+
+impl ModStatisticsBuilder {
+    fn build(self, name: &str) -> ModStatistics {
+        let mut versions_by_week: Vec<(TimeMinutes, Vec<(String, usize)>)> = self.versions_by_week.into_iter()
+            .map(|(week, versions)| {
+                let week_begin = TimeMinutes::new((week * TimeMinutes::WEEK).saturating_sub(WEEK_OFFSET).max(1)).unwrap();
+                let mut versions: Vec<(String, usize)> = versions.into_iter()
+                    .map(|(version, server_ids)| (version, server_ids.len()))
+                    .collect();
+                versions.sort();
+                (week_begin, versions)
+            })
+            .collect();
+        versions_by_week.sort_by_key(|(week_begin, _)| *week_begin);
+
+        let mut top_servers: Vec<(ServerId, u64)> = self.player_minutes_by_server.into_iter().collect();
+        top_servers.sort_by_key(|(_, player_minutes)| std::cmp::Reverse(*player_minutes));
+        top_servers.truncate(TOP_SERVERS_SIZE);
+
+        ModStatistics {
+            name: name.to_owned(),
+            number_servers_now: self.servers_now.len(),
+            number_servers_all: self.servers_all.len(),
+            player_minutes: self.player_minutes,
+            versions_by_week,
+            top_servers,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ModsStatistics {
+    pub mods: HashMap<String, ModStatistics>,
+    // названия модов по убыванию number_servers_now
+    pub by_number_servers_now: Vec<String>,
+    // названия модов по убыванию player_minutes
+    pub by_player_minutes: Vec<String>,
+}
+
+fn get_week(time: u32) -> u32 {
+    (time + WEEK_OFFSET) / TimeMinutes::WEEK
+}
+
+// как `HashMap::entry(key.to_owned())`, но строка копируется только для нового ключа
+fn get_or_insert<'a, V: Default>(map: &'a mut HashMap<String, V>, key: &str) -> &'a mut V {
+    if !map.contains_key(key) {
+        map.insert(key.to_owned(), V::default());
+    }
+    map.get_mut(key).unwrap()
+}
+
+/// статистика строится по одной игре, чтобы cacher мог отпускать блокировку State между частями игр
+pub struct ModsStatisticsBuilder {
+    builders: HashMap<String, ModStatisticsBuilder>,
+    // незавершённые игры считаем продолжающимися до минуты, следующей за последним наблюдением
+    end: TimeMinutes,
+}
+
+impl ModsStatisticsBuilder {
+    pub fn new(state: &State) -> Self {
+        let end = state.last_observed_time.unwrap_or_else(TimeMinutes::now).get() + 1;
+        ModsStatisticsBuilder { builders: HashMap::new(), end: TimeMinutes::new(end).unwrap() }
+    }
+
+    pub fn add_game(&mut self, state: &State, game: &Game) {
+        let server_id = match game.server_id {
+            Some(server_id) => server_id,
+            None => return,
+        };
+        let mods = match game.get_mods(state) {
+            Some(mods) => mods,
+            None => return,
+        };
+        let is_online = game.time_end.is_none();
+        let player_minutes = game.player_minutes_in_interval(game.time_begin, self.end);
+        let game_end = game.time_end.unwrap_or(self.end);
+        let weeks = get_week(game.time_begin.get())..=get_week(game_end.get() - 1);
+
+        for mod_ in mods {
+            let name: &str = state.all_mod_names.get(mod_.name).into();
+            if name == BASE_MOD_NAME {
+                continue;
+            }
+            let version: &str = state.all_versions.get(mod_.version).into();
+
+            let builder = get_or_insert(&mut self.builders, name);
+            builder.servers_all.insert(server_id);
+            if is_online {
+                builder.servers_now.insert(server_id);
+            }
+            builder.player_minutes += player_minutes;
+            *builder.player_minutes_by_server.entry(server_id).or_insert(0) += player_minutes;
+            for week in weeks.clone() {
+                let versions = builder.versions_by_week.entry(week).or_default();
+                get_or_insert(versions, version).insert(server_id);
+            }
+        }
+    }
+
+    pub fn build(self) -> ModsStatistics {
+        let mods: HashMap<String, ModStatistics> = self.builders.into_iter()
+            .map(|(name, builder)| {
+                let statistics = builder.build(&name);
+                (name, statistics)
+            })
+            .collect();
+        let get_sorted_names = |get_key: &dyn Fn(&ModStatistics) -> u64| {
+            let mut names: Vec<&ModStatistics> = mods.values().collect();
+            names.sort_by(|mod1, mod2| get_key(mod2).cmp(&get_key(mod1)).then_with(|| mod1.name.cmp(&mod2.name)));
+            names.into_iter().map(|mod_| mod_.name.clone()).collect::<Vec<_>>()
+        };
+        let by_number_servers_now = get_sorted_names(&|mod_| mod_.number_servers_now as u64);
+        let by_player_minutes = get_sorted_names(&|mod_| mod_.player_minutes);
+        println!("[info]  [mods_statistics] built statistics for {} mods", mods.len());
+        ModsStatistics { mods, by_number_servers_now, by_player_minutes }
+    }
+}
+
+impl ModsStatistics {
+    pub fn build(state: &State) -> Self {
+        let mut builder = ModsStatisticsBuilder::new(state);
+        for game in state.games.values() {
+            builder.add_game(state, game);
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weeks_begin_on_monday() {
+        // Monday, 6 January 2020, 00:00 UTC
+        let monday = 26_304_480;
+        assert_eq!(get_week(monday), get_week(monday + TimeMinutes::WEEK - 1));
+        assert_eq!(get_week(monday - 1) + 1, get_week(monday));
+        assert_eq!(get_week(monday) * TimeMinutes::WEEK - WEEK_OFFSET, monday);
+    }
+}
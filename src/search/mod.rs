@@ -0,0 +1,315 @@
+//! Поиск серверов по запросу вида `factorio player:foo mod:space-exploration tag:pvp version:1.0 host:1.2.3.4 online:true`
+//! Фильтры по модам, тегам, версиям и адресам используют инвертированные индексы ([SearchIndex]),
+//! которые перестраиваются cacher'ом, поэтому новые серверы находятся не сразу (с задержкой до `cacher::INTERVAL`)
+//! Фильтр по игроку использует `State::players_index`
+//! Остальная часть запроса нечётко ищется в текущих и прошлых названиях серверов (см. [name_index])
+//! Результаты упорядочены по качеству совпадения названия, давности последней игры и популярности (часы игроков)
+
+use std::collections::BTreeMap;
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::search::name_index::NameIndex;
+use crate::state::{Game, ServerId, State, TimeMinutes};
+use crate::state::metadata::MetadataValue;
+
+pub mod name_index;
+
+// веса слагаемых при ранжировании
+const NAME_MATCH_WEIGHT: f32 = 0.7;
+const RECENCY_WEIGHT: f32 = 0.15;
+const POPULARITY_WEIGHT: f32 = 0.15;
+// через сколько дней после завершения последней игры вклад давности уменьшается вдвое
+const RECENCY_HALF_LIFE_DAYS: f32 = 30.0;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Filter {
+    // имя игрока с учётом регистра
+    Player(String),
+    Mod(String),
+    Tag(String),
+    // префикс версии, например "1.0" соответствует "1.0.0"
+    Version(String),
+    // префикс адреса, например "1.2.3.4" соответствует "1.2.3.4:34197"
+    Host(String),
+    Online(bool),
+}
+
+#[derive(Eq, PartialEq, Debug)]
+pub struct Query {
+    pub filters: Vec<Filter>,
+    // в нижнем регистре, пустая строка если в запросе только фильтры
+    pub text: String,
+}
+
+/// разбивает запрос по пробелам, учитывая кавычки: `player:"name with spaces"`
+fn split_query(query: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut is_in_quotes = false;
+    for c in query.chars() {
+        match c {
+            '"' => is_in_quotes = !is_in_quotes,
+            c if c.is_whitespace() && !is_in_quotes => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+pub fn parse_query(query: &str) -> Result<Query, String> {
+    let mut filters = Vec::new();
+    let mut text_words = Vec::new();
+    for word in split_query(query) {
+        let (key, value) = match word.find(':') {
+            Some(index) => (&word[..index], word[index + 1..].to_lowercase()),
+            None => ("", String::new()),
+        };
+        let filter = match key {
+            "player" => Filter::Player(word[key.len() + 1..].to_owned()),
+            "mod" => Filter::Mod(value),
+            "tag" => Filter::Tag(value),
+            "version" => Filter::Version(value),
+            "host" => Filter::Host(value),
+            "online" => match value.as_str() {
+                "true" | "yes" => Filter::Online(true),
+                "false" | "no" => Filter::Online(false),
+                _ => return Err(format!("invalid value of `online` filter: `{}`", value)),
+            },
+            // двоеточие может встречаться в названии сервера
+            _ => {
+                text_words.push(word.to_lowercase());
+                continue;
+            }
+        };
+        if value.is_empty() {
+            return Err(format!("empty value of `{}` filter", key));
+        }
+        filters.push(filter);
+    }
+    Ok(Query { filters, text: text_words.join(" ") })
+}
+
+type Postings = Vec<ServerId>;
+
+/// инвертированные индексы: значение (в нижнем регистре) → серверы, в играх которых встречалось это значение
+#[derive(Default)]
+pub struct SearchIndex {
+    names: NameIndex<ServerId>,
+    // суммарное время всех игроков на сервере, в минутах
+    server_player_minutes: HashMap<ServerId, u64>,
+    max_player_minutes: u64,
+    mods: HashMap<String, Postings>,
+    tags: HashMap<String, Postings>,
+    // BTreeMap для поиска по префиксу
+    versions: BTreeMap<String, Postings>,
+    hosts: BTreeMap<String, Postings>,
+}
+
+fn into_postings(map: HashMap<String, HashSet<ServerId>>) -> impl Iterator<Item=(String, Postings)> {
+    map.into_iter().map(|(key, server_ids)| (key, server_ids.into_iter().collect()))
+}
+
+fn find_by_prefix(map: &BTreeMap<String, Postings>, prefix: &str) -> HashSet<ServerId> {
+    map.range(prefix.to_owned()..)
+        .take_while(|(key, _)| key.starts_with(prefix))
+        .flat_map(|(_, server_ids)| server_ids.iter().copied())
+        .collect()
+}
+
+fn find_exact(map: &HashMap<String, Postings>, key: &str) -> HashSet<ServerId> {
+    map.get(key).map_or_else(HashSet::new, |server_ids| server_ids.iter().copied().collect())
+}
+
+fn add_to_index(map: &mut HashMap<String, HashSet<ServerId>>, key: &str, server_id: ServerId) {
+    map.entry(key.to_lowercase()).or_default().insert(server_id);
+}
+
+/// индекс строится по одной игре, чтобы cacher мог отпускать блокировку State между частями игр
+pub struct SearchIndexBuilder {
+    mods: HashMap<String, HashSet<ServerId>>,
+    tags: HashMap<String, HashSet<ServerId>>,
+    versions: HashMap<String, HashSet<ServerId>>,
+    hosts: HashMap<String, HashSet<ServerId>>,
+    names: Vec<(ServerId, String, bool)>,
+    server_player_minutes: HashMap<ServerId, u64>,
+    observed_end: u32,
+}
+
+impl SearchIndexBuilder {
+    pub fn new(state: &State) -> Self {
+        SearchIndexBuilder {
+            mods: HashMap::new(),
+            tags: HashMap::new(),
+            versions: HashMap::new(),
+            hosts: HashMap::new(),
+            names: Vec::new(),
+            server_player_minutes: HashMap::new(),
+            observed_end: state.last_observed_time.map_or(0, |time| time.get() + 1),
+        }
+    }
+
+    pub fn add_game(&mut self, state: &State, game: &Game) {
+        let server_id = match game.server_id {
+            Some(server_id) => server_id,
+            // игра ещё не объединена в сервер
+            None => return,
+        };
+
+        let is_current_game = game.next_game_id.is_none();
+        self.names.push((server_id, game.get_name(state).to_owned(), is_current_game));
+        for change in state.get_game_metadata_changes(game.game_id) {
+            if let MetadataValue::Name(name) = change.old_value {
+                self.names.push((server_id, state.all_game_names.get(name).into(), false));
+            }
+        }
+
+        let player_minutes = self.server_player_minutes.entry(server_id).or_insert(0);
+        for interval in &game.players_intervals {
+            let interval_end = interval.end.map_or(self.observed_end, TimeMinutes::get);
+            *player_minutes += interval_end.saturating_sub(interval.begin.get()) as u64;
+        }
+        if let Some(game_mods) = game.get_mods(state) {
+            for mod_ in game_mods {
+                add_to_index(&mut self.mods, state.all_mod_names.get(mod_.name).into(), server_id);
+            }
+        }
+        let game_tags: &str = state.all_tags.get(game.tags).into();
+        for tag in game_tags.split('\x02').filter(|tag| !tag.is_empty()) {
+            add_to_index(&mut self.tags, tag, server_id);
+        }
+        add_to_index(&mut self.versions, state.all_versions.get(game.game_version).into(), server_id);
+        if let Some(host_address) = game.host_address {
+            add_to_index(&mut self.hosts, state.all_host_addresses.get(host_address).into(), server_id);
+        }
+    }
+
+    pub fn build(self) -> SearchIndex {
+        let index = SearchIndex {
+            names: NameIndex::new(self.names),
+            max_player_minutes: self.server_player_minutes.values().copied().max().unwrap_or(0),
+            server_player_minutes: self.server_player_minutes,
+            mods: into_postings(self.mods).collect(),
+            tags: into_postings(self.tags).collect(),
+            versions: into_postings(self.versions).collect(),
+            hosts: into_postings(self.hosts).collect(),
+        };
+        println!("[info]  [search] index is built: {} servers, {} names, {} mods, {} hosts",
+                 index.server_player_minutes.len(), index.names.number_names(), index.mods.len(), index.hosts.len());
+        index
+    }
+}
+
+fn find_by_player(state: &State, player_name: &str) -> HashSet<ServerId> {
+    state.get_player_sessions(player_name).iter()
+        .filter_map(|session| state.get_game(session.game_id).server_id)
+        .collect()
+}
+
+impl SearchIndex {
+    pub fn build(state: &State) -> Self {
+        let mut builder = SearchIndexBuilder::new(state);
+        for game in state.games.values() {
+            builder.add_game(state, game);
+        }
+        builder.build()
+    }
+
+    /// серверы, удовлетворяющие всем фильтрам (кроме `Filter::Online`, который зависит от текущего State)
+    /// None если в запросе нет таких фильтров
+    fn find_by_filters(&self, filters: &[Filter], state: &State) -> Option<HashSet<ServerId>> {
+        let mut result: Option<HashSet<ServerId>> = None;
+        for filter in filters {
+            let server_ids = match filter {
+                Filter::Player(name) => find_by_player(state, name),
+                Filter::Mod(name) => find_exact(&self.mods, name),
+                Filter::Tag(tag) => find_exact(&self.tags, tag),
+                Filter::Version(version) => find_by_prefix(&self.versions, version),
+                Filter::Host(host) => find_by_prefix(&self.hosts, host),
+                Filter::Online(_) => continue,
+            };
+            result = Some(match result {
+                Some(result) => result.intersection(&server_ids).copied().collect(),
+                None => server_ids,
+            });
+        }
+        result
+    }
+
+    /// от 0 до 1, логарифмическая шкала относительно самого популярного сервера
+    fn get_popularity(&self, server_id: ServerId) -> f32 {
+        let player_minutes = self.server_player_minutes.get(&server_id).copied().unwrap_or(0);
+        if self.max_player_minutes == 0 {
+            return 0.0;
+        }
+        ((1 + player_minutes) as f32).ln() / ((1 + self.max_player_minutes) as f32).ln()
+    }
+
+    /// серверы, удовлетворяющие запросу, в порядке убывания релевантности
+    pub fn search(&self, query: &Query, state: &State) -> Vec<ServerId> {
+        let filtered_server_ids = self.find_by_filters(&query.filters, state);
+        let online_filter = query.filters.iter()
+            .filter_map(|filter| match filter {
+                Filter::Online(online) => Some(*online),
+                _ => None,
+            })
+            .last();
+
+        // сервер → качество совпадения названия
+        let candidates: Vec<(ServerId, f32)> = if query.text.is_empty() {
+            self.server_player_minutes.keys().map(|&server_id| (server_id, 0.0)).collect()
+        } else {
+            self.names.find(&query.text).into_iter().collect()
+        };
+
+        let now = state.last_observed_time.map_or(0, TimeMinutes::get);
+        let mut results: Vec<(ServerId, f32)> = candidates.into_iter()
+            .filter(|(server_id, _)| filtered_server_ids.as_ref().map_or(true, |server_ids| server_ids.contains(server_id)))
+            .filter_map(|(server_id, name_match)| {
+                let last_game = state.get_game(state.get_server_last_game_id(server_id));
+                if online_filter.map_or(false, |online| last_game.time_end.is_none() != online) {
+                    return None;
+                }
+                let recency = match last_game.time_end {
+                    None => 1.0,
+                    Some(time_end) => {
+                        let days = now.saturating_sub(time_end.get()) as f32 / (24 * 60) as f32;
+                        0.5f32.powf(days / RECENCY_HALF_LIFE_DAYS)
+                    }
+                };
+                let score = NAME_MATCH_WEIGHT * name_match
+                    + RECENCY_WEIGHT * recency
+                    + POPULARITY_WEIGHT * self.get_popularity(server_id);
+                Some((server_id, score))
+            })
+            .collect();
+        results.sort_by(|(_, score1), (_, score2)| score2.partial_cmp(score1).unwrap());
+        results.into_iter().map(|(server_id, _)| server_id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query() {
+        let query = parse_query(r#"  Space  player:"Some Player" mod:space-exploration online:true a:b "#).unwrap();
+        assert_eq!(query.filters, vec![
+            Filter::Player("Some Player".to_owned()),
+            Filter::Mod("space-exploration".to_owned()),
+            Filter::Online(true),
+        ]);
+        assert_eq!(query.text, "space a:b");
+
+        assert!(parse_query("online:maybe").is_err());
+        assert!(parse_query("tag:").is_err());
+    }
+}
@@ -0,0 +1,169 @@
+//! Нечёткий поиск по названиям серверов (текущим и прошлым) с помощью индекса триграмм
+//! Запрос находит название, если содержит его как подстроку, или если большая часть триграмм запроса встречается в названии
+//! (это допускает опечатки, пропущенные и переставленные буквы)
+
+use std::hash::Hash;
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::util::strip_rich_text;
+
+type Trigram = [char; 3];
+
+// доля триграмм запроса, которые должны встретиться в названии
+const MIN_TRIGRAMS_COVERAGE: f32 = 0.5;
+// совпадение с прошлым названием сервера немного хуже совпадения с текущим
+const HISTORICAL_NAME_PENALTY: f32 = 0.9;
+
+/// удаляет rich text, приводит к нижнему регистру, заменяет знаки препинания на пробелы
+pub fn normalize_name(name: &str) -> String {
+    strip_rich_text(name)
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// триграммы нормализованной строки, дополненной пробелами (чтобы учитывать начала и концы слов)
+fn get_trigrams(name: &str) -> HashSet<Trigram> {
+    let chars: Vec<char> = std::iter::once(' ')
+        .chain(name.chars())
+        .chain(std::iter::once(' '))
+        .collect();
+    chars.windows(3)
+        .map(|window| [window[0], window[1], window[2]])
+        .collect()
+}
+
+struct IndexedName<T> {
+    id: T,
+    name: String,
+    is_current: bool,
+}
+
+/// `T` — идентификатор сервера
+pub struct NameIndex<T> {
+    names: Vec<IndexedName<T>>,
+    // триграмма → индексы в `names`
+    trigrams: HashMap<Trigram, Vec<u32>>,
+}
+
+impl<T> Default for NameIndex<T> {
+    fn default() -> Self {
+        NameIndex { names: Vec::new(), trigrams: HashMap::new() }
+    }
+}
+
+impl<T: Copy + Eq + Hash> NameIndex<T> {
+    /// `names` — тройки (сервер, название, является ли название текущим)
+    pub fn new(names: impl IntoIterator<Item=(T, String, bool)>) -> Self {
+        let mut unique_names = HashMap::new();
+        for (id, name, is_current) in names {
+            let name = normalize_name(&name);
+            if name.is_empty() {
+                continue;
+            }
+            *unique_names.entry((id, name)).or_insert(false) |= is_current;
+        }
+
+        let mut index = NameIndex::default();
+        for ((id, name), is_current) in unique_names {
+            let name_index = index.names.len() as u32;
+            for trigram in get_trigrams(&name) {
+                index.trigrams.entry(trigram).or_default().push(name_index);
+            }
+            index.names.push(IndexedName { id, name, is_current });
+        }
+        index
+    }
+
+    pub fn number_names(&self) -> usize {
+        self.names.len()
+    }
+
+    /// качество совпадения (от 0 до 1] для каждого найденного сервера
+    /// 1 — название совпадает с запросом, 0.9 — содержит запрос, не больше 0.8 — нечёткое совпадение
+    pub fn find(&self, query: &str) -> HashMap<T, f32> {
+        let query = normalize_name(query);
+        let mut result = HashMap::new();
+        if query.is_empty() {
+            return result;
+        }
+        let mut add_match = |name: &IndexedName<T>, quality: f32| {
+            let quality = if name.is_current { quality } else { quality * HISTORICAL_NAME_PENALTY };
+            let best_quality = result.entry(name.id).or_insert(0.0);
+            if *best_quality < quality {
+                *best_quality = quality;
+            }
+        };
+        let get_substring_quality = |name: &str| {
+            if name == query {
+                Some(1.0)
+            } else if name.contains(&query) {
+                Some(0.9)
+            } else {
+                None
+            }
+        };
+
+        // у коротких запросов слишком мало триграмм, ищем только подстроку
+        if query.chars().count() < 3 {
+            for name in &self.names {
+                if let Some(quality) = get_substring_quality(&name.name) {
+                    add_match(name, quality);
+                }
+            }
+            return result;
+        }
+
+        let query_trigrams = get_trigrams(&query);
+        let mut number_common_trigrams: HashMap<u32, u32> = HashMap::new();
+        for trigram in &query_trigrams {
+            if let Some(name_indexes) = self.trigrams.get(trigram) {
+                for &name_index in name_indexes {
+                    *number_common_trigrams.entry(name_index).or_insert(0) += 1;
+                }
+            }
+        }
+        for (name_index, number_common) in number_common_trigrams {
+            let name = &self.names[name_index as usize];
+            let coverage = number_common as f32 / query_trigrams.len() as f32;
+            let quality = match get_substring_quality(&name.name) {
+                Some(quality) => quality,
+                None if coverage >= MIN_TRIGRAMS_COVERAGE => 0.8 * coverage,
+                None => continue,
+            };
+            add_match(name, quality);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_search() {
+        let index = NameIndex::new(vec![
+            (1, "[color=red]Space Exploration[/color] | 24/7".to_owned(), true),
+            (2, "Vanilla".to_owned(), true),
+            (2, "Space Age".to_owned(), false),
+            (3, "Krastorio 2".to_owned(), true),
+        ]);
+        assert_eq!(normalize_name("[color=red]Space Exploration[/color] | 24/7"), "space exploration 24 7");
+
+        let result = index.find("spce explortion");
+        assert_eq!(result.keys().collect::<Vec<_>>(), vec![&1]);
+        assert!(result[&1] < 0.8);
+
+        let result = index.find("space");
+        assert_eq!(result[&1], 0.9);
+        assert_eq!(result[&2], 0.9 * HISTORICAL_NAME_PENALTY);
+
+        assert_eq!(index.find("VANILLA")[&2], 1.0);
+        assert_eq!(index.find("24").keys().collect::<Vec<_>>(), vec![&1]);
+        assert!(index.find("factorissimo").is_empty());
+    }
+}
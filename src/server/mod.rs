@@ -10,8 +10,22 @@ pub fn init(state_lock: StateLock, cacher_state_lock: CacherStateLock) {
     let routes = routes![
         routes::index,
         routes::get_server_info::get_server_info,
+        routes::get_server_history::get_server_history,
+        routes::get_server_online::get_server_online,
+        routes::get_server_chart::get_server_chart,
+        routes::get_server_badge::get_server_badge,
+        routes::get_server_heatmap::get_server_heatmap,
+        routes::get_player::get_player,
+        routes::get_global_online::get_global_online,
+        routes::get_mods::get_mods,
+        routes::get_mods::get_mod,
         routes::main_page::main_page,
         routes::main_page::search,
+        routes::admin::api_errors,
+        routes::admin::unknown_fields,
+        routes::admin::server_overrides,
+        routes::admin::merge_servers,
+        routes::admin::split_server,
     ];
     rocket::ignite()
         .attach(cors::CORS())
@@ -0,0 +1,93 @@
+use std::env;
+
+use rocket::{get, post, Request, State};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest};
+use rocket::response::status;
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use fss::api;
+use fss::api::unknown_fields::UnknownField;
+use fss::state::{GameId, StateLock};
+use fss::state::overrides::ServerOverride;
+
+/// request guard for `/admin/*` routes
+/// request must have `X-Admin-Token` header equal to `ADMIN_TOKEN` env variable
+pub struct AdminToken;
+
+impl<'a, 'r> FromRequest<'a, 'r> for AdminToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let admin_token = match env::var("ADMIN_TOKEN") {
+            Ok(admin_token) if !admin_token.is_empty() => admin_token,
+            // admin routes are disabled
+            _ => return Outcome::Failure((Status::Forbidden, ())),
+        };
+        match request.headers().get_one("X-Admin-Token") {
+            Some(token) if token == admin_token => Outcome::Success(AdminToken),
+            _ => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ApiErrorCount {
+    kind: &'static str,
+    count: u64,
+}
+
+/// number of failed requests to matchmaking api since process start, grouped by `ApiError::kind`
+#[get("/admin/api-errors")]
+pub fn api_errors(_admin_token: AdminToken) -> Json<Vec<ApiErrorCount>> {
+    let error_counts = api::get_error_counts().into_iter()
+        .map(|(kind, count)| ApiErrorCount { kind, count })
+        .collect();
+    Json(error_counts)
+}
+
+/// fields of api responses which are not described in `api::Game` (each with a sample value)
+#[get("/admin/unknown-fields")]
+pub fn unknown_fields(_admin_token: AdminToken) -> Json<Vec<UnknownField>> {
+    Json(api::unknown_fields::get_unknown_fields())
+}
+
+/// manual merges/splits of servers, in format which is accepted by `replay --overrides`
+#[get("/admin/server-overrides")]
+pub fn server_overrides(_admin_token: AdminToken, state_lock: State<StateLock>) -> Json<Vec<ServerOverride>> {
+    Json(state_lock.read().server_overrides.clone())
+}
+
+fn add_server_override(state_lock: &StateLock, server_override: ServerOverride) -> Result<(), status::BadRequest<String>> {
+    state_lock.write().add_server_override(server_override)
+        .map_err(|err| status::BadRequest(Some(err)))
+}
+
+fn get_server_last_game_id(state_lock: &StateLock, server_id: usize) -> Result<GameId, status::BadRequest<String>> {
+    let state = state_lock.read();
+    let server_id = state.as_server_id(server_id)
+        .ok_or_else(|| status::BadRequest(Some(format!("unknown server {}", server_id))))?;
+    Ok(state.get_server_last_game_id(server_id))
+}
+
+#[post("/admin/merge-servers?<server_id_a>&<server_id_b>")]
+pub fn merge_servers(
+    _admin_token: AdminToken,
+    server_id_a: usize,
+    server_id_b: usize,
+    state_lock: State<StateLock>,
+) -> Result<(), status::BadRequest<String>> {
+    let game_id_a = get_server_last_game_id(&state_lock, server_id_a)?;
+    let game_id_b = get_server_last_game_id(&state_lock, server_id_b)?;
+    add_server_override(&state_lock, ServerOverride::Merge { game_id_a, game_id_b })
+}
+
+/// `game_id` becomes the first game of new server
+#[post("/admin/split-server?<game_id>")]
+pub fn split_server(_admin_token: AdminToken, game_id: u32, state_lock: State<StateLock>) -> Result<(), status::BadRequest<String>> {
+    let game_id = GameId::new(game_id)
+        .ok_or_else(|| status::BadRequest(Some("game_id must be positive".to_owned())))?;
+    add_server_override(&state_lock, ServerOverride::Split { game_id })
+}
@@ -0,0 +1,78 @@
+use rocket::get;
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use fss::state::{StateLock, TimeMinutes};
+use fss::state::global_online::GlobalOnlinePoint;
+use fss::state::online;
+
+use crate::server::routes::get_server_online::get_time_interval;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GlobalOnlinePointInfo {
+    pub time: TimeMinutes,
+    pub number_games: f32,
+    pub number_players: f32,
+    pub number_games_with_password: f32,
+    pub number_players_with_password: f32,
+    // indexes correspond to `GlobalOnlineSeries::major_versions`
+    pub number_games_by_version: Vec<f32>,
+    pub number_players_by_version: Vec<f32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GlobalOnlineSeries {
+    // in minutes
+    pub resolution: u32,
+    pub major_versions: Vec<String>,
+    // averages for minutes [time, time + resolution)
+    // points without observations are omitted
+    pub points: Vec<GlobalOnlinePointInfo>,
+}
+
+fn convert_point(point: GlobalOnlinePoint, number_versions: usize) -> GlobalOnlinePointInfo {
+    let mut number_games_by_version = point.number_games_by_version;
+    let mut number_players_by_version = point.number_players_by_version;
+    number_games_by_version.resize(number_versions, 0.0);
+    number_players_by_version.resize(number_versions, 0.0);
+    GlobalOnlinePointInfo {
+        time: point.time,
+        number_games: point.number_games,
+        number_players: point.number_players,
+        number_games_with_password: point.number_games_with_password,
+        number_players_with_password: point.number_players_with_password,
+        number_games_by_version,
+        number_players_by_version,
+    }
+}
+
+/// число публичных игр и игроков онлайн по всем серверам
+/// (данные старше недели хранятся с точностью до часа)
+#[get("/global/online?<time_begin>&<time_end>&<resolution>")]
+pub fn get_global_online(
+    time_begin: Option<u32>,
+    time_end: Option<u32>,
+    resolution: Option<u32>,
+    state_lock: rocket::State<StateLock>,
+) -> Option<Json<GlobalOnlineSeries>> {
+    let state = state_lock.read();
+
+    let (time_begin, time_end) = get_time_interval(time_begin, time_end, state.last_observed_time)?;
+    let min_resolution = online::get_resolution(time_begin, time_end, online::MAX_NUMBER_POINTS);
+    let resolution = resolution
+        .unwrap_or_else(|| online::get_resolution(time_begin, time_end, online::DEFAULT_NUMBER_POINTS))
+        .max(min_resolution);
+
+    let global_online = &state.global_online;
+    let number_versions = global_online.major_versions.len();
+    let points = global_online.get_series(time_begin, time_end, resolution).into_iter()
+        .map(|point| convert_point(point, number_versions))
+        .collect();
+    Some(Json(GlobalOnlineSeries {
+        resolution,
+        major_versions: global_online.major_versions.clone(),
+        points,
+    }))
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use rocket::{get, State};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use fss::cacher::CacherStateLock;
+use fss::mods_statistics::{ModsStatistics, ModStatistics};
+use fss::state::{ServerId, StateLock, TimeMinutes};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModInfo {
+    pub name: String,
+    pub number_servers_now: usize,
+    pub number_servers_all: usize,
+    pub player_minutes: u64,
+}
+
+impl From<&ModStatistics> for ModInfo {
+    fn from(mod_: &ModStatistics) -> Self {
+        ModInfo {
+            name: mod_.name.clone(),
+            number_servers_now: mod_.number_servers_now,
+            number_servers_all: mod_.number_servers_all,
+            player_minutes: mod_.player_minutes,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModsInfo {
+    pub total: usize,
+    pub mods: Vec<ModInfo>,
+}
+
+fn get_mods_statistics(cacher_state_lock: &CacherStateLock) -> Arc<ModsStatistics> {
+    cacher_state_lock.read().mods_statistics.clone()
+}
+
+/// `sort` — "servers" (число серверов, на которых мод установлен сейчас) или "playerHours"
+#[get("/mods?<sort>&<limit>&<offset>")]
+pub fn get_mods(
+    sort: Option<String>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    cacher_state_lock: State<CacherStateLock>,
+) -> Option<Json<ModsInfo>> {
+    let mods_statistics = get_mods_statistics(&cacher_state_lock);
+    let names = match sort.as_deref().unwrap_or("servers") {
+        "servers" => &mods_statistics.by_number_servers_now,
+        "playerHours" => &mods_statistics.by_player_minutes,
+        _ => return None,
+    };
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let mods = names.iter()
+        .skip(offset.unwrap_or(0))
+        .take(limit)
+        .map(|name| ModInfo::from(&mods_statistics.mods[name]))
+        .collect();
+    Some(Json(ModsInfo { total: names.len(), mods }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModVersionsWeek {
+    pub week_begin: TimeMinutes,
+    // (version, number servers)
+    pub versions: Vec<(String, usize)>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModServerInfo {
+    pub server_id: ServerId,
+    pub name: String,
+    pub is_online: bool,
+    // on games with this mod
+    pub player_minutes: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModPageInfo {
+    #[serde(flatten)]
+    pub info: ModInfo,
+    pub versions_by_week: Vec<ModVersionsWeek>,
+    pub top_servers: Vec<ModServerInfo>,
+}
+
+#[get("/mod/<name>")]
+pub fn get_mod(
+    name: String,
+    state_lock: State<StateLock>,
+    cacher_state_lock: State<CacherStateLock>,
+) -> Option<Json<ModPageInfo>> {
+    let mods_statistics = get_mods_statistics(&cacher_state_lock);
+    let mod_ = mods_statistics.mods.get(&name)?;
+
+    let state = state_lock.read();
+    let top_servers = mod_.top_servers.iter()
+        .map(|&(server_id, player_minutes)| {
+            let last_game = state.get_game(state.get_server_last_game_id(server_id));
+            ModServerInfo {
+                server_id,
+                name: last_game.get_name(&state).to_owned(),
+                is_online: last_game.time_end.is_none(),
+                player_minutes,
+            }
+        })
+        .collect();
+    let versions_by_week = mod_.versions_by_week.iter()
+        .map(|(week_begin, versions)| ModVersionsWeek { week_begin: *week_begin, versions: versions.clone() })
+        .collect();
+    Some(Json(ModPageInfo {
+        info: ModInfo::from(mod_),
+        versions_by_week,
+        top_servers,
+    }))
+}
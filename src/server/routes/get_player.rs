@@ -0,0 +1,92 @@
+use hashbrown::HashMap;
+use rocket::get;
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use fss::state::{GameId, ServerId, StateLock, TimeMinutes};
+
+const NUMBER_RECENT_SESSIONS: usize = 50;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Player {
+    pub name: String,
+    // in minutes
+    pub total_playtime: u64,
+    // sorted by playtime
+    pub servers: Vec<PlayerServer>,
+    // most recent first
+    pub recent_sessions: Vec<PlayerSession>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerServer {
+    // None if game is not merged into server yet
+    pub server_id: Option<ServerId>,
+    pub name: String,
+    // in minutes
+    pub playtime: u64,
+    pub last_seen: TimeMinutes,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerSession {
+    pub server_id: Option<ServerId>,
+    pub game_id: GameId,
+    pub server_name: String,
+    pub begin: TimeMinutes,
+    // None if player is online
+    pub end: Option<TimeMinutes>,
+}
+
+/// все сессии игрока на всех серверах
+#[get("/player/<name>")]
+pub fn get_player(name: String, state_lock: rocket::State<StateLock>) -> Option<Json<Player>> {
+    let state = state_lock.read();
+    let sessions = state.get_player_sessions(&name);
+    if sessions.is_empty() {
+        return None;
+    }
+    let now = state.last_observed_time.unwrap_or_else(TimeMinutes::now);
+
+    let mut sessions: Vec<PlayerSession> = sessions.iter()
+        .map(|&session| {
+            let game = state.get_game(session.game_id);
+            PlayerSession {
+                server_id: game.server_id,
+                game_id: session.game_id,
+                server_name: game.get_name(&state).to_owned(),
+                begin: session.begin,
+                end: session.end,
+            }
+        })
+        .collect();
+    sessions.sort_by_key(|session| std::cmp::Reverse(session.begin));
+
+    // сессии в играх, которые ещё не объединены в сервер, группируем по GameId
+    let mut servers: HashMap<Result<ServerId, GameId>, PlayerServer> = HashMap::new();
+    let mut total_playtime = 0;
+    for session in &sessions {
+        let session_end = session.end.unwrap_or(now);
+        let playtime = session_end.get().saturating_sub(session.begin.get()) as u64;
+        total_playtime += playtime;
+
+        let key = session.server_id.ok_or(session.game_id);
+        let server = servers.entry(key).or_insert_with(|| PlayerServer {
+            server_id: session.server_id,
+            // сессии отсортированы по убыванию времени, поэтому это последнее название сервера
+            name: session.server_name.clone(),
+            playtime: 0,
+            last_seen: session_end,
+        });
+        server.playtime += playtime;
+        server.last_seen = server.last_seen.max(session_end);
+    }
+    let mut servers: Vec<PlayerServer> = servers.into_iter().map(|(_, server)| server).collect();
+    servers.sort_by_key(|server| std::cmp::Reverse(server.playtime));
+
+    sessions.truncate(NUMBER_RECENT_SESSIONS);
+    Some(Json(Player { name, total_playtime, servers, recent_sessions: sessions }))
+}
@@ -0,0 +1,172 @@
+use rocket::get;
+
+use fss::state::{ServerId, StateLock, TimeMinutes};
+
+use crate::server::routes::util::{escape_xml, SvgResponder, truncate_text};
+
+// короткое время, чтобы бейджи в readme не устаревали
+const CACHE_MAX_AGE: u32 = 60;  // in seconds
+const LABEL: &str = "factorio";
+// label задаётся пользователем, ограничиваем ширину бейджа
+const MAX_LABEL_LENGTH: usize = 32;
+const ONLINE_COLOR: &str = "#4c1";
+const OFFLINE_COLOR: &str = "#9f9f9f";
+const LABEL_COLOR: &str = "#555";
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum BadgeStyle {
+    Flat,
+    FlatSquare,
+    Plastic,
+}
+
+impl BadgeStyle {
+    fn parse(style: &str) -> Option<Self> {
+        match style {
+            "flat" => Some(BadgeStyle::Flat),
+            "flat-square" => Some(BadgeStyle::FlatSquare),
+            "plastic" => Some(BadgeStyle::Plastic),
+            _ => None,
+        }
+    }
+}
+
+/// "3m", "5h", "2d"
+pub fn format_duration(minutes: u32) -> String {
+    match minutes {
+        0..=59 => format!("{}m", minutes),
+        60..=1439 => format!("{}h", minutes / 60),
+        _ => format!("{}d", minutes / (24 * 60)),
+    }
+}
+
+// ширина текста шрифтом Verdana 11px, приблизительно
+fn get_text_width(text: &str) -> u32 {
+    text.chars().count() as u32 * 7 + 10
+}
+
+/// в стиле https://shields.io
+pub fn render_badge(label: &str, message: &str, color: &str, style: BadgeStyle) -> String {
+    let label_width = get_text_width(label);
+    let message_width = get_text_width(message);
+    let width = label_width + message_width;
+    let radius = if style == BadgeStyle::FlatSquare { 0 } else { 3 };
+    let height = if style == BadgeStyle::Plastic { 18 } else { 20 };
+    let gradient = match style {
+        BadgeStyle::Flat => r##"<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>"##,
+        BadgeStyle::Plastic => r##"<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#fff" stop-opacity=".7"/><stop offset=".1" stop-color="#aaa" stop-opacity=".1"/><stop offset=".9" stop-opacity=".3"/><stop offset="1" stop-opacity=".5"/></linearGradient>"##,
+        BadgeStyle::FlatSquare => "",
+    };
+    let gradient_rect = if gradient.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<rect width="{}" height="{}" fill="url(#s)"/>"#, width, height)
+    };
+    let text_y = height as f64 / 2.0 + 4.0;
+    let label = escape_xml(label);
+    let message = escape_xml(message);
+
+    format!(
+        concat!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" role="img" aria-label="{label}: {message}">"##,
+            r##"<title>{label}: {message}</title>{gradient}"##,
+            r##"<clipPath id="r"><rect width="{width}" height="{height}" rx="{radius}" fill="#fff"/></clipPath>"##,
+            r##"<g clip-path="url(#r)">"##,
+            r##"<rect width="{label_width}" height="{height}" fill="{label_color}"/>"##,
+            r##"<rect x="{label_width}" width="{message_width}" height="{height}" fill="{color}"/>"##,
+            r##"{gradient_rect}</g>"##,
+            r##"<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">"##,
+            r##"<text x="{label_x}" y="{text_shadow_y}" fill="#010101" fill-opacity=".3">{label}</text>"##,
+            r##"<text x="{label_x}" y="{text_y}">{label}</text>"##,
+            r##"<text x="{message_x}" y="{text_shadow_y}" fill="#010101" fill-opacity=".3">{message}</text>"##,
+            r##"<text x="{message_x}" y="{text_y}">{message}</text>"##,
+            r##"</g></svg>"##,
+        ),
+        width = width,
+        height = height,
+        radius = radius,
+        label = label,
+        message = message,
+        gradient = gradient,
+        gradient_rect = gradient_rect,
+        label_width = label_width,
+        message_width = message_width,
+        label_color = LABEL_COLOR,
+        color = color,
+        label_x = label_width as f64 / 2.0,
+        message_x = label_width as f64 + message_width as f64 / 2.0,
+        text_y = text_y,
+        text_shadow_y = text_y + 1.0,
+    )
+}
+
+/// "online · 12/50 players" или "offline since 3h"
+#[get("/server/<server_id>/badge.svg?<style>&<label>")]
+pub fn get_server_badge(
+    server_id: usize,
+    style: Option<String>,
+    label: Option<String>,
+    state_lock: rocket::State<StateLock>,
+) -> Option<SvgResponder> {
+    let style = BadgeStyle::parse(style.as_deref().unwrap_or("flat"))?;
+    let label = truncate_text(label.as_deref().unwrap_or(LABEL), MAX_LABEL_LENGTH);
+
+    let state = state_lock.read();
+    let server_id: ServerId = state.as_server_id(server_id)?;
+    let game = state.get_game(state.get_server_last_game_id(server_id));
+    let (message, color) = match game.time_end {
+        None => {
+            let message = if game.max_players == 0 {
+                format!("online · {} players", game.number_players_online())
+            } else {
+                format!("online · {}/{} players", game.number_players_online(), game.max_players)
+            };
+            (message, ONLINE_COLOR)
+        }
+        Some(time_end) => {
+            let now = state.last_observed_time.unwrap_or_else(TimeMinutes::now);
+            let offline_duration = now.get().saturating_sub(time_end.get());
+            (format!("offline since {}", format_duration(offline_duration)), OFFLINE_COLOR)
+        }
+    };
+
+    let svg = render_badge(&label, &message, color, style);
+    Some(SvgResponder { svg, max_age: CACHE_MAX_AGE })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration() {
+        assert_eq!(format_duration(0), "0m");
+        assert_eq!(format_duration(59), "59m");
+        assert_eq!(format_duration(60), "1h");
+        assert_eq!(format_duration(24 * 60 - 1), "23h");
+        assert_eq!(format_duration(24 * 60), "1d");
+        assert_eq!(format_duration(10 * 24 * 60 + 5), "10d");
+    }
+
+    #[test]
+    fn badge() {
+        let svg = render_badge("a<b", "online · 1/2 players", ONLINE_COLOR, BadgeStyle::Flat);
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>"));
+        assert!(svg.contains("a&lt;b") && !svg.contains("a<b"));
+        assert!(svg.contains(r#"fill="#4c1""#));
+        assert!(svg.contains(r#"rx="3""#) && svg.contains("url(#s)"));
+        let width = get_text_width("a<b") + get_text_width("online · 1/2 players");
+        assert!(svg.contains(&format!(r#"width="{}" height="20""#, width)));
+
+        let svg = render_badge("factorio", "offline since 3h", OFFLINE_COLOR, BadgeStyle::FlatSquare);
+        assert!(svg.contains(r#"rx="0""#) && !svg.contains("url(#s)"));
+        let svg = render_badge("factorio", "offline since 3h", OFFLINE_COLOR, BadgeStyle::Plastic);
+        assert!(svg.contains(r#"height="18""#));
+    }
+
+    #[test]
+    fn long_label() {
+        let label = truncate_text(&"x".repeat(1000), MAX_LABEL_LENGTH);
+        assert_eq!(label.chars().count(), MAX_LABEL_LENGTH);
+    }
+}
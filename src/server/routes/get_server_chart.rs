@@ -0,0 +1,292 @@
+use std::fmt::Write;
+
+use chrono::NaiveDateTime;
+use rocket::get;
+
+use fss::state::{ServerId, StateLock, TimeMinutes};
+use fss::state::online::{self, OnlinePoint};
+use fss::util::strip_rich_text;
+
+use crate::server::routes::util::{escape_xml, SvgResponder, truncate_text};
+
+// online обновляется раз в минуту
+const CACHE_MAX_AGE: u32 = 60;  // in seconds
+const DEFAULT_WIDTH: u32 = 600;
+const DEFAULT_HEIGHT: u32 = 300;
+
+#[derive(Copy, Clone)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl Period {
+    fn parse(period: &str) -> Option<Self> {
+        match period {
+            "day" => Some(Period::Day),
+            "week" => Some(Period::Week),
+            "month" => Some(Period::Month),
+            _ => None,
+        }
+    }
+
+    fn minutes(self) -> u32 {
+        match self {
+            Period::Day => 24 * 60,
+            Period::Week => TimeMinutes::WEEK,
+            Period::Month => 30 * 24 * 60,
+        }
+    }
+
+    // интервал между подписями на оси времени и их формат
+    fn label_interval(self) -> (u32, &'static str) {
+        match self {
+            Period::Day => (3 * 60, "%H:%M"),
+            Period::Week => (24 * 60, "%b %d"),
+            Period::Month => (5 * 24 * 60, "%b %d"),
+        }
+    }
+}
+
+pub struct Theme {
+    pub background: &'static str,
+    pub text: &'static str,
+    pub grid: &'static str,
+    pub line: &'static str,
+    // область между min и max
+    pub band: &'static str,
+}
+
+pub const LIGHT_THEME: Theme = Theme {
+    background: "#ffffff",
+    text: "#333333",
+    grid: "#e0e0e0",
+    line: "#1976d2",
+    band: "#90caf9",
+};
+
+pub const DARK_THEME: Theme = Theme {
+    background: "#2f3136",  // как в Discord
+    text: "#dcddde",
+    grid: "#4f545c",
+    line: "#ff9800",
+    band: "#8a5a12",
+};
+
+pub fn parse_theme(theme: &str) -> Option<&'static Theme> {
+    match theme {
+        "light" => Some(&LIGHT_THEME),
+        "dark" => Some(&DARK_THEME),
+        _ => None,
+    }
+}
+
+/// 1, 2, 5, 10, 20, 50, ... — наименьшее из таких чисел, при котором делений не больше `number_ticks`
+fn get_tick_step(max_value: u32, number_ticks: u32) -> u32 {
+    let min_step = ((max_value + number_ticks - 1) / number_ticks).max(1);
+    let mut magnitude = 1;
+    loop {
+        for &multiplier in &[1, 2, 5] {
+            if multiplier * magnitude >= min_step {
+                return multiplier * magnitude;
+            }
+        }
+        magnitude *= 10;
+    }
+}
+
+fn format_time(time: u32, format: &str) -> String {
+    NaiveDateTime::from_timestamp(time as i64 * 60, 0).format(format).to_string()
+}
+
+/// последовательные точки без пропусков (пропуски на графике не соединяются)
+fn split_into_segments(points: &[OnlinePoint], resolution: u32) -> Vec<&[OnlinePoint]> {
+    let mut segments = Vec::new();
+    let mut segment_begin = 0;
+    for i in 1..=points.len() {
+        if i == points.len() || points[i].time.get() - points[i - 1].time.get() > resolution {
+            segments.push(&points[segment_begin..i]);
+            segment_begin = i;
+        }
+    }
+    segments
+}
+
+pub struct ChartOptions {
+    pub title: String,
+    pub time_begin: TimeMinutes,
+    pub time_end: TimeMinutes,
+    pub resolution: u32,
+    pub period: Period,
+    pub width: u32,
+    pub height: u32,
+    pub theme: &'static Theme,
+}
+
+pub fn render_chart(points: &[OnlinePoint], options: &ChartOptions) -> String {
+    const MARGIN_LEFT: f64 = 40.0;
+    const MARGIN_RIGHT: f64 = 15.0;
+    const MARGIN_TOP: f64 = 30.0;
+    const MARGIN_BOTTOM: f64 = 25.0;
+
+    let width = options.width as f64;
+    let height = options.height as f64;
+    let plot_width = width - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_height = height - MARGIN_TOP - MARGIN_BOTTOM;
+    let theme = options.theme;
+
+    let begin = options.time_begin.get();
+    let end = options.time_end.get();
+    let max_online = points.iter().map(|point| point.max).max().unwrap_or(0).max(1);
+    let tick_step = get_tick_step(max_online, 4);
+    let y_max = ((max_online + tick_step - 1) / tick_step * tick_step) as f64;
+    let x = |time: f64| MARGIN_LEFT + (time - begin as f64) / (end - begin) as f64 * plot_width;
+    let y = |value: f64| MARGIN_TOP + plot_height - value / y_max * plot_height;
+
+    let mut svg = String::new();
+    // write! в String не может завершиться ошибкой
+    let _ = write!(svg,
+                   r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">"#,
+                   options.width, options.height);
+    let _ = write!(svg, r#"<rect width="100%" height="100%" fill="{}"/>"#, theme.background);
+    let _ = write!(svg, r#"<text x="{}" y="18" font-size="14" font-weight="bold" fill="{}">{}</text>"#,
+                   MARGIN_LEFT, theme.text, escape_xml(&options.title));
+    let _ = write!(svg, r#"<text x="{}" y="18" text-anchor="end" fill="{}">players online (UTC)</text>"#,
+                   width - MARGIN_RIGHT, theme.text);
+
+    // горизонтальная сетка и подписи оси числа игроков
+    for value in (0..=y_max as u32).step_by(tick_step as usize) {
+        let value_y = y(value as f64);
+        let _ = write!(svg, r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="{}"/>"#,
+                       MARGIN_LEFT, value_y, width - MARGIN_RIGHT, value_y, theme.grid);
+        let _ = write!(svg, r#"<text x="{:.1}" y="{:.1}" text-anchor="end" fill="{}">{}</text>"#,
+                       MARGIN_LEFT - 5.0, value_y + 4.0, theme.text, value);
+    }
+
+    // подписи оси времени
+    let (label_interval, label_format) = options.period.label_interval();
+    let mut label_time = (begin + label_interval - 1) / label_interval * label_interval;
+    while label_time < end {
+        let label_x = x(label_time as f64);
+        let _ = write!(svg, r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="{}"/>"#,
+                       label_x, MARGIN_TOP, label_x, MARGIN_TOP + plot_height, theme.grid);
+        let _ = write!(svg, r#"<text x="{:.1}" y="{:.1}" text-anchor="middle" fill="{}">{}</text>"#,
+                       label_x, height - 8.0, theme.text, format_time(label_time, label_format));
+        label_time += label_interval;
+    }
+
+    // точка соответствует середине интервала [time, time + resolution)
+    let point_x = |point: &OnlinePoint| x(point.time.get() as f64 + options.resolution as f64 / 2.0);
+    for segment in split_into_segments(points, options.resolution) {
+        let band = segment.iter()
+            .map(|point| format!("{:.1},{:.1}", point_x(point), y(point.max as f64)))
+            .chain(segment.iter().rev().map(|point| format!("{:.1},{:.1}", point_x(point), y(point.min as f64))))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let line = segment.iter()
+            .map(|point| format!("{:.1},{:.1}", point_x(point), y(point.avg as f64)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let _ = write!(svg, r#"<polygon points="{}" fill="{}" fill-opacity="0.5"/>"#, band, theme.band);
+        let _ = write!(svg, r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="1.5"/>"#, line, theme.line);
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// график числа игроков онлайн за последний день/неделю/месяц, для встраивания в Discord или на форум
+/// (png не поддерживается, так как для него нужен растеризатор svg)
+#[get("/server/<server_id>/chart.svg?<period>&<width>&<height>&<theme>")]
+pub fn get_server_chart(
+    server_id: usize,
+    period: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    theme: Option<String>,
+    state_lock: rocket::State<StateLock>,
+) -> Option<SvgResponder> {
+    let period = Period::parse(period.as_deref().unwrap_or("day"))?;
+    let theme = parse_theme(theme.as_deref().unwrap_or("light"))?;
+    let width = width.unwrap_or(DEFAULT_WIDTH).max(200).min(2000);
+    let height = height.unwrap_or(DEFAULT_HEIGHT).max(100).min(1000);
+
+    let state = state_lock.read();
+    let server_id: ServerId = state.as_server_id(server_id)?;
+    let time_end = state.last_observed_time.unwrap_or_else(TimeMinutes::now).get() + 1;
+    let time_begin = TimeMinutes::new(time_end.saturating_sub(period.minutes()).max(1))?;
+    let time_end = TimeMinutes::new(time_end)?;
+    // примерно одна точка на 2 пикселя
+    let resolution = online::get_resolution(time_begin, time_end, width / 2);
+    let points = state.get_server_online_series(server_id, time_begin, time_end, resolution);
+
+    let title = strip_rich_text(state.get_game_name(state.get_server_last_game_id(server_id)));
+    // справа от названия подпись "players online (UTC)"
+    let title_max_length = (width.saturating_sub(200) / 8).max(10) as usize;
+    let options = ChartOptions {
+        title: truncate_text(&title, title_max_length),
+        time_begin,
+        time_end,
+        resolution,
+        period,
+        width,
+        height,
+        theme,
+    };
+    let svg = render_chart(&points, &options);
+    Some(SvgResponder { svg, max_age: CACHE_MAX_AGE })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(time: u32, online: u32) -> OnlinePoint {
+        OnlinePoint { time: TimeMinutes::new(time).unwrap(), min: online, max: online, avg: online as f32 }
+    }
+
+    #[test]
+    fn tick_step() {
+        assert_eq!(get_tick_step(0, 4), 1);
+        assert_eq!(get_tick_step(1, 4), 1);
+        assert_eq!(get_tick_step(4, 4), 1);
+        assert_eq!(get_tick_step(5, 4), 2);
+        assert_eq!(get_tick_step(9, 4), 5);
+        assert_eq!(get_tick_step(20, 4), 5);
+        assert_eq!(get_tick_step(21, 4), 10);
+        assert_eq!(get_tick_step(150, 4), 50);
+        assert_eq!(get_tick_step(1000, 4), 500);
+    }
+
+    #[test]
+    fn segments() {
+        let points = vec![point(10, 1), point(15, 2), point(20, 3), point(35, 4), point(40, 5), point(50, 6)];
+        let segments: Vec<Vec<u32>> = split_into_segments(&points, 5).iter()
+            .map(|segment| segment.iter().map(|point| point.max).collect())
+            .collect();
+        assert_eq!(segments, vec![vec![1, 2, 3], vec![4, 5], vec![6]]);
+        assert!(split_into_segments(&[], 5).is_empty());
+    }
+
+    #[test]
+    fn chart() {
+        let options = ChartOptions {
+            title: "<b>a & b</b>".to_owned(),
+            time_begin: TimeMinutes::new(60).unwrap(),
+            time_end: TimeMinutes::new(120).unwrap(),
+            resolution: 5,
+            period: Period::Day,
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            theme: &DARK_THEME,
+        };
+        let points = vec![point(60, 1), point(65, 2), point(100, 3)];
+        let svg = render_chart(&points, &options);
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>"));
+        assert!(svg.contains("&lt;b&gt;a &amp; b&lt;/b&gt;") && !svg.contains("<b>"));
+        assert!(svg.contains(DARK_THEME.background));
+        // gap between 65 and 100 splits the line
+        assert_eq!(svg.matches("<polyline").count(), 2);
+    }
+}
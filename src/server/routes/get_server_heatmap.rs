@@ -0,0 +1,48 @@
+use rocket::get;
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use fss::state::{ServerId, StateLock, TimeMinutes};
+use fss::state::online;
+
+const DEFAULT_NUMBER_WEEKS: u32 = 4;
+const MAX_NUMBER_WEEKS: u32 = 52;
+// UTC-12:00 .. UTC+14:00
+const MIN_TZ_OFFSET: i32 = -12 * 60;
+const MAX_TZ_OFFSET: i32 = 14 * 60;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Heatmap {
+    pub weeks: u32,
+    // in minutes
+    pub tz_offset: i32,
+    // average[weekday][hour], weekdays starting from Monday, null if there are no observations
+    pub average: Vec<Vec<Option<f32>>>,
+}
+
+/// среднее число игроков онлайн по дням недели и часам за последние `weeks` недель
+#[get("/server/<server_id>/heatmap?<weeks>&<tz_offset>")]
+pub fn get_server_heatmap(
+    server_id: usize,
+    weeks: Option<u32>,
+    tz_offset: Option<i32>,
+    state_lock: rocket::State<StateLock>,
+) -> Option<Json<Heatmap>> {
+    let weeks = weeks.unwrap_or(DEFAULT_NUMBER_WEEKS);
+    let tz_offset = tz_offset.unwrap_or(0);
+    if weeks == 0 || weeks > MAX_NUMBER_WEEKS || tz_offset < MIN_TZ_OFFSET || tz_offset > MAX_TZ_OFFSET {
+        return None;
+    }
+
+    let state = state_lock.read();
+    let server_id: ServerId = state.as_server_id(server_id)?;
+    let time_end = state.last_observed_time.unwrap_or_else(TimeMinutes::now).get() + 1;
+    let time_begin = TimeMinutes::new(time_end.saturating_sub(weeks * TimeMinutes::WEEK).max(1))?;
+    let time_end = TimeMinutes::new(time_end)?;
+
+    let game_ids = state.get_server_games_in_interval(server_id, time_begin, time_end);
+    let runs = state.get_online_runs(game_ids, time_begin, time_end);
+    let average = online::get_weekly_heatmap(&runs, tz_offset);
+    Some(Json(Heatmap { weeks, tz_offset, average }))
+}
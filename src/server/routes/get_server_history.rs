@@ -0,0 +1,49 @@
+use rocket::get;
+use rocket_contrib::json::Json;
+use serde::Serialize;
+use serde_json::Value;
+
+use fss::state::{GameId, ServerId, State, StateLock, TimeMinutes};
+use fss::state::metadata::MetadataValue;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataChange {
+    pub time: TimeMinutes,
+    pub game_id: GameId,
+    pub field: &'static str,
+    pub old_value: Value,
+    pub new_value: Value,
+}
+
+fn convert_value(value: MetadataValue, state: &State) -> Value {
+    match value {
+        MetadataValue::Name(name) => Value::from(state.all_game_names.get_str(name)),
+        MetadataValue::Description(description) => Value::from(state.all_game_descriptions.get_str(description)),
+        MetadataValue::GameVersion(game_version) => Value::from(state.all_versions.get_str(game_version)),
+        MetadataValue::Tags(tags) => {
+            let tags: &str = state.all_tags.get(tags).into();
+            Value::from(tags.split('\x02').filter(|tag| !tag.is_empty()).collect::<Vec<_>>())
+        }
+        MetadataValue::MaxPlayers(max_players) => Value::from(max_players),
+        MetadataValue::HasPassword(has_password) => Value::from(has_password),
+    }
+}
+
+/// изменения метаинформации (название, описание, версия, ...) за всё время существования сервера
+#[get("/server/<server_id>/history")]
+pub fn get_server_history(server_id: usize, state_lock: rocket::State<StateLock>) -> Option<Json<Vec<MetadataChange>>> {
+    let state = state_lock.read();
+    let server_id: ServerId = state.as_server_id(server_id)?;
+
+    let history = state.get_server_metadata_history(server_id).into_iter()
+        .map(|(game_id, change)| MetadataChange {
+            time: change.time,
+            game_id,
+            field: change.old_value.field_name(),
+            old_value: convert_value(change.old_value, &state),
+            new_value: convert_value(change.new_value, &state),
+        })
+        .collect();
+    Some(Json(history))
+}
@@ -2,12 +2,15 @@ use rocket::get;
 use rocket_contrib::json::Json;
 use serde::Serialize;
 
-use fss::state::{GameId, ServerId, State, StateLock, TimeMinutes};
+use fss::state::{DetailsStatus, GameId, ObservationGap, ServerId, State, StateLock, TimeMinutes};
 use fss::state;
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Server {
-    games: Vec<Game>
+    games: Vec<Game>,
+    // минуты без данных, клиент отображает их отдельно
+    observation_gaps: Vec<ObservationGap>,
 }
 
 #[derive(Serialize)]
@@ -27,15 +30,72 @@ pub struct Game {
     pub name: String,
     pub max_players: u32,
     pub game_version: String,
+    // None for games saved before build was recorded
+    pub build_version: Option<u32>,
+    pub build_mode: Option<String>,
+    pub platform: Option<String>,
     pub game_time_elapsed: u32,
     pub has_password: bool,
     pub tags: Vec<String>,
     pub mod_count: u16,
 
     pub description: String,
-    pub host_address: String,
-    // pair is (name, version)
+    pub details_status: DetailsStatus,
+    // None unless details_status is Fetched
+    pub host_address: Option<String>,
+    // pair is (name, version), None if details are not fetched or if mods_same_as_prev_game
     pub mods: Option<Vec<(String, String)>>,
+    // to reduce size of json: mods are same as mods of previous game in the response
+    pub mods_same_as_prev_game: bool,
+    // changes of mods and host address during the game
+    pub details_revisions: Vec<DetailsRevision>,
+    pub steam_id: Option<String>,
+    // as string, because javascript numbers can't represent all u64 values
+    pub mods_crc: Option<String>,
+    pub require_user_verification: Option<bool>,
+    pub last_heartbeat: Option<TimeMinutes>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DetailsRevision {
+    pub time: TimeMinutes,
+    // pair is (old value, new value)
+    pub host_address: Option<(String, String)>,
+    pub mods: Option<(Vec<(String, String)>, Vec<(String, String)>)>,
+}
+
+fn convert_mods(mods: &[state::Mod], state: &State) -> Vec<(String, String)> {
+    mods.iter()
+        .map(|mod_| (state.all_mod_names.get_str(mod_.name), state.all_versions.get_str(mod_.version)))
+        .collect()
+}
+
+fn convert_details_revision(revision: &state::metadata::DetailsRevision, state: &State) -> DetailsRevision {
+    DetailsRevision {
+        time: revision.time,
+        host_address: revision.host_address.map(|(old_host_address, new_host_address)| (
+            state.all_host_addresses.get_str(old_host_address),
+            state.all_host_addresses.get_str(new_host_address),
+        )),
+        mods: revision.mods.as_ref().map(|(old_mods, new_mods)| (
+            convert_mods(old_mods, state),
+            convert_mods(new_mods, state),
+        )),
+    }
+}
+
+// BigStringPart одинаковых модов у разных игр могут отличаться, поэтому сравниваем строки
+fn collapse_same_mods(games: &mut [Game]) {
+    let mut prev_game_mods = None;
+    for game in games {
+        if game.mods.is_some() && game.mods == prev_game_mods {
+            game.mods = None;
+            game.mods_same_as_prev_game = true;
+        } else {
+            prev_game_mods = game.mods.clone();
+        }
+    }
 }
 
 fn convert_game(game: &state::Game, state: &State, time_begin: TimeMinutes, time_end: TimeMinutes) -> Game {
@@ -45,6 +105,8 @@ fn convert_game(game: &state::Game, state: &State, time_begin: TimeMinutes, time
             let player_name = state.all_player_names.get(interval.player_index);
             (player_name.into(), interval.begin, interval.end)
         }).collect();
+    let build = game.build.map(|build| state.get_build(build));
+    let details_extra = state.get_game_details_extra(game.game_id).cloned().unwrap_or_default();
 
     Game {
         game_id: game.game_id,
@@ -59,14 +121,25 @@ fn convert_game(game: &state::Game, state: &State, time_begin: TimeMinutes, time
         description: state.all_game_descriptions.get(game.description).into(),
         max_players: game.max_players,
         game_version: state.all_versions.get(game.game_version).into(),
+        build_version: build.map(|build| build.build_version),
+        build_mode: build.map(|build| build.build_mode.clone()),
+        platform: build.map(|build| build.platform.clone()),
         game_time_elapsed: game.game_time_elapsed,
         has_password: game.has_password,
         tags: (state.all_tags.get(game.tags).into(): &str)
             .split("\n").to_owned().map(ToOwned::to_owned).collect(),
         mod_count: game.mod_count,
-        host_address: state.all_host_addresses.get(game.host_address.unwrap()).into(),
-        // todo
-        mods: None,
+        details_status: game.details_status,
+        host_address: game.host_address.map(|host_address| state.all_host_addresses.get_str(host_address)),
+        mods: game.get_mods(state).as_ref().map(|mods| convert_mods(mods, state)),
+        mods_same_as_prev_game: false,
+        details_revisions: state.get_game_details_revisions(game.game_id).iter()
+            .map(|revision| convert_details_revision(revision, state))
+            .collect(),
+        steam_id: details_extra.steam_id.map(|steam_id| state.all_steam_ids.get_str(steam_id)),
+        mods_crc: details_extra.mods_crc.map(|mods_crc| mods_crc.to_string()),
+        require_user_verification: details_extra.require_user_verification,
+        last_heartbeat: details_extra.last_heartbeat,
     }
 }
 
@@ -84,10 +157,12 @@ pub fn get_server_info(
     let time_end = TimeMinutes::new(time_end.unwrap_or(u32::max_value()))?;
 
     let game_ids = state.get_server_games_in_interval(server_id, time_begin, time_end);
-    let games = game_ids.into_iter()
+    let mut games: Vec<Game> = game_ids.into_iter()
         .map(|game_id| convert_game(state.get_game(game_id), &state, time_begin, time_end))
         .collect();
+    collapse_same_mods(&mut games);
+    let observation_gaps = state.get_observation_gaps_in_interval(time_begin, time_end);
 
     // todo посмотреть на типичный размер json, кажется метаинформация занимает очень много
-    Some(Json(Server { games }))
+    Some(Json(Server { games, observation_gaps }))
 }
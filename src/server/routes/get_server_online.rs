@@ -0,0 +1,58 @@
+use rocket::get;
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use fss::state::{ServerId, StateLock, TimeMinutes};
+use fss::state::online::{self, OnlinePoint};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OnlineSeries {
+    // in minutes
+    pub resolution: u32,
+    // (time, min, max, avg) for minutes [time, time + resolution)
+    // points without observations (see `State::observation_gaps`) are omitted
+    pub points: Vec<(TimeMinutes, u32, u32, f32)>,
+}
+
+/// [time_begin, time_end) по умолчанию — последняя неделя
+pub fn get_time_interval(
+    time_begin: Option<u32>,
+    time_end: Option<u32>,
+    last_observed_time: Option<TimeMinutes>,
+) -> Option<(TimeMinutes, TimeMinutes)> {
+    let default_time_end = last_observed_time.unwrap_or_else(TimeMinutes::now).get() + 1;
+    let time_end = time_end.unwrap_or(default_time_end);
+    let time_begin = time_begin.unwrap_or_else(|| time_end.saturating_sub(TimeMinutes::WEEK).max(1));
+    if time_begin >= time_end || time_end - time_begin > online::MAX_INTERVAL_LENGTH {
+        return None;
+    }
+    Some((TimeMinutes::new(time_begin)?, TimeMinutes::new(time_end)?))
+}
+
+pub fn convert_points(points: Vec<OnlinePoint>) -> Vec<(TimeMinutes, u32, u32, f32)> {
+    points.into_iter()
+        .map(|point| (point.time, point.min, point.max, point.avg))
+        .collect()
+}
+
+#[get("/server/<server_id>/online?<time_begin>&<time_end>&<resolution>")]
+pub fn get_server_online(
+    server_id: usize,
+    time_begin: Option<u32>,
+    time_end: Option<u32>,
+    resolution: Option<u32>,
+    state_lock: rocket::State<StateLock>,
+) -> Option<Json<OnlineSeries>> {
+    let state = state_lock.read();
+
+    let server_id: ServerId = state.as_server_id(server_id)?;
+    let (time_begin, time_end) = get_time_interval(time_begin, time_end, state.last_observed_time)?;
+    let min_resolution = online::get_resolution(time_begin, time_end, online::MAX_NUMBER_POINTS);
+    let resolution = resolution
+        .unwrap_or_else(|| online::get_resolution(time_begin, time_end, online::DEFAULT_NUMBER_POINTS))
+        .max(min_resolution);
+
+    let points = state.get_server_online_series(server_id, time_begin, time_end, resolution);
+    Some(Json(OnlineSeries { resolution, points: convert_points(points) }))
+}
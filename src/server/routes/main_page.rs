@@ -1,13 +1,10 @@
-use std::sync::Mutex;
-
-use itertools::Itertools;
-use lazy_static::lazy_static;
 use rocket::{get, State};
-use rocket::response::content;
+use rocket::response::{content, status};
 use rocket_contrib::json::Json;
 use serde::Serialize;
 
 use fss::cacher::CacherStateLock;
+use fss::search;
 use fss::state::{ServerId, StateLock, TimeMinutes};
 
 use crate::server::routes::util::ArcResponder;
@@ -27,31 +24,30 @@ pub struct GameSearchInfo {
     pub time_end: Option<TimeMinutes>,
 }
 
-// because search is resource-consuming
-lazy_static! {
-    static ref SEARCH_MUTEX: Mutex<()> = Mutex::new(());
-}
-
-#[get("/search-servers?<query>")]
-pub fn search(query: String, state_lock: State<StateLock>) -> Json<Vec<GameSearchInfo>> {
-    use regex::{escape, RegexBuilder};
-    use std::cmp::Reverse;
-
-    const NUMBER_GAMES: usize = 100;
+/// `query` — название сервера и фильтры, например `space player:foo mod:space-exploration online:true`
+/// (см. `fss::search`), результаты упорядочены по релевантности
+#[get("/search-servers?<query>&<limit>&<offset>")]
+pub fn search(
+    query: String,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    state_lock: State<StateLock>,
+    cacher_state_lock: State<CacherStateLock>,
+) -> Result<Json<Vec<GameSearchInfo>>, status::BadRequest<String>> {
+    const DEFAULT_LIMIT: usize = 20;
+    const MAX_LIMIT: usize = 100;
 
-    let _guard = SEARCH_MUTEX.lock().unwrap();
-    let query_regex = RegexBuilder::new(&escape(&query))
-        .case_insensitive(true)
-        .build().unwrap();
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let offset = offset.unwrap_or(0);
+    let query = search::parse_query(&query)
+        .map_err(|err| status::BadRequest(Some(err)))?;
+    // индекс неизменяемый, поэтому блокировку cacher_state не удерживаем во время поиска
+    let search_index = cacher_state_lock.read().search_index.clone();
 
     let state = state_lock.read();
-    let games = state.game_ids.iter()
-        .skip(1)
-        .rev()  // select top NUMBER_GAMES among latest ones
-        .filter(|&game_id| query_regex.is_match(state.get_game_name(*game_id)))
-        .map(|&game_id| state.get_game(game_id).server_id.unwrap())
-        .unique()
-        .take(NUMBER_GAMES)
+    let games = search_index.search(&query, &state).into_iter()
+        .skip(offset)
+        .take(limit)
         .map(|server_id| {
             let last_game_id = state.get_server_last_game_id(server_id);
             let first_game_id = state.get_server_first_game_id(server_id);
@@ -64,7 +60,6 @@ pub fn search(query: String, state_lock: State<StateLock>) -> Json<Vec<GameSearc
                 time_end: last_game.time_end,
             }
         })
-        .sorted_by_key(|info| (info.time_end.is_some(), Reverse(info.time_end), info.time_begin))
         .collect();
-    Json(games)
+    Ok(Json(games))
 }
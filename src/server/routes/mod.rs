@@ -4,7 +4,16 @@ use rocket::response::status;
 
 use fss::state::StateLock;
 
+pub mod admin;
+pub mod get_global_online;
+pub mod get_mods;
+pub mod get_player;
+pub mod get_server_badge;
+pub mod get_server_chart;
+pub mod get_server_heatmap;
+pub mod get_server_history;
 pub mod get_server_info;
+pub mod get_server_online;
 pub mod main_page;
 pub mod util;
 
@@ -2,6 +2,7 @@ use std::io::Cursor;
 use std::sync::Arc;
 
 use rocket::{Request, Response, response};
+use rocket::http::{ContentType, Header};
 use rocket::response::Responder;
 
 /// https://github.com/SergioBenitez/Rocket/issues/893#issuecomment-456151567
@@ -20,3 +21,45 @@ impl<'r, T: 'r> Responder<'r> for ArcResponder<T> where T: AsRef<[u8]> {
             .ok()
     }
 }
+
+/// svg image, which can be embedded into other sites (e.g. Discord or forums), so it is cached for `max_age` seconds
+pub struct SvgResponder {
+    pub svg: String,
+    pub max_age: u32,
+}
+
+impl<'r> Responder<'r> for SvgResponder {
+    fn respond_to(self, _request: &Request) -> response::Result<'r> {
+        Response::build()
+            .header(ContentType::SVG)
+            .header(Header::new("Cache-Control", format!("public, max-age={}", self.max_age)))
+            .sized_body(Cursor::new(self.svg))
+            .ok()
+    }
+}
+
+pub fn escape_xml(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            // control characters are not allowed in xml
+            c if c.is_control() => {}
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+pub fn truncate_text(text: &str, max_length: usize) -> String {
+    if text.chars().count() <= max_length {
+        return text.to_owned();
+    }
+    let mut result: String = text.chars().take(max_length.saturating_sub(1)).collect();
+    result.push('…');
+    result
+}
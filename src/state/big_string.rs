@@ -3,6 +3,11 @@ use std::num::NonZeroU32;
 use hashbrown::HashMap;
 use serde::{Deserialize, Serialize};
 
+/// \x00 — разделитель подстрок, поэтому в `BigString::add` он заменяется на \x01
+pub fn normalize_byte(byte: u8) -> u8 {
+    if byte == 0 { 1 } else { byte }
+}
+
 // todo documentation
 #[derive(Eq, PartialEq, Serialize, Deserialize)]
 pub struct BigString {
@@ -37,6 +42,12 @@ impl BigString {
         self.add_vec(string.as_bytes())
     }
 
+    /// совпадает ли подстрока с `add(string)`, `string` передаётся побайтово, чтобы не создавать строку
+    pub fn part_equals(&self, part_index: BigStringPart, string: impl Iterator<Item = u8>) -> bool {
+        let part = self.get(part_index).0;
+        part.iter().copied().eq(string.map(normalize_byte))
+    }
+
     pub fn add_vec(&mut self, string: &[u8]) -> BigStringPart {
         let part_index = self.content.len() as u32;
         self.content.extend_from_slice(string);
@@ -149,6 +160,16 @@ mod tests {
         assert_eq!(big_string.get_str(part_index), "hello");
     }
 
+    #[test]
+    fn part_equals() {
+        let mut big_string = BigString::new();
+        let part_index = big_string.add("a\x00b");
+        assert!(big_string.part_equals(part_index, "a\x00b".bytes()));
+        assert!(big_string.part_equals(part_index, "a\x01b".bytes()));
+        assert!(!big_string.part_equals(part_index, "a".bytes()));
+        assert!(!big_string.part_equals(part_index, "a\x00bc".bytes()));
+    }
+
     #[test]
     fn compress() {
         let mut big_string = BigString::new();
@@ -0,0 +1,328 @@
+//! Число публичных игр и игроков онлайн по всем серверам (по ответам /get-games)
+//! Последняя неделя хранится поминутно, более старые данные прореживаются до часовых средних
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::api;
+use crate::state::{State, TimeMinutes};
+
+// сколько минут хранится без прореживания
+const RECENT_SAMPLES_DURATION: u32 = TimeMinutes::WEEK;
+const HOUR: u32 = 60;
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GlobalOnlineSample {
+    pub time: TimeMinutes,
+    // 1 для поминутных значений, HOUR для прореженных (округлённые средние значения за [time, time + resolution))
+    pub resolution: u32,
+    pub number_games: u32,
+    pub number_players: u32,
+    pub number_games_with_password: u32,
+    pub number_players_with_password: u32,
+    // индексы — индексы в `GlobalOnline::major_versions`
+    // (могут быть короче `major_versions`, если версия появилась позже)
+    pub number_games_by_version: Vec<u32>,
+    pub number_players_by_version: Vec<u32>,
+}
+
+/// средние значения `GlobalOnlineSample` за [time, time + resolution)
+#[derive(Clone, PartialEq, Debug)]
+pub struct GlobalOnlinePoint {
+    pub time: TimeMinutes,
+    pub number_games: f32,
+    pub number_players: f32,
+    pub number_games_with_password: f32,
+    pub number_players_with_password: f32,
+    pub number_games_by_version: Vec<f32>,
+    pub number_players_by_version: Vec<f32>,
+}
+
+impl GlobalOnlinePoint {
+    fn round(&self, resolution: u32) -> GlobalOnlineSample {
+        let round = |value: f32| value.round() as u32;
+        GlobalOnlineSample {
+            time: self.time,
+            resolution,
+            number_games: round(self.number_games),
+            number_players: round(self.number_players),
+            number_games_with_password: round(self.number_games_with_password),
+            number_players_with_password: round(self.number_players_with_password),
+            number_games_by_version: self.number_games_by_version.iter().map(|&value| round(value)).collect(),
+            number_players_by_version: self.number_players_by_version.iter().map(|&value| round(value)).collect(),
+        }
+    }
+}
+
+#[derive(Default, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GlobalOnline {
+    // "0.17", "0.18", "1.0", в порядке появления
+    pub major_versions: Vec<String>,
+    // упорядочены по времени, все hourly_samples раньше всех recent_samples
+    pub hourly_samples: Vec<GlobalOnlineSample>,
+    pub recent_samples: Vec<GlobalOnlineSample>,
+}
+
+/// "1.0.0" → "1.0"
+pub fn get_major_version(version: &str) -> &str {
+    match version.match_indices('.').nth(1) {
+        Some((index, _)) => &version[..index],
+        None => version,
+    }
+}
+
+fn add_to_vec<T: Default + Clone + std::ops::AddAssign>(values: &mut Vec<T>, index: usize, value: T) {
+    if values.len() <= index {
+        values.resize(index + 1, T::default());
+    }
+    values[index] += value;
+}
+
+/// среднее значение `samples`, взвешенное по `resolution`
+fn average_samples(samples: &[GlobalOnlineSample], time: TimeMinutes) -> GlobalOnlinePoint {
+    let total_weight: u32 = samples.iter().map(|sample| sample.resolution).sum();
+    let mut result = GlobalOnlinePoint {
+        time,
+        number_games: 0.0,
+        number_players: 0.0,
+        number_games_with_password: 0.0,
+        number_players_with_password: 0.0,
+        number_games_by_version: Vec::new(),
+        number_players_by_version: Vec::new(),
+    };
+    for sample in samples {
+        let weight = sample.resolution as f32 / total_weight as f32;
+        result.number_games += sample.number_games as f32 * weight;
+        result.number_players += sample.number_players as f32 * weight;
+        result.number_games_with_password += sample.number_games_with_password as f32 * weight;
+        result.number_players_with_password += sample.number_players_with_password as f32 * weight;
+        for (index, &value) in sample.number_games_by_version.iter().enumerate() {
+            add_to_vec(&mut result.number_games_by_version, index, value as f32 * weight);
+        }
+        for (index, &value) in sample.number_players_by_version.iter().enumerate() {
+            add_to_vec(&mut result.number_players_by_version, index, value as f32 * weight);
+        }
+    }
+    result
+}
+
+/// разбивает упорядоченные по времени `samples` (не раньше `base`) на группы [time, time + resolution),
+/// где `time - base` кратно resolution
+fn group_samples(samples: &[GlobalOnlineSample], base: u32, resolution: u32) -> impl Iterator<Item=(u32, &[GlobalOnlineSample])> {
+    let mut rest = samples;
+    std::iter::from_fn(move || {
+        let first = rest.first()?;
+        let group_begin = base + (first.time.get() - base) / resolution * resolution;
+        let group_length = rest.iter()
+            .position(|sample| sample.time.get() >= group_begin + resolution)
+            .unwrap_or(rest.len());
+        let (group, new_rest) = rest.split_at(group_length);
+        rest = new_rest;
+        Some((group_begin, group))
+    })
+}
+
+impl GlobalOnline {
+    fn get_major_version_index(&mut self, version: &str) -> usize {
+        let major_version = get_major_version(version);
+        match self.major_versions.iter().position(|version| version == major_version) {
+            Some(index) => index,
+            None => {
+                self.major_versions.push(major_version.to_owned());
+                self.major_versions.len() - 1
+            }
+        }
+    }
+
+    pub fn add_sample(&mut self, get_games_response: &[api::Game], time: TimeMinutes) {
+        if let Some(last_sample) = self.recent_samples.last() {
+            // например при replay с пересекающимися архивами
+            if last_sample.time >= time {
+                return;
+            }
+        }
+
+        let mut sample = GlobalOnlineSample {
+            time,
+            resolution: 1,
+            number_games: 0,
+            number_players: 0,
+            number_games_with_password: 0,
+            number_players_with_password: 0,
+            number_games_by_version: Vec::new(),
+            number_players_by_version: Vec::new(),
+        };
+        for game in get_games_response {
+            let number_players = game.players.len() as u32;
+            sample.number_games += 1;
+            sample.number_players += number_players;
+            if game.has_password {
+                sample.number_games_with_password += 1;
+                sample.number_players_with_password += number_players;
+            }
+            let version_index = self.get_major_version_index(&game.application_version.game_version);
+            add_to_vec(&mut sample.number_games_by_version, version_index, 1);
+            add_to_vec(&mut sample.number_players_by_version, version_index, number_players);
+        }
+        self.recent_samples.push(sample);
+        self.downsample_old_samples(time);
+    }
+
+    /// заменяет поминутные значения старше `RECENT_SAMPLES_DURATION` (целыми часами) на часовые средние
+    fn downsample_old_samples(&mut self, now: TimeMinutes) {
+        let threshold = now.get().saturating_sub(RECENT_SAMPLES_DURATION) / HOUR * HOUR;
+        let number_old_samples = self.recent_samples.iter()
+            .position(|sample| sample.time.get() >= threshold)
+            .unwrap_or(self.recent_samples.len());
+        if number_old_samples == 0 {
+            return;
+        }
+
+        let old_samples: Vec<GlobalOnlineSample> = self.recent_samples.drain(..number_old_samples).collect();
+        for (hour_begin, samples) in group_samples(&old_samples, 0, HOUR) {
+            let time = TimeMinutes::new(hour_begin).unwrap();
+            self.hourly_samples.push(average_samples(samples, time).round(HOUR));
+        }
+    }
+
+    /// значения, усреднённые по интервалам [time, time + resolution) внутри [time_begin, time_end)
+    /// интервалы без наблюдений пропускаются
+    pub fn get_series(&self, time_begin: TimeMinutes, time_end: TimeMinutes, resolution: u32) -> Vec<GlobalOnlinePoint> {
+        assert!(resolution > 0);
+        let in_interval = |sample: &&GlobalOnlineSample| time_begin <= sample.time && sample.time < time_end;
+        let samples: Vec<GlobalOnlineSample> = self.hourly_samples.iter()
+            .chain(self.recent_samples.iter())
+            .filter(in_interval)
+            .cloned()
+            .collect();
+
+        group_samples(&samples, time_begin.get(), resolution)
+            .map(|(group_begin, samples)| average_samples(samples, TimeMinutes::new(group_begin).unwrap()))
+            .collect()
+    }
+}
+
+/// суммы минут (игр или игроков) за один час
+#[derive(Default)]
+struct HourMinutes {
+    number_games: u64,
+    number_players: u64,
+    number_games_with_password: u64,
+    number_players_with_password: u64,
+    number_games_by_version: Vec<u64>,
+    number_players_by_version: Vec<u64>,
+}
+
+impl HourMinutes {
+    fn into_sample(self, hour: u32) -> Option<GlobalOnlineSample> {
+        let average = |minutes: u64| ((minutes as f64) / (HOUR as f64)).round() as u32;
+        Some(GlobalOnlineSample {
+            time: TimeMinutes::new(hour * HOUR)?,
+            resolution: HOUR,
+            number_games: average(self.number_games),
+            number_players: average(self.number_players),
+            number_games_with_password: average(self.number_games_with_password),
+            number_players_with_password: average(self.number_players_with_password),
+            number_games_by_version: self.number_games_by_version.into_iter().map(average).collect(),
+            number_players_by_version: self.number_players_by_version.into_iter().map(average).collect(),
+        })
+    }
+}
+
+/// (час, число минут полуинтервала [begin, end) в этом часе)
+fn split_by_hours(begin: u32, end: u32) -> impl Iterator<Item=(u32, u64)> {
+    (begin / HOUR..(end + HOUR - 1) / HOUR).map(move |hour| {
+        let hour_begin = (hour * HOUR).max(begin);
+        let hour_end = ((hour + 1) * HOUR).min(end);
+        (hour, hour_end.saturating_sub(hour_begin) as u64)
+    })
+}
+
+impl State {
+    /// часовые значения по интервалам игр (для состояний, сохранённых до появления global_online)
+    /// учитываются только целые часы до `last_observed_time`, часы без игр пропускаются
+    pub fn rebuild_global_online(&self) -> GlobalOnline {
+        let mut global_online = GlobalOnline::default();
+        let end = match self.last_observed_time {
+            Some(last_observed_time) => last_observed_time.get() + 1,
+            None => return global_online,
+        };
+
+        let mut hours: HashMap<u32, HourMinutes> = HashMap::new();
+        for game in self.games.values() {
+            let game_version: &str = self.all_versions.get(game.game_version).into();
+            let version_index = global_online.get_major_version_index(game_version);
+            let game_end = game.time_end.map_or(end, |time_end| time_end.get().min(end));
+            for (hour, minutes) in split_by_hours(game.time_begin.get(), game_end) {
+                let hour_minutes = hours.entry(hour).or_default();
+                hour_minutes.number_games += minutes;
+                if game.has_password {
+                    hour_minutes.number_games_with_password += minutes;
+                }
+                add_to_vec(&mut hour_minutes.number_games_by_version, version_index, minutes);
+            }
+            for player_interval in &game.players_intervals {
+                let interval_end = player_interval.end.map_or(game_end, |interval_end| interval_end.get().min(game_end));
+                for (hour, minutes) in split_by_hours(player_interval.begin.get(), interval_end) {
+                    let hour_minutes = hours.entry(hour).or_default();
+                    hour_minutes.number_players += minutes;
+                    if game.has_password {
+                        hour_minutes.number_players_with_password += minutes;
+                    }
+                    add_to_vec(&mut hour_minutes.number_players_by_version, version_index, minutes);
+                }
+            }
+        }
+
+        let mut hours: Vec<(u32, HourMinutes)> = hours.into_iter()
+            .filter(|&(hour, _)| (hour + 1) * HOUR <= end)
+            .collect();
+        hours.sort_by_key(|&(hour, _)| hour);
+        global_online.hourly_samples = hours.into_iter()
+            .filter_map(|(hour, hour_minutes)| hour_minutes.into_sample(hour))
+            .collect();
+        println!("[info]  [state] global online is rebuilt: {} hourly samples", global_online.hourly_samples.len());
+        global_online
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(time: u32, number_players: u32) -> GlobalOnlineSample {
+        GlobalOnlineSample {
+            time: TimeMinutes::new(time).unwrap(),
+            resolution: 1,
+            number_games: 1,
+            number_players,
+            number_games_with_password: 0,
+            number_players_with_password: 0,
+            number_games_by_version: vec![1],
+            number_players_by_version: vec![number_players],
+        }
+    }
+
+    #[test]
+    fn downsample_and_aggregate() {
+        assert_eq!(get_major_version("0.18.27"), "0.18");
+        assert_eq!(get_major_version("1.0"), "1.0");
+
+        let begin = 100 * HOUR;
+        let mut global_online = GlobalOnline::default();
+        global_online.recent_samples = vec![sample(begin, 2), sample(begin + 1, 4), sample(begin + HOUR, 10)];
+        global_online.downsample_old_samples(TimeMinutes::new(begin + HOUR + RECENT_SAMPLES_DURATION).unwrap());
+        let hourly_sample = GlobalOnlineSample { resolution: HOUR, ..sample(begin, 3) };
+        assert_eq!(global_online.hourly_samples, vec![hourly_sample]);
+        assert_eq!(global_online.recent_samples, vec![sample(begin + HOUR, 10)]);
+
+        let time_begin = TimeMinutes::new(begin).unwrap();
+        let time_end = TimeMinutes::new(begin + 2 * HOUR).unwrap();
+        let points = global_online.get_series(time_begin, time_end, 2 * HOUR);
+        assert_eq!(points.len(), 1);
+        // часовое значение весит в 60 раз больше поминутного
+        assert!((points[0].number_players - (3.0 * 60.0 + 10.0) / 61.0).abs() < 1e-4);
+    }
+}
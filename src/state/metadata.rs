@@ -0,0 +1,185 @@
+//! История изменений метаинформации серверов (название, описание, версия, теги, число слотов, пароль)
+//! Изменения внутри одной игры хранятся в `State::metadata_changes`,
+//! изменения между соседними играми сервера вычисляются при запросе истории
+//! Изменения модов и адреса (обнаруженные повторным запросом /get-game-details) хранятся в `State::details_revisions`
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::state::{BigStringPart, Game, GameId, Mod, ServerId, State, TimeMinutes};
+
+pub const NUMBER_METADATA_FIELDS: usize = 6;
+// некоторые серверы меняют название или описание каждую минуту (часы, число игроков в названии),
+// поэтому для каждого поля игры хранятся только последние изменения:
+// два самых старых изменения объединяются в одно, так что начальное и текущее значения сохраняются
+pub const MAX_METADATA_CHANGES_PER_FIELD: usize = 32;
+
+#[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum MetadataValue {
+    Name(BigStringPart),
+    Description(BigStringPart),
+    GameVersion(BigStringPart),
+    Tags(BigStringPart),
+    MaxPlayers(u32),
+    HasPassword(bool),
+}
+
+impl MetadataValue {
+    pub fn field_index(&self) -> usize {
+        match self {
+            MetadataValue::Name(_) => 0,
+            MetadataValue::Description(_) => 1,
+            MetadataValue::GameVersion(_) => 2,
+            MetadataValue::Tags(_) => 3,
+            MetadataValue::MaxPlayers(_) => 4,
+            MetadataValue::HasPassword(_) => 5,
+        }
+    }
+
+    pub fn field_name(&self) -> &'static str {
+        match self {
+            MetadataValue::Name(_) => "name",
+            MetadataValue::Description(_) => "description",
+            MetadataValue::GameVersion(_) => "gameVersion",
+            MetadataValue::Tags(_) => "tags",
+            MetadataValue::MaxPlayers(_) => "maxPlayers",
+            MetadataValue::HasPassword(_) => "hasPassword",
+        }
+    }
+
+    pub fn get_big_string_part(&self) -> Option<BigStringPart> {
+        match *self {
+            MetadataValue::Name(part) | MetadataValue::Description(part) | MetadataValue::GameVersion(part) | MetadataValue::Tags(part) => Some(part),
+            MetadataValue::MaxPlayers(_) | MetadataValue::HasPassword(_) => None,
+        }
+    }
+
+    // одна и та же строка может храниться в BigString несколько раз, поэтому сравниваем содержимое
+    pub fn is_same(&self, other: &MetadataValue, state: &State) -> bool {
+        use MetadataValue::*;
+        match (*self, *other) {
+            (Name(a), Name(b)) => state.all_game_names.get(a).0 == state.all_game_names.get(b).0,
+            (Description(a), Description(b)) => state.all_game_descriptions.get(a).0 == state.all_game_descriptions.get(b).0,
+            (GameVersion(a), GameVersion(b)) => state.all_versions.get(a).0 == state.all_versions.get(b).0,
+            (Tags(a), Tags(b)) => state.all_tags.get(a).0 == state.all_tags.get(b).0,
+            (a, b) => a == b,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MetadataChange {
+    pub time: TimeMinutes,
+    pub old_value: MetadataValue,
+    pub new_value: MetadataValue,
+}
+
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DetailsRevision {
+    pub time: TimeMinutes,
+    // (старое значение, новое значение), None если значение не изменилось
+    pub host_address: Option<(BigStringPart, BigStringPart)>,
+    pub mods: Option<(Vec<Mod>, Vec<Mod>)>,
+}
+
+impl Game {
+    pub fn get_metadata(&self) -> [MetadataValue; NUMBER_METADATA_FIELDS] {
+        [
+            MetadataValue::Name(self.name),
+            MetadataValue::Description(self.description),
+            MetadataValue::GameVersion(self.game_version),
+            MetadataValue::Tags(self.tags),
+            MetadataValue::MaxPlayers(self.max_players),
+            MetadataValue::HasPassword(self.has_password),
+        ]
+    }
+
+    pub fn set_metadata_value(&mut self, value: MetadataValue) {
+        match value {
+            MetadataValue::Name(name) => self.name = name,
+            MetadataValue::Description(description) => self.description = description,
+            MetadataValue::GameVersion(game_version) => self.game_version = game_version,
+            MetadataValue::Tags(tags) => self.tags = tags,
+            MetadataValue::MaxPlayers(max_players) => self.max_players = max_players,
+            MetadataValue::HasPassword(has_password) => self.has_password = has_password,
+        }
+    }
+}
+
+impl State {
+    pub fn get_game_metadata_changes(&self, game_id: GameId) -> &[MetadataChange] {
+        self.metadata_changes.get(&game_id).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// строки, которые встречались в истории поля `field_index` игры (чтобы не добавлять их в BigString повторно)
+    pub fn get_game_metadata_prev_parts(&self, game_id: GameId, field_index: usize) -> Vec<BigStringPart> {
+        self.get_game_metadata_changes(game_id).iter()
+            .filter(|change| change.new_value.field_index() == field_index)
+            .flat_map(|change| change.old_value.get_big_string_part().into_iter().chain(change.new_value.get_big_string_part()))
+            .collect()
+    }
+
+    /// см. MAX_METADATA_CHANGES_PER_FIELD
+    pub fn add_metadata_changes(&mut self, game_id: GameId, new_changes: Vec<MetadataChange>) {
+        let mut changes = self.metadata_changes.remove(&game_id).unwrap_or_default();
+        for change in new_changes {
+            let field_index = change.new_value.field_index();
+            changes.push(change);
+
+            let field_changes: Vec<usize> = changes.iter()
+                .positions(|change| change.new_value.field_index() == field_index)
+                .collect();
+            if field_changes.len() > MAX_METADATA_CHANGES_PER_FIELD {
+                let (first, second) = (field_changes[0], field_changes[1]);
+                changes[second].old_value = changes[first].old_value;
+                changes.remove(first);
+                let merged_change = changes[second - 1];
+                if merged_change.old_value.is_same(&merged_change.new_value, self) {
+                    changes.remove(second - 1);
+                }
+            }
+        }
+        if !changes.is_empty() {
+            self.metadata_changes.insert(game_id, changes);
+        }
+    }
+
+    pub fn get_game_details_revisions(&self, game_id: GameId) -> &[DetailsRevision] {
+        self.details_revisions.get(&game_id).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// метаинформация игры в момент её появления
+    fn get_game_initial_metadata(&self, game: &Game) -> [MetadataValue; NUMBER_METADATA_FIELDS] {
+        let mut metadata = game.get_metadata();
+        for change in self.get_game_metadata_changes(game.game_id).iter().rev() {
+            metadata[change.old_value.field_index()] = change.old_value;
+        }
+        metadata
+    }
+
+    /// все изменения метаинформации сервера, упорядоченные по времени
+    /// (GameId — игра, в которой наблюдается новое значение)
+    pub fn get_server_metadata_history(&self, server_id: ServerId) -> Vec<(GameId, MetadataChange)> {
+        let mut history = Vec::new();
+        let mut prev_game_metadata: Option<[MetadataValue; NUMBER_METADATA_FIELDS]> = None;
+        let mut game_id = Some(self.get_server_first_game_id(server_id));
+        while let Some(id) = game_id {
+            let game = self.get_game(id);
+
+            let initial_metadata = self.get_game_initial_metadata(game);
+            if let Some(prev_game_metadata) = prev_game_metadata {
+                for (old_value, new_value) in prev_game_metadata.iter().zip(initial_metadata.iter()) {
+                    if !old_value.is_same(new_value, self) {
+                        let change = MetadataChange { time: game.time_begin, old_value: *old_value, new_value: *new_value };
+                        history.push((id, change));
+                    }
+                }
+            }
+            history.extend(self.get_game_metadata_changes(id).iter().map(|&change| (id, change)));
+
+            prev_game_metadata = Some(game.get_metadata());
+            game_id = game.next_game_id;
+        }
+        history
+    }
+}
@@ -4,15 +4,22 @@ use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use hashbrown::HashMap;
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 
 pub use big_string::*;
 
+use crate::state::metadata::MetadataValue;
 use crate::util::duration_since;
 use crate::util::map_deref::{map_deref, map_deref_mut};
 
 pub mod updater;
+pub mod overrides;
+pub mod metadata;
+pub mod online;
+pub mod players_index;
+pub mod global_online;
 mod big_string;
 
 /// unix time, с точностью до минут
@@ -53,6 +60,8 @@ pub type HostId = [u8; 32];
 /// будем использовать собственную нумерацию серверов, обозначаемую ServerId
 /// ServerId — индекс для массива game.game_ids
 /// `game_ids[ServerId]` — последний game_id этого сервера (такой что .next_game_id == None)
+/// ServerId сервера, поглощённого при ручном объединении (см. `overrides`), указывает на первую игру этого сервера,
+/// а её server_id — на объединённый сервер
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct ServerId(NonZeroU32);
 
@@ -78,6 +87,15 @@ pub struct PlayerInterval {
     pub end: Option<TimeMinutes>,
 }
 
+/// полуинтервал [begin, end) минут, за которые нет ответов /get-games
+/// (запрос завершился ошибкой или процесс был перезапущен)
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ObservationGap {
+    pub begin: TimeMinutes,
+    pub end: TimeMinutes,
+}
+
 impl PlayerInterval {
     pub fn new(player_index: BigStringPart, begin: TimeMinutes) -> Self {
         PlayerInterval {
@@ -88,6 +106,47 @@ impl PlayerInterval {
     }
 }
 
+/// состояние запроса /get-game-details для игры
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[repr(u8)]
+pub enum DetailsStatus {
+    NotFetched,
+    Fetched,
+    // /get-game-details вернул 404 (обычно игра уже завершилась) или много раз подряд завершился ошибкой
+    Unavailable,
+    // игра исчезла раньше чем через GET_GAME_DETAILS_DELAY минут, запрос не выполнялся
+    // (см. `fetcher_get_game_details::get_new_game_decision`)
+    Skipped,
+}
+
+/// сборка игры (одинаковая у большого числа игр, поэтому хранится один раз в `State::builds`)
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameBuild {
+    pub build_version: u32,
+    // например "headless", "steam", "alpha"
+    pub build_mode: String,
+    // например "linux64", "win64"
+    pub platform: String,
+}
+
+/// `State::builds[BuildIndex - 1]`
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BuildIndex(NonZeroU32);
+
+/// поля /get-game-details, которые есть не у всех игр и нужны только для отображения,
+/// поэтому хранятся не в `Game`, а в `State::details_extra` (None если не получены или отсутствуют в ответе)
+#[derive(Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameDetailsExtra {
+    pub steam_id: Option<BigStringPart>,
+    pub mods_crc: Option<u64>,
+    pub require_user_verification: Option<bool>,
+    // на момент последнего запроса /get-game-details
+    pub last_heartbeat: Option<TimeMinutes>,
+}
+
 // содержит всю информацию об одной сессии сервера (одна сессия == один game_id)
 // в течении сессии метаинформация о сервере (название, версия, моды и т.д.) не должны меняться
 // ожидается, что сессия длится непрерывный отрезок по времени
@@ -114,17 +173,21 @@ pub struct Game {
     pub description: BigStringPart,
     pub max_players: u32,
     pub game_version: BigStringPart,
+    // None для игр, сохранённых до того как сборка стала запоминаться
+    pub build: Option<BuildIndex>,
     pub game_time_elapsed: u32,
     pub has_password: bool,
     // разделённые символом \x02
     pub tags: BigStringPart,
     pub mod_count: u16,
+    pub details_status: DetailsStatus,
 
-    // None означает что значение ещё не получено (с помощью запроса на /get-game-details)
+    // None означает что значение ещё не получено (с помощью запроса на /get-game-details) или не будет получено (см. details_status)
     pub host_address: Option<BigStringPart>,
     // None означает что значение ещё не получено или что такое же как у prev_game_id
     // todo: "такое же как у prev_game_id"
     pub mods: Option<Vec<Mod>>,
+    // остальные поля /get-game-details хранятся в `State::details_extra`
 }
 
 impl Game {
@@ -192,8 +255,25 @@ impl Game {
             .sum()
     }
 
+    /// как `total_player_minutes`, но учитывается только пересечение интервалов игроков с [begin, end)
+    /// (незавершённые интервалы считаются продолжающимися до `end`)
+    pub fn player_minutes_in_interval(&self, begin: TimeMinutes, end: TimeMinutes) -> u64 {
+        self.players_intervals.iter()
+            .map(|player_interval| {
+                let interval_begin = player_interval.begin.max(begin);
+                let interval_end = player_interval.end.map_or(end, |interval_end| interval_end.min(end));
+                interval_end.get().saturating_sub(interval_begin.get()) as u64
+            })
+            .sum()
+    }
+
     pub fn are_details_fetched(&self) -> bool {
-        self.host_address.is_some()
+        self.details_status == DetailsStatus::Fetched
+    }
+
+    /// details получены или не будут запрашиваться
+    pub fn are_details_resolved(&self) -> bool {
+        self.details_status != DetailsStatus::NotFetched
     }
 
     pub fn prev_game<'a>(&self, state: &'a State) -> Option<&'a Game> {
@@ -205,7 +285,11 @@ impl Game {
     }
 
     pub fn get_mods<'a>(&'a self, state: &'a State) -> &'a Option<Vec<Mod>> {
-        if !self.are_details_fetched() { return &None; }
+        match self.details_status {
+            DetailsStatus::Fetched => {}
+            // моды неизвестны, не берём их у prev_game_id
+            DetailsStatus::NotFetched | DetailsStatus::Unavailable | DetailsStatus::Skipped => return &None,
+        }
         match (&self.mods, self.prev_game_id) {
             (mods @ Some(_), _) => mods,
             (None, Some(prev_game_id)) => {
@@ -265,6 +349,26 @@ pub struct State {
     pub game_ids: Vec<GameId>,
     // game_id из last_get_games_response
     pub current_game_ids: Vec<GameId>,
+    // время last_get_games_response
+    pub last_observed_time: Option<TimeMinutes>,
+    // упорядочены по времени
+    pub observation_gaps: Vec<ObservationGap>,
+    // в порядке применения
+    pub server_overrides: Vec<overrides::ServerOverride>,
+    // изменения метаинформации игр в порядке времени (для большинства игр изменений нет)
+    pub metadata_changes: HashMap<GameId, Vec<metadata::MetadataChange>>,
+    // изменения модов и адреса игр (см. `fetcher_get_game_details::Priority::Refetch`)
+    pub details_revisions: HashMap<GameId, Vec<metadata::DetailsRevision>>,
+    // см. `GameDetailsExtra`, только для игр у которых есть хотя бы одно из полей
+    pub details_extra: HashMap<GameId, GameDetailsExtra>,
+    // различные сборки игр в порядке появления (см. `Game::build`)
+    pub builds: Vec<GameBuild>,
+    // число игр и игроков онлайн по всем серверам
+    pub global_online: global_online::GlobalOnline,
+
+    // строится после загрузки (см. `State::rebuild_players_index`)
+    #[serde(skip)]
+    pub players_index: players_index::PlayersIndex,
 
     pub all_game_names: BigString,
     pub all_game_descriptions: BigString,
@@ -273,6 +377,7 @@ pub struct State {
     pub all_host_addresses: BigString,
     pub all_mod_names: BigString,
     pub all_player_names: BigString,
+    pub all_steam_ids: BigString,
 }
 
 impl State {
@@ -294,15 +399,24 @@ impl State {
         self.get_game_name(game_id)
     }
 
+    /// ServerId, поглощённый при ручном объединении, заменяется объединённым
     pub fn as_server_id(&self, id: usize) -> Option<ServerId> {
         if 1 <= id && id < self.game_ids.len() {
-            Some(ServerId(NonZeroU32::new(id as u32).unwrap()))
+            let server_id = ServerId(NonZeroU32::new(id as u32).unwrap());
+            Some(self.get_game(self.game_ids[id]).server_id.unwrap_or(server_id))
         } else {
             None
         }
     }
 
+    /// все ServerId, кроме поглощённых при ручном объединении
+    pub fn get_server_ids(&self) -> impl Iterator<Item=ServerId> + '_ {
+        (1..self.game_ids.len())
+            .filter_map(move |id| self.as_server_id(id).filter(|server_id| server_id.0.get() as usize == id))
+    }
+
     pub fn get_server_last_game_id(&self, id: ServerId) -> GameId {
+        let id = self.as_server_id(id.0.get() as usize).unwrap();
         self.game_ids[id.0.get() as usize].clone()
     }
 
@@ -343,6 +457,43 @@ impl State {
         game_ids
     }
 
+    // [time_begin, time_end)
+    pub fn get_observation_gaps_in_interval(&self, time_begin: TimeMinutes, time_end: TimeMinutes) -> Vec<ObservationGap> {
+        self.observation_gaps.iter()
+            .filter(|gap| gap.begin < time_end && time_begin < gap.end)
+            .copied()
+            .collect()
+    }
+
+    /// время, которым закрываются интервалы игр и игроков, исчезнувших к моменту `time`:
+    /// следующая минута после последнего успешного наблюдения
+    /// (если между наблюдениями был пропуск, то не приписываем его игрокам)
+    pub fn get_closing_time(&self, time: TimeMinutes) -> TimeMinutes {
+        match self.last_observed_time {
+            Some(last_observed_time) if last_observed_time < time => TimeMinutes::new(last_observed_time.get() + 1).unwrap(),
+            _ => time,
+        }
+    }
+
+    pub fn get_game_details_extra(&self, id: GameId) -> Option<&GameDetailsExtra> {
+        self.details_extra.get(&id)
+    }
+
+    pub fn get_build(&self, index: BuildIndex) -> &GameBuild {
+        &self.builds[index.0.get() as usize - 1]
+    }
+
+    pub fn add_build(&mut self, build: GameBuild) -> BuildIndex {
+        let index = match self.builds.iter().position(|existing_build| *existing_build == build) {
+            Some(index) => index,
+            None => {
+                self.builds.push(build);
+                self.builds.len() - 1
+            }
+        };
+        BuildIndex(NonZeroU32::new(index as u32 + 1).unwrap())
+    }
+
     fn get_game_host(&self, id: GameId) -> Option<&str> {
         let game = self.get_game(id);
         game.host_address.map(|host_address| self.all_host_addresses.get(host_address).into())
@@ -356,6 +507,7 @@ impl State {
         self.all_host_addresses.set_debug_name("host_addresses".to_owned());
         self.all_mod_names.set_debug_name("mod_names".to_owned());
         self.all_player_names.set_debug_name("player_names".to_owned());
+        self.all_steam_ids.set_debug_name("steam_ids".to_owned());
     }
 
     pub fn compress(&mut self) {
@@ -373,6 +525,7 @@ impl State {
         let map_host_addresses = self.all_host_addresses.compress();
         let map_mod_names = self.all_mod_names.compress();
         let map_player_names = self.all_player_names.compress();
+        let map_steam_ids = self.all_steam_ids.compress();
         for game in self.games.values_mut() {
             game.name = *map_names.get(&game.name).unwrap();
             game.description = *map_descriptions.get(&game.description).unwrap();
@@ -393,6 +546,38 @@ impl State {
                 players_interval.player_index = *map_player_names.get(&players_interval.player_index).unwrap();
             }
         }
+        self.players_index.remap_player_names(&map_player_names);
+
+        for details_extra in self.details_extra.values_mut() {
+            if let Some(ref mut steam_id) = details_extra.steam_id {
+                *steam_id = *map_steam_ids.get(steam_id).unwrap();
+            }
+        }
+
+        let map_metadata_value = |value: &mut MetadataValue| match value {
+            MetadataValue::Name(name) => *name = *map_names.get(name).unwrap(),
+            MetadataValue::Description(description) => *description = *map_descriptions.get(description).unwrap(),
+            MetadataValue::GameVersion(game_version) => *game_version = *map_versions.get(game_version).unwrap(),
+            MetadataValue::Tags(tags) => *tags = *map_tags.get(tags).unwrap(),
+            MetadataValue::MaxPlayers(_) | MetadataValue::HasPassword(_) => {}
+        };
+        for change in self.metadata_changes.values_mut().flatten() {
+            map_metadata_value(&mut change.old_value);
+            map_metadata_value(&mut change.new_value);
+        }
+
+        for revision in self.details_revisions.values_mut().flatten() {
+            if let Some((old_host_address, new_host_address)) = &mut revision.host_address {
+                *old_host_address = *map_host_addresses.get(old_host_address).unwrap();
+                *new_host_address = *map_host_addresses.get(new_host_address).unwrap();
+            }
+            if let Some((old_mods, new_mods)) = &mut revision.mods {
+                for mod_ in old_mods.iter_mut().chain(new_mods.iter_mut()) {
+                    mod_.name = *map_mod_names.get(&mod_.name).unwrap();
+                    mod_.version = *map_versions.get(&mod_.version).unwrap();
+                }
+            }
+        }
     }
 
     fn compress_mods(&mut self) {
@@ -435,6 +620,10 @@ impl State {
         let id1 = GameId::new(7663758).unwrap();
         let id2 = GameId::new(7664718).unwrap();
         let id3 = GameId::new(7673117).unwrap();
+        // например в тестах
+        if !self.games.contains_key(&id1) {
+            return;
+        }
         self.get_game_mut(id1).prev_game_id = None;
         self.get_game_mut(id1).next_game_id = Some(id2);
         self.get_game_mut(id2).prev_game_id = Some(id1);
@@ -463,6 +652,6 @@ mod tests {
         use std::mem::size_of;
         assert_eq!(size_of::<PlayerInterval>(), 12);
         assert_eq!(size_of::<Option<ServerId>>(), 4);
-        assert_eq!(size_of::<Game>(), 136);
+        assert_eq!(size_of::<Game>(), 144);
     }
 }
@@ -0,0 +1,262 @@
+//! Временной ряд числа игроков онлайн (для графиков), вычисляется из `players_intervals`
+//! Минуты без наблюдений (см. `State::observation_gaps`) не учитываются при агрегации
+//! Ряд хранится отрезками с постоянным числом игроков (см. [OnlineRun]), а не по минутам,
+//! поэтому время и память не зависят от длины запрашиваемого интервала
+
+use crate::state::{GameId, ServerId, State, TimeMinutes};
+
+// ограничение на число точек в ответе (если resolution не указан или слишком мал)
+pub const DEFAULT_NUMBER_POINTS: u32 = 1000;
+pub const MAX_NUMBER_POINTS: u32 = 10000;
+// ограничение на длину запрашиваемого интервала
+pub const MAX_INTERVAL_LENGTH: u32 = 2 * 366 * 24 * 60;
+
+/// число игроков онлайн в каждую минуту [begin, end) (в минутах), None если минуты не наблюдались
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct OnlineRun {
+    pub begin: u32,
+    pub end: u32,
+    pub online: Option<u32>,
+}
+
+/// агрегация числа игроков онлайн по минутам [time, time + resolution)
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct OnlinePoint {
+    pub time: TimeMinutes,
+    pub min: u32,
+    pub max: u32,
+    pub avg: f32,
+}
+
+struct OnlinePointBuilder {
+    // номер точки от time_begin
+    index: u32,
+    min: u32,
+    max: u32,
+    sum: u64,
+    number_minutes: u32,
+}
+
+impl OnlinePointBuilder {
+    fn new(index: u32) -> Self {
+        OnlinePointBuilder { index, min: u32::MAX, max: 0, sum: 0, number_minutes: 0 }
+    }
+
+    fn add(&mut self, online: u32, number_minutes: u32) {
+        self.min = self.min.min(online);
+        self.max = self.max.max(online);
+        self.sum += online as u64 * number_minutes as u64;
+        self.number_minutes += number_minutes;
+    }
+
+    fn build(&self, time_begin: TimeMinutes, resolution: u32) -> OnlinePoint {
+        OnlinePoint {
+            time: TimeMinutes::new(time_begin.get() + self.index * resolution).unwrap(),
+            min: self.min,
+            max: self.max,
+            avg: self.sum as f32 / self.number_minutes as f32,
+        }
+    }
+}
+
+/// resolution (в минутах), при котором число точек не превышает `number_points`
+pub fn get_resolution(time_begin: TimeMinutes, time_end: TimeMinutes, number_points: u32) -> u32 {
+    let length = time_end.get() - time_begin.get();
+    ((length + number_points - 1) / number_points).max(1)
+}
+
+/// `runs` упорядочены по времени и начинаются не раньше `time_begin`
+/// точки без наблюдаемых минут пропускаются
+pub fn aggregate_online(runs: &[OnlineRun], time_begin: TimeMinutes, resolution: u32) -> Vec<OnlinePoint> {
+    assert!(resolution > 0);
+    let begin = time_begin.get();
+    let mut points = Vec::new();
+    let mut current_point: Option<OnlinePointBuilder> = None;
+    for run in runs {
+        let online = match run.online {
+            Some(online) => online,
+            None => continue,
+        };
+        let mut minute = run.begin;
+        while minute < run.end {
+            let index = (minute - begin) / resolution;
+            let point_end = (begin + (index + 1) * resolution).min(run.end);
+            if current_point.as_ref().map_or(true, |point| point.index != index) {
+                points.extend(current_point.take().map(|point| point.build(time_begin, resolution)));
+                current_point = Some(OnlinePointBuilder::new(index));
+            }
+            current_point.as_mut().unwrap().add(online, point_end - minute);
+            minute = point_end;
+        }
+    }
+    points.extend(current_point.map(|point| point.build(time_begin, resolution)));
+    points
+}
+
+/// среднее число игроков онлайн для каждой пары (день недели, час), дни недели начиная с понедельника
+/// `tz_offset` — смещение часового пояса в минутах (например 180 для UTC+3)
+/// None для пар без наблюдаемых минут
+pub fn get_weekly_heatmap(runs: &[OnlineRun], tz_offset: i32) -> Vec<Vec<Option<f32>>> {
+    const DAY: i64 = 24 * 60;
+    // 1 января 1970 — четверг
+    const EPOCH_WEEKDAY: i64 = 3;
+
+    let mut sums = vec![[0u64; 24]; 7];
+    let mut counts = vec![[0u32; 24]; 7];
+    for run in runs {
+        let online = match run.online {
+            Some(online) => online,
+            None => continue,
+        };
+        // отрезок разбивается по границам часов (в местном времени)
+        let mut local_time = run.begin as i64 + tz_offset as i64;
+        let local_end = run.end as i64 + tz_offset as i64;
+        while local_time < local_end {
+            let hour_end = (local_time.div_euclid(60) + 1) * 60;
+            let number_minutes = (hour_end.min(local_end) - local_time) as u32;
+            let weekday = (local_time.div_euclid(DAY) + EPOCH_WEEKDAY).rem_euclid(7) as usize;
+            let hour = (local_time.rem_euclid(DAY) / 60) as usize;
+            sums[weekday][hour] += online as u64 * number_minutes as u64;
+            counts[weekday][hour] += number_minutes;
+            local_time = hour_end;
+        }
+    }
+
+    sums.iter().zip(counts.iter())
+        .map(|(sums, counts)| {
+            sums.iter().zip(counts.iter())
+                .map(|(&sum, &count)| if count == 0 { None } else { Some(sum as f32 / count as f32) })
+                .collect()
+        })
+        .collect()
+}
+
+impl State {
+    /// число игроков онлайн (суммарно по играм `game_ids`) на [time_begin, time_end), упорядочено по времени
+    /// None для минут, которые попадают в пропуск наблюдений или ещё не наступили
+    pub fn get_online_runs(
+        &self,
+        game_ids: impl IntoIterator<Item=GameId>,
+        time_begin: TimeMinutes,
+        time_end: TimeMinutes,
+    ) -> Vec<OnlineRun> {
+        assert!(time_begin < time_end);
+        let begin = time_begin.get();
+        let end = time_end.get();
+        // минута last_observed_time ещё наблюдается, интервалы онлайн игроков закрываем следующей минутой
+        let observed_end = match self.last_observed_time {
+            Some(last_observed_time) => last_observed_time.get() + 1,
+            None => begin,
+        };
+
+        // (время, изменение числа игроков)
+        let mut events: Vec<(u32, i64)> = Vec::new();
+        for game_id in game_ids {
+            let game = self.get_game(game_id);
+            for interval in &game.players_intervals {
+                let interval_begin = interval.begin.get().max(begin);
+                let interval_end = interval.end.map_or(observed_end, TimeMinutes::get).min(end);
+                if interval_begin < interval_end {
+                    events.push((interval_begin, 1));
+                    events.push((interval_end, -1));
+                }
+            }
+        }
+        events.sort_unstable();
+
+        let mut unobserved: Vec<(u32, u32)> = self.get_observation_gaps_in_interval(time_begin, time_end).iter()
+            .map(|gap| (gap.begin.get().max(begin), gap.end.get().min(end)))
+            .collect();
+        if observed_end < end {
+            unobserved.push((observed_end.max(begin), end));
+        }
+        unobserved.sort_unstable();
+
+        let mut boundaries: Vec<u32> = std::iter::once(begin)
+            .chain(events.iter().map(|&(time, _)| time))
+            .chain(unobserved.iter().flat_map(|&(gap_begin, gap_end)| std::iter::once(gap_begin).chain(std::iter::once(gap_end))))
+            .filter(|&time| time < end)
+            .collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let mut runs: Vec<OnlineRun> = Vec::new();
+        let mut online = 0;
+        let mut event_index = 0;
+        let mut gap_index = 0;
+        for (index, &run_begin) in boundaries.iter().enumerate() {
+            let run_end = boundaries.get(index + 1).copied().unwrap_or(end);
+            while event_index < events.len() && events[event_index].0 <= run_begin {
+                online += events[event_index].1;
+                event_index += 1;
+            }
+            while gap_index < unobserved.len() && unobserved[gap_index].1 <= run_begin {
+                gap_index += 1;
+            }
+            let is_observed = unobserved.get(gap_index).map_or(true, |&(gap_begin, _)| run_begin < gap_begin);
+            let run_online = if is_observed { Some(online as u32) } else { None };
+            match runs.last_mut() {
+                Some(run) if run.online == run_online => run.end = run_end,
+                _ => runs.push(OnlineRun { begin: run_begin, end: run_end, online: run_online }),
+            }
+        }
+        runs
+    }
+
+    pub fn get_server_online_series(
+        &self,
+        server_id: ServerId,
+        time_begin: TimeMinutes,
+        time_end: TimeMinutes,
+        resolution: u32,
+    ) -> Vec<OnlinePoint> {
+        let game_ids = self.get_server_games_in_interval(server_id, time_begin, time_end);
+        let runs = self.get_online_runs(game_ids, time_begin, time_end);
+        aggregate_online(&runs, time_begin, resolution)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(begin: u32, end: u32, online: Option<u32>) -> OnlineRun {
+        OnlineRun { begin, end, online }
+    }
+
+    #[test]
+    fn aggregate() {
+        let time_begin = TimeMinutes::new(10).unwrap();
+        // 1, 3, None, None, None, 2
+        let runs = [run(10, 11, Some(1)), run(11, 12, Some(3)), run(12, 15, None), run(15, 16, Some(2))];
+        let points = aggregate_online(&runs, time_begin, 2);
+        assert_eq!(points, vec![
+            OnlinePoint { time: time_begin, min: 1, max: 3, avg: 2.0 },
+            OnlinePoint { time: TimeMinutes::new(14).unwrap(), min: 2, max: 2, avg: 2.0 },
+        ]);
+        assert_eq!(get_resolution(time_begin, TimeMinutes::new(2010).unwrap(), 1000), 2);
+        assert_eq!(get_resolution(time_begin, TimeMinutes::new(11).unwrap(), 1000), 1);
+
+        // один отрезок на несколько точек
+        let points = aggregate_online(&[run(11, 20, Some(4))], time_begin, 4);
+        let times: Vec<u32> = points.iter().map(|point| point.time.get()).collect();
+        assert_eq!(times, vec![10, 14, 18]);
+        assert!(points.iter().all(|point| point.min == 4 && point.max == 4 && point.avg == 4.0));
+    }
+
+    #[test]
+    fn weekly_heatmap() {
+        // Monday, 6 January 2020, 00:00 UTC
+        let monday = 26_304_480;
+        let runs = [run(monday, monday + 60, Some(4)), run(monday + 60, monday + 61, Some(2)), run(monday + 61, monday + 62, None)];
+        let heatmap = get_weekly_heatmap(&runs, 0);
+        assert_eq!(heatmap[0][0], Some(4.0));
+        assert_eq!(heatmap[0][1], Some(2.0));
+        assert_eq!(heatmap[0][2], None);
+
+        // UTC-1: Sunday 23:00
+        let heatmap = get_weekly_heatmap(&runs, -60);
+        assert_eq!(heatmap[6][23], Some(4.0));
+        assert_eq!(heatmap[0][0], Some(2.0));
+    }
+}
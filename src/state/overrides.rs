@@ -0,0 +1,136 @@
+//! Ручные исправления объединения игр в серверы (эвристики в `updater::try_merge_host` иногда ошибаются)
+//! Исправления хранятся в терминах GameId (а не ServerId),
+//! чтобы их можно было применить заново к State, построенному с помощью replay
+//! При объединении ServerId второго сервера не удаляется, а указывает на его первую игру (см. `State::as_server_id`),
+//! поэтому разделение по этой игре возвращает ему прежний ServerId
+
+use serde::{Deserialize, Serialize};
+
+use std::num::NonZeroU32;
+
+use crate::state::{GameId, ServerId, State};
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ServerOverride {
+    /// объединить серверы, которым принадлежат игры `game_id_a` и `game_id_b`
+    #[serde(rename_all = "camelCase")]
+    Merge { game_id_a: GameId, game_id_b: GameId },
+    /// разделить сервер так, что `game_id` станет первой игрой нового сервера
+    #[serde(rename_all = "camelCase")]
+    Split { game_id: GameId },
+}
+
+impl State {
+    /// применяет исправление и сохраняет его в `server_overrides`
+    pub fn add_server_override(&mut self, server_override: ServerOverride) -> Result<(), String> {
+        match server_override {
+            ServerOverride::Merge { game_id_a, game_id_b } => self.merge_servers(game_id_a, game_id_b)?,
+            ServerOverride::Split { game_id } => self.split_server(game_id)?,
+        }
+        println!("[info]  [state] applied server override {:?}", server_override);
+        self.server_overrides.push(server_override);
+        Ok(())
+    }
+
+    /// например после replay
+    pub fn add_server_overrides(&mut self, server_overrides: Vec<ServerOverride>) {
+        for server_override in server_overrides {
+            let description = format!("{:?}", server_override);
+            if let Err(err) = self.add_server_override(server_override) {
+                eprintln!("[warn]  [state] can't apply server override {}: {}", description, err);
+            }
+        }
+    }
+
+    fn get_chain_first_game_id(&self, mut game_id: GameId) -> GameId {
+        while let Some(prev_game_id) = self.get_game(game_id).prev_game_id {
+            game_id = prev_game_id;
+        }
+        game_id
+    }
+
+    fn get_chain_last_game_id(&self, mut game_id: GameId) -> GameId {
+        while let Some(next_game_id) = self.get_game(game_id).next_game_id {
+            game_id = next_game_id;
+        }
+        game_id
+    }
+
+    fn check_game_has_server(&self, game_id: GameId) -> Result<(), String> {
+        match self.games.get(&game_id) {
+            None => Err(format!("unknown game {}", game_id)),
+            Some(game) if game.server_id.is_none() => Err(format!("game {} is not merged into server yet", game_id)),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn merge_servers(&mut self, game_id_a: GameId, game_id_b: GameId) -> Result<(), String> {
+        self.check_game_has_server(game_id_a)?;
+        self.check_game_has_server(game_id_b)?;
+        let first_game_id_a = self.get_chain_first_game_id(game_id_a);
+        let first_game_id_b = self.get_chain_first_game_id(game_id_b);
+        if first_game_id_a == first_game_id_b {
+            return Err("games already belong to the same server".to_owned());
+        }
+
+        // сервер, который появился раньше, становится началом объединённого сервера
+        let (head_first_game_id, tail_first_game_id) = if first_game_id_a < first_game_id_b {
+            (first_game_id_a, first_game_id_b)
+        } else {
+            (first_game_id_b, first_game_id_a)
+        };
+        let head_last_game_id = self.get_chain_last_game_id(head_first_game_id);
+        let tail_last_game_id = self.get_chain_last_game_id(tail_first_game_id);
+        if head_last_game_id >= tail_first_game_id || self.get_game(head_last_game_id).time_end.is_none() {
+            return Err("servers overlap in time".to_owned());
+        }
+
+        let server_id = self.get_game(head_last_game_id).server_id.unwrap();
+        let tail_server_id = self.get_game(tail_first_game_id).server_id.unwrap();
+        self.get_game_mut(head_last_game_id).next_game_id = Some(tail_first_game_id);
+        self.get_game_mut(tail_first_game_id).prev_game_id = Some(head_last_game_id);
+
+        self.game_ids[server_id.0.get() as usize] = tail_last_game_id;
+        self.game_ids[tail_server_id.0.get() as usize] = tail_first_game_id;
+        let server_id = Some(server_id);
+        let mut game_id = Some(tail_first_game_id);
+        while let Some(id) = game_id {
+            let game = self.get_game_mut(id);
+            game.server_id = server_id;
+            game_id = game.next_game_id;
+        }
+        Ok(())
+    }
+
+    fn split_server(&mut self, game_id: GameId) -> Result<(), String> {
+        self.check_game_has_server(game_id)?;
+        let prev_game_id = self.get_game(game_id).prev_game_id
+            .ok_or_else(|| format!("game {} is already the first game of server", game_id))?;
+        let last_game_id = self.get_chain_last_game_id(game_id);
+        let prev_server_id = self.get_game(game_id).server_id.unwrap();
+
+        self.get_game_mut(prev_game_id).next_game_id = None;
+        self.get_game_mut(game_id).prev_game_id = None;
+        self.game_ids[prev_server_id.0.get() as usize] = prev_game_id;
+
+        // ServerId, который был у отделяемой части до объединения
+        let merged_server_id = (1..self.game_ids.len()).find(|&id| self.game_ids[id] == game_id);
+        let server_id = match merged_server_id {
+            Some(id) => id,
+            None => {
+                self.game_ids.push(game_id);
+                self.game_ids.len() - 1
+            }
+        };
+        self.game_ids[server_id] = last_game_id;
+        let server_id = ServerId(NonZeroU32::new(server_id as u32).unwrap());
+        let mut game_id = Some(game_id);
+        while let Some(id) = game_id {
+            let game = self.get_game_mut(id);
+            game.server_id = Some(server_id);
+            game_id = game.next_game_id;
+        }
+        Ok(())
+    }
+}
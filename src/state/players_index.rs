@@ -0,0 +1,133 @@
+//! Индекс игроков: для каждого имени игрока — все его сессии (PlayerInterval) на всех серверах
+//! Не сериализуется: строится заново после загрузки State и поддерживается updater'ом (см. `updater::update_game`)
+//! Имена не копируются: игрок хранится как BigStringPart из `State::all_player_names`, а ключ — хеш имени
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+use hashbrown::HashMap;
+
+use crate::state::{BigString, BigStringPart, GameId, normalize_byte, State, TimeMinutes};
+
+/// PlayerInterval игрока в игре `game_id`
+/// (индекс в `players_intervals` не подходит, так как updater переставляет интервалы)
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PlayerSession {
+    pub game_id: GameId,
+    pub begin: TimeMinutes,
+    // None если игрок всё ещё онлайн (см. [PlayersIndex::end_session])
+    pub end: Option<TimeMinutes>,
+}
+
+#[derive(Eq, PartialEq)]
+struct PlayerEntry {
+    // одно из вхождений имени игрока в all_player_names
+    player_index: BigStringPart,
+    // сессии в порядке добавления (то есть примерно по возрастанию begin)
+    sessions: Vec<PlayerSession>,
+}
+
+#[derive(Default, Eq, PartialEq)]
+pub struct PlayersIndex {
+    // хеш имени → игрок
+    // при коллизии хешей игрок добавляется по следующему свободному ключу (hash + 1, ...),
+    // игроки не удаляются, поэтому поиск идёт по ключам подряд до первого свободного
+    players: HashMap<u64, PlayerEntry>,
+}
+
+// имя хешируется в том виде, в котором оно хранится в BigString
+fn hash_player_name(player_name: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for &byte in player_name {
+        hasher.write_u8(normalize_byte(byte));
+    }
+    hasher.finish()
+}
+
+impl PlayersIndex {
+    // (ключ, найден ли игрок), если не найден — первый свободный ключ
+    fn find_key(&self, all_player_names: &BigString, player_name: &[u8]) -> (u64, bool) {
+        let mut key = hash_player_name(player_name);
+        while let Some(entry) = self.players.get(&key) {
+            if all_player_names.part_equals(entry.player_index, player_name.iter().copied()) {
+                return (key, true);
+            }
+            key = key.wrapping_add(1);
+        }
+        (key, false)
+    }
+
+    pub fn add_session(
+        &mut self,
+        all_player_names: &BigString,
+        player_index: BigStringPart,
+        game_id: GameId,
+        begin: TimeMinutes,
+        end: Option<TimeMinutes>,
+    ) {
+        let (key, _) = self.find_key(all_player_names, all_player_names.get(player_index).0);
+        let entry = self.players.entry(key).or_insert_with(|| PlayerEntry { player_index, sessions: Vec::new() });
+        entry.sessions.push(PlayerSession { game_id, begin, end });
+    }
+
+    /// игрок вышел из игры `game_id` (или игра завершилась)
+    pub fn end_session(&mut self, all_player_names: &BigString, player_index: BigStringPart, game_id: GameId, end: TimeMinutes) {
+        let (key, found) = self.find_key(all_player_names, all_player_names.get(player_index).0);
+        if !found {
+            return;
+        }
+        let session = self.players.get_mut(&key).unwrap().sessions.iter_mut().rev()
+            .find(|session| session.game_id == game_id && session.end.is_none());
+        if let Some(session) = session {
+            session.end = Some(end);
+        }
+    }
+
+    pub fn get_sessions(&self, all_player_names: &BigString, player_name: &str) -> &[PlayerSession] {
+        match self.find_key(all_player_names, player_name.as_bytes()) {
+            (key, true) => &self.players[&key].sessions,
+            (_, false) => &[],
+        }
+    }
+
+    /// ключ игрока в индексе, одинаковый для всех вхождений имени в all_player_names
+    pub fn get_player_key(&self, all_player_names: &BigString, player_index: BigStringPart) -> Option<u64> {
+        match self.find_key(all_player_names, all_player_names.get(player_index).0) {
+            (key, true) => Some(key),
+            (_, false) => None,
+        }
+    }
+
+    pub fn number_players(&self) -> usize {
+        self.players.len()
+    }
+
+    /// после `BigString::compress` для all_player_names (хеши имён не меняются)
+    pub fn remap_player_names(&mut self, map_player_names: &HashMap<BigStringPart, BigStringPart>) {
+        for entry in self.players.values_mut() {
+            entry.player_index = *map_player_names.get(&entry.player_index).unwrap();
+        }
+    }
+}
+
+impl State {
+    pub fn rebuild_players_index(&mut self) {
+        let mut players_index = PlayersIndex::default();
+        let mut game_ids: Vec<GameId> = self.games.keys().copied().collect();
+        game_ids.sort();
+        for game_id in game_ids {
+            let game = self.get_game(game_id);
+            let mut players_intervals: Vec<_> = game.players_intervals.iter().collect();
+            players_intervals.sort_by_key(|interval| interval.begin);
+            for interval in players_intervals {
+                players_index.add_session(&self.all_player_names, interval.player_index, game_id, interval.begin, interval.end);
+            }
+        }
+        println!("[info]  [state] players index is built: {} players", players_index.number_players());
+        self.players_index = players_index;
+    }
+
+    pub fn get_player_sessions(&self, player_name: &str) -> &[PlayerSession] {
+        self.players_index.get_sessions(&self.all_player_names, player_name)
+    }
+}
@@ -5,14 +5,15 @@ use std::sync::{Arc, mpsc};
 use std::time::Duration;
 
 use hashbrown::{HashMap, HashSet};
-use itertools::Itertools;
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 
 use crate::api;
+use crate::fetcher_get_game_details::{Priority, Request};
 use crate::fetcher_get_games::FetcherOutput;
 use crate::global_config::GLOBAL_CONFIG;
-use crate::state::{Game, GameId, HostId, PlayerInterval, State, StateLock, TimeMinutes};
+use crate::state::{BigString, BigStringPart, DetailsStatus, Game, GameBuild, GameId, HostId, ObservationGap, PlayerInterval, State, StateLock, TimeMinutes};
+use crate::state::metadata::{MetadataChange, MetadataValue};
 
 //impl From<api::Mod> for Mod {
 //    fn from(v: api::Mod) -> Self {
@@ -34,9 +35,50 @@ use crate::state::{Game, GameId, HostId, PlayerInterval, State, StateLock, TimeM
 #[derive(Eq, PartialEq, Serialize, Deserialize)]
 pub struct UpdaterState {
     pub scheduled_to_merge_host_ids: HashMap<HostId, HostIdMergeInfo>,
+    // для обработки приостановки (см. README), записи не удаляются, так как приостановка может длиться сколько угодно
+    pub hosts_history: HashMap<HostId, HostHistory>,
+    // время следующего повторного запроса /get-game-details для текущих игр
+    pub details_refetch_times: HashMap<GameId, TimeMinutes>,
 }
 
 pub const HOST_ID_MERGE_DELAY: u32 = 20;  // in minutes
+// сколько хосту должен соответствовать ≤1 game_id, чтобы он считался подходящим для приостановки
+pub const SUSPENSION_SINGLE_GAME_DURATION: u32 = 24 * 60;  // in minutes
+
+#[derive(Eq, PartialEq, Serialize, Deserialize)]
+pub struct HostHistory {
+    pub last_game_id: GameId,
+    // начало периода, в течение которого хосту соответствует ≤1 game_id
+    // (наложения при перезапуске короче HOST_ID_MERGE_DELAY не учитываются)
+    pub single_game_since: Option<TimeMinutes>,
+    // начало текущего наложения (хосту соответствует >1 game_id)
+    pub multi_game_since: Option<TimeMinutes>,
+    // последний раз когда хост был онлайн
+    pub last_seen: TimeMinutes,
+}
+
+impl HostHistory {
+    fn is_suitable_for_suspension(&self) -> bool {
+        match self.single_game_since {
+            Some(single_game_since) => self.last_seen.get() - single_game_since.get() >= SUSPENSION_SINGLE_GAME_DURATION,
+            None => false,
+        }
+    }
+}
+
+fn join_tags(tags: &[String]) -> String {
+    String::from_utf8(join_tags_bytes(tags).collect()).unwrap()
+}
+
+// байты `join_tags`, теги разделены \x02
+fn join_tags_bytes(tags: &[String]) -> impl Iterator<Item = u8> + '_ {
+    tags.iter()
+        .enumerate()
+        .flat_map(|(index, tag)| {
+            let separator = if index == 0 { None } else { Some(b'\x02') };
+            separator.into_iter().chain(tag.bytes().map(|byte| if byte == b'\x02' { b'\x01' } else { byte }))
+        })
+}
 
 fn convert_snapshot_to_game(game_snapshot: &api::Game, state: &mut State, time: TimeMinutes) -> Game {
     // host_id.unwrap() можно делать, потому что в api::get_games игры без host_id удаляются
@@ -45,13 +87,18 @@ fn convert_snapshot_to_game(game_snapshot: &api::Game, state: &mut State, time:
     assert_eq!(host_id.len(), 32);
     let host_id = host_id.deref().try_into().clone().unwrap();
 
-    let tags = game_snapshot.tags.iter()
-        .map(|tag| tag.replace('\x02', "\x01"))
-        .join("\x02");
+    let tags = join_tags(&game_snapshot.tags);
+    let application_version = &game_snapshot.application_version;
+    let build = GameBuild {
+        build_version: application_version.build_version,
+        build_mode: application_version.build_mode.clone(),
+        platform: application_version.platform.clone(),
+    };
 
     let players_intervals = game_snapshot.players.iter()
         .map(|player| {
             let player_id = state.all_player_names.add(player);
+            state.players_index.add_session(&state.all_player_names, player_id, game_snapshot.game_id, time, None);
             PlayerInterval::new(player_id, time)
         }).collect();
 
@@ -68,25 +115,81 @@ fn convert_snapshot_to_game(game_snapshot: &api::Game, state: &mut State, time:
         description: state.all_game_descriptions.add(&game_snapshot.description),
         max_players: game_snapshot.max_players,
         game_version: state.all_versions.add(&game_snapshot.application_version.game_version),
+        build: Some(state.add_build(build)),
         game_time_elapsed: game_snapshot.game_time_elapsed,
         has_password: game_snapshot.has_password,
         tags: state.all_tags.add(&tags),
         mod_count: game_snapshot.mod_count.unwrap_or(0),
+        details_status: DetailsStatus::NotFetched,
         host_address: None,
         mods: None,
     }
 }
 
-#[allow(warnings)]
-fn check_game_match_snapshot(state: &State, game: &Game, game_snapshot: &api::Game) {
-    // todo
-//    check_field_equal(game_prev.host_id, game.host_id, "host_id");
-//    check_field_equal(game_prev., game., "");
-    // name max_players game_version
+// None если значение не изменилось
+// если значение вернулось к одному из `prev_values` (например, сервер чередует несколько описаний), строка не добавляется заново
+fn check_big_string_field_match(big_string: &mut BigString, value: BigStringPart, prev_values: &[BigStringPart], snapshot_value: &str) -> Option<BigStringPart> {
+    if big_string.part_equals(value, snapshot_value.bytes()) {
+        return None;
+    }
+    let prev_value = prev_values.iter().copied()
+        .find(|&prev_value| big_string.part_equals(prev_value, snapshot_value.bytes()));
+    Some(prev_value.unwrap_or_else(|| big_string.add(snapshot_value)))
+}
+
+// как `check_big_string_field_match`, но строка тегов создаётся только если они изменились
+fn check_tags_match(big_string: &mut BigString, value: BigStringPart, prev_values: &[BigStringPart], snapshot_tags: &[String]) -> Option<BigStringPart> {
+    if big_string.part_equals(value, join_tags_bytes(snapshot_tags)) {
+        return None;
+    }
+    let prev_value = prev_values.iter().copied()
+        .find(|&prev_value| big_string.part_equals(prev_value, join_tags_bytes(snapshot_tags)));
+    Some(prev_value.unwrap_or_else(|| big_string.add(&join_tags(snapshot_tags))))
 }
 
-fn update_game(game_snapshot: &api::Game, state: &mut State, time: TimeMinutes) {
-//    check_match_metainfo(&mut state, game, &game_snapshot);
+/// метаинформация игры может меняться во время игры (например, название или описание)
+/// запоминаем изменения и обновляем поля Game до последних значений
+fn check_game_match_snapshot(game_snapshot: &api::Game, state: &mut State, time: TimeMinutes) {
+    let game_id = game_snapshot.game_id;
+    let metadata = state.get_game(game_id).get_metadata();
+
+    let mut changes = Vec::new();
+    for &old_value in metadata.iter() {
+        let prev_values = state.get_game_metadata_prev_parts(game_id, old_value.field_index());
+        let new_value = match old_value {
+            MetadataValue::Name(name) =>
+                check_big_string_field_match(&mut state.all_game_names, name, &prev_values, &game_snapshot.name)
+                    .map(MetadataValue::Name),
+            MetadataValue::Description(description) =>
+                check_big_string_field_match(&mut state.all_game_descriptions, description, &prev_values, &game_snapshot.description)
+                    .map(MetadataValue::Description),
+            MetadataValue::GameVersion(game_version) =>
+                check_big_string_field_match(&mut state.all_versions, game_version, &prev_values, &game_snapshot.application_version.game_version)
+                    .map(MetadataValue::GameVersion),
+            MetadataValue::Tags(tags) =>
+                check_tags_match(&mut state.all_tags, tags, &prev_values, &game_snapshot.tags)
+                    .map(MetadataValue::Tags),
+            MetadataValue::MaxPlayers(max_players) => Some(MetadataValue::MaxPlayers(game_snapshot.max_players))
+                .filter(|_| max_players != game_snapshot.max_players),
+            MetadataValue::HasPassword(has_password) => Some(MetadataValue::HasPassword(game_snapshot.has_password))
+                .filter(|_| has_password != game_snapshot.has_password),
+        };
+        if let Some(new_value) = new_value {
+            changes.push(MetadataChange { time, old_value, new_value });
+        }
+    }
+    if changes.is_empty() { return; }
+
+    let game = state.get_game_mut(game_id);
+    for change in &changes {
+        game.set_metadata_value(change.new_value);
+    }
+    state.add_metadata_changes(game_id, changes);
+}
+
+// `closing_time` is used for players who left (see `State::get_closing_time`)
+fn update_game(game_snapshot: &api::Game, state: &mut State, time: TimeMinutes, closing_time: TimeMinutes) {
+    check_game_match_snapshot(game_snapshot, state, time);
 
     let game = state.games.get_mut(&game_snapshot.game_id).unwrap();
     game.game_time_elapsed = game_snapshot.game_time_elapsed;
@@ -110,7 +213,8 @@ fn update_game(game_snapshot: &api::Game, state: &mut State, time: TimeMinutes)
         if player_names.contains(player_name) {
             player_names.remove(player_name);
         } else {
-            player_interval.end = Some(time);
+            player_interval.end = Some(closing_time);
+            state.players_index.end_session(&state.all_player_names, player_interval.player_index, game.game_id, closing_time);
             game.players_intervals.swap(i, first_online_player_index);
             first_online_player_index += 1;
         }
@@ -121,6 +225,7 @@ fn update_game(game_snapshot: &api::Game, state: &mut State, time: TimeMinutes)
         let player_index = state.all_player_names.add_vec(&player_name);
         let player_interval = PlayerInterval::new(player_index, time);
         game.players_intervals.push(player_interval);
+        state.players_index.add_session(&state.all_player_names, player_index, game.game_id, time, None);
     }
 }
 
@@ -161,6 +266,16 @@ fn merge_games(curr_game_id: GameId, prev_game_id: Option<GameId>, state: &mut S
         }
     }
 
+    // prev_game.next_game_id может быть уже задан вручную (см. `State::add_server_override`)
+    let prev_game_id = prev_game_id.filter(|&prev_game_id| {
+        let prev_game_next_game_id = state.get_game(prev_game_id).next_game_id;
+        if prev_game_next_game_id.is_some() {
+            eprintln!("[warn]  [updater] Can't merge games {} and {}: prev game already has next game {:?}",
+                      prev_game_id, curr_game_id, prev_game_next_game_id);
+        }
+        prev_game_next_game_id.is_none()
+    });
+
     let server_id = match prev_game_id {
         Some(prev_game_id) if prev_game_id < curr_game_id => {
             println!(
@@ -173,11 +288,15 @@ fn merge_games(curr_game_id: GameId, prev_game_id: Option<GameId>, state: &mut S
             assert!(prev_game.time_end.is_some());
             // todo: что если prev_game.next_game_id != None (мб такое возможно при приостановке)
             prev_game.next_game_id = Some(curr_game_id);
+            let prev_game_server_id = prev_game.server_id;
             let curr_game = state.get_game_mut(curr_game_id);
             curr_game.prev_game_id = Some(prev_game_id);
 
-            let server_id = state.game_ids.iter()
-                .position(|&game_id| game_id == prev_game_id)
+            // на prev_game_id может указывать и ServerId, поглощённый при ручном объединении (см. `overrides`),
+            // поэтому по возможности берём server_id игры
+            let server_id = prev_game_server_id
+                .map(|server_id| server_id.0.get() as usize)
+                .or_else(|| state.game_ids.iter().position(|&game_id| game_id == prev_game_id))
                 // prev_game_id был добавлен в state.game_ids когда происходило объединение множеств {...} и {..., prev_game_id}
                 .unwrap_or_else(|| panic!("Can't find prev game: prev_game_id={}, curr_game_id={}", prev_game_id, curr_game_id));
             state.game_ids[server_id] = curr_game_id;
@@ -194,16 +313,17 @@ fn merge_games(curr_game_id: GameId, prev_game_id: Option<GameId>, state: &mut S
     state.get_game_mut(curr_game_id).server_id = Some(server_id);
 }
 
-fn update_finished_games(prev_game_ids_all: &HashSet<GameId>, curr_game_ids_all: &HashSet<GameId>, state: &mut State, time: TimeMinutes) {
+fn update_finished_games(prev_game_ids_all: &HashSet<GameId>, curr_game_ids_all: &HashSet<GameId>, state: &mut State, closing_time: TimeMinutes) {
     let removed_game_ids = prev_game_ids_all.difference(&curr_game_ids_all);
     for &removed_game_id in removed_game_ids {
-        let game = state.get_game_mut(removed_game_id);
-        game.time_end = Some(time);
+        let game = state.games.get_mut(&removed_game_id).unwrap();
+        game.time_end = Some(closing_time);
         for player_interval in game.players_intervals.iter_mut().rev() {
             if player_interval.end.is_some() {
                 break;
             }
-            player_interval.end = Some(time);
+            player_interval.end = Some(closing_time);
+            state.players_index.end_session(&state.all_player_names, player_interval.player_index, removed_game_id, closing_time);
         }
     }
 }
@@ -212,16 +332,17 @@ fn try_match_by_property(
     prev_game_ids_host: &Vec<GameId>,
     curr_game_ids_host: &Vec<GameId>,
     state: &mut State,
-    get_property: impl Fn(&GameId, &State) -> String,
+    // None если значение неизвестно (например у игры с пропущенными details), такие игры не сопоставляются
+    get_property: impl Fn(&GameId, &State) -> Option<String>,
 ) -> bool {
-    let prev_game_ids_by_property: HashMap<String, GameId> = prev_game_ids_host
+    let prev_game_ids_by_property: HashMap<Option<String>, GameId> = prev_game_ids_host
         .iter().map(|&game_id| (get_property(&game_id, state), game_id)).collect();
-    let curr_game_ids_by_property: HashMap<String, GameId> = curr_game_ids_host
+    let curr_game_ids_by_property: HashMap<Option<String>, GameId> = curr_game_ids_host
         .iter().map(|&game_id| (get_property(&game_id, state), game_id)).collect();
     // если все property уникальны
     if prev_game_ids_by_property.len() == prev_game_ids_host.len() && curr_game_ids_by_property.len() == curr_game_ids_host.len() {
         for (property_value, game_id) in curr_game_ids_by_property {
-            let prev_game_id = prev_game_ids_by_property.get(&property_value);
+            let prev_game_id = property_value.and_then(|property_value| prev_game_ids_by_property.get(&Some(property_value)));
             merge_games(game_id, prev_game_id.copied(), state);
         }
         true
@@ -238,12 +359,10 @@ fn try_merge_host(prev_game_ids_host: &[GameId], curr_game_ids_host: &[GameId],
     let prev_game_ids_host: Vec<GameId> = prev_game_ids_host.difference(&common_game_ids_host).copied().collect();
     let curr_game_ids_host: Vec<GameId> = curr_game_ids_host.difference(&common_game_ids_host).copied().collect();
 
-    // не объединяем game_ids пока не отправили запрос на /get-game-details
-    for &game_id in &prev_game_ids_host {
-        assert!(state.get_game(game_id).are_details_fetched());
-    }
-    for &game_id in &curr_game_ids_host {
-        if !state.get_game(game_id).are_details_fetched() {
+    // не объединяем game_ids пока не выполнили запрос на /get-game-details (или пока fetcher не решил его пропустить)
+    // prev game_ids тоже могут быть не получены, если игра исчезла вскоре после появления
+    for &game_id in prev_game_ids_host.iter().chain(&curr_game_ids_host) {
+        if !state.get_game(game_id).are_details_resolved() {
             return false;
         }
     }
@@ -255,8 +374,8 @@ fn try_merge_host(prev_game_ids_host: &[GameId], curr_game_ids_host: &[GameId],
             merge_games(game_id, None, state);
         }
     } else {
-        let get_game_name = |&game_id: &GameId, state: &State| state.get_game_name(game_id).to_owned();
-        let get_game_host = |&game_id: &GameId, state: &State| state.get_game_host(game_id).unwrap().to_owned();
+        let get_game_name = |&game_id: &GameId, state: &State| Some(state.get_game_name(game_id).to_owned());
+        let get_game_host = |&game_id: &GameId, state: &State| state.get_game_host(game_id).map(ToOwned::to_owned);
         // It is extremely important that we call second [try_match_by_property] only if first returns false
         let matched = false
             || try_match_by_property(&prev_game_ids_host, &curr_game_ids_host, state, get_game_name)
@@ -271,8 +390,14 @@ fn try_merge_host(prev_game_ids_host: &[GameId], curr_game_ids_host: &[GameId],
     true
 }
 
-pub fn try_merge_host_ids(updater_state: &mut UpdaterState, state: &mut State, time: TimeMinutes) {
+pub fn try_merge_host_ids(
+    sender_fetcher_get_game_details: &mpsc::Sender<Request>,
+    updater_state: &mut UpdaterState,
+    state: &mut State,
+    time: TimeMinutes,
+) {
     let curr_game_ids_by_host = group_game_ids_by_host(&state.current_game_ids, state);
+    request_details_for_merge_waiting_games(sender_fetcher_get_game_details, &curr_game_ids_by_host, updater_state, state, time);
 
     // было бы здорово если бы у HashMap был метод .drain_filter(): https://github.com/rust-lang/rust/issues/59618
     updater_state.scheduled_to_merge_host_ids = updater_state.scheduled_to_merge_host_ids.drain()
@@ -308,6 +433,28 @@ pub fn try_merge_host_ids(updater_state: &mut UpdaterState, state: &mut State, t
         .collect();
 }
 
+/// объединение хоста ждёт game details его игр, поэтому запрашиваем их вне очереди
+/// (fetcher_get_game_details игнорирует повторные запросы)
+fn request_details_for_merge_waiting_games(
+    sender_fetcher_get_game_details: &mpsc::Sender<Request>,
+    curr_game_ids_by_host: &HashMap<HostId, Vec<GameId>>,
+    updater_state: &UpdaterState,
+    state: &State,
+    time: TimeMinutes,
+) {
+    for (host_id, merge_info) in &updater_state.scheduled_to_merge_host_ids {
+        if time.get() - merge_info.time_end.get() < HOST_ID_MERGE_DELAY {
+            continue;
+        }
+        let curr_game_ids_host = curr_game_ids_by_host.get(host_id).map(Vec::as_slice).unwrap_or(&[]);
+        for &game_id in merge_info.game_ids.iter().chain(curr_game_ids_host) {
+            if !state.get_game(game_id).are_details_resolved() {
+                sender_fetcher_get_game_details.send(Request { game_id, priority: Priority::MergeWaiting }).unwrap();
+            }
+        }
+    }
+}
+
 fn schedule_host_ids_merging(
     prev_game_ids_all: &HashSet<GameId>,
     curr_game_ids_all: &HashSet<GameId>,
@@ -325,7 +472,10 @@ fn schedule_host_ids_merging(
         if let Some(merge_info) = updater_state.scheduled_to_merge_host_ids.get_mut(&host_id) {
             merge_info.time_end = time;
         } else {
-            let game_ids = prev_game_ids_by_host.get(&host_id).map_or(Vec::new(), ToOwned::to_owned);
+            let game_ids = prev_game_ids_by_host.get(&host_id).map_or_else(
+                || get_suspended_game_id(&host_id, updater_state, state).into_iter().collect(),
+                ToOwned::to_owned,
+            );
             let merge_info = HostIdMergeInfo {
                 time_begin: time,
                 time_end: time,
@@ -336,10 +486,146 @@ fn schedule_host_ids_merging(
     }
 }
 
+/// приостановка: хост появился снова спустя долгое время (больше чем HOST_ID_MERGE_DELAY)
+/// в таком случае объединяем его новую игру с последней игрой хоста
+fn get_suspended_game_id(host_id: &HostId, updater_state: &UpdaterState, state: &State) -> Option<GameId> {
+    let host_history = updater_state.hosts_history.get(host_id)?;
+    if !host_history.is_suitable_for_suspension() {
+        return None;
+    }
+
+    let game = state.get_game(host_history.last_game_id);
+    // next_game_id может быть уже вычислен, если игра была объединена при обычном перезапуске
+    if game.time_end.is_none() || game.next_game_id.is_some() || !game.are_details_resolved() {
+        return None;
+    }
+    println!("[info]  [updater] host {}: resumed after suspension, last game {} (last seen at {:?})",
+             base64::encode(host_id), game.game_id, host_history.last_seen);
+    Some(game.game_id)
+}
+
+fn update_hosts_history(updater_state: &mut UpdaterState, state: &State, time: TimeMinutes) {
+    let curr_game_ids_by_host = group_game_ids_by_host(&state.current_game_ids, state);
+    for (host_id, game_ids) in curr_game_ids_by_host {
+        let last_game_id = *game_ids.iter().max().unwrap();
+        let host_history = updater_state.hosts_history.entry(host_id).or_insert_with(|| HostHistory {
+            last_game_id,
+            single_game_since: Some(time),
+            multi_game_since: None,
+            last_seen: time,
+        });
+        host_history.last_game_id = last_game_id;
+        host_history.last_seen = time;
+
+        if game_ids.len() <= 1 {
+            host_history.multi_game_since = None;
+            if host_history.single_game_since.is_none() {
+                host_history.single_game_since = Some(time);
+            }
+        } else {
+            let multi_game_since = *host_history.multi_game_since.get_or_insert(time);
+            if time.get() - multi_game_since.get() >= HOST_ID_MERGE_DELAY {
+                // мультихост
+                host_history.single_game_since = None;
+            }
+        }
+    }
+}
+
+/// восстанавливает hosts_history по интервалам игр так, как её построил бы `update_hosts_history`
+/// (для состояний, сохранённых без hosts_history)
+pub fn rebuild_hosts_history(state: &State) -> HashMap<HostId, HostHistory> {
+    let last_observed_time = match state.last_observed_time {
+        Some(last_observed_time) => last_observed_time,
+        None => return HashMap::new(),
+    };
+
+    // (время, +1 при появлении игры и -1 при исчезновении)
+    let mut events_by_host: HashMap<HostId, Vec<(TimeMinutes, i32)>> = HashMap::new();
+    let mut last_game_id_by_host: HashMap<HostId, GameId> = HashMap::new();
+    for game in state.games.values() {
+        let events = events_by_host.entry(game.host_id).or_default();
+        events.push((game.time_begin, 1));
+        if let Some(time_end) = game.time_end {
+            events.push((time_end, -1));
+        }
+        let last_game_id = last_game_id_by_host.entry(game.host_id).or_insert(game.game_id);
+        *last_game_id = (*last_game_id).max(game.game_id);
+    }
+
+    let mut hosts_history = HashMap::new();
+    for (host_id, mut events) in events_by_host {
+        // time_end — первая минута без игры, поэтому при равном времени исчезновение обрабатывается раньше
+        events.sort();
+        let mut number_games = 0;
+        let mut single_game_since = None;
+        let mut multi_game_since: Option<TimeMinutes> = None;
+        for &(time, delta) in &events {
+            number_games += delta;
+            if number_games <= 1 {
+                if let Some(multi_game_since) = multi_game_since.take() {
+                    if time.get() - multi_game_since.get() >= HOST_ID_MERGE_DELAY {
+                        single_game_since = None;
+                    }
+                }
+                single_game_since = single_game_since.or(Some(time));
+            } else {
+                multi_game_since = multi_game_since.or(Some(time));
+            }
+        }
+
+        let last_seen = if number_games > 0 {
+            last_observed_time
+        } else {
+            let (last_event_time, _) = *events.last().unwrap();
+            TimeMinutes::new(last_event_time.get() - 1).unwrap_or(last_event_time)
+        };
+        if let Some(since) = multi_game_since {
+            if last_seen.get() - since.get() >= HOST_ID_MERGE_DELAY {
+                single_game_since = None;
+            }
+        }
+        let host_history = HostHistory {
+            last_game_id: last_game_id_by_host[&host_id],
+            single_game_since,
+            multi_game_since,
+            last_seen,
+        };
+        hosts_history.insert(host_id, host_history);
+    }
+    hosts_history
+}
+
+/// моды и адрес могут измениться во время игры, поэтому для долгих игр периодически повторяем запрос /get-game-details
+/// такие запросы имеют низкий приоритет (см. `fetcher_get_game_details::Priority`)
+fn schedule_details_refetch(
+    sender_fetcher_get_game_details: &mpsc::Sender<Request>,
+    updater_state: &mut UpdaterState,
+    state: &State,
+    time: TimeMinutes,
+) {
+    let refetch_interval = match GLOBAL_CONFIG.lock().unwrap().get_game_details_refetch_interval {
+        Some(refetch_interval) => refetch_interval,
+        None => return,
+    };
+    let next_refetch_time = TimeMinutes::new(time.get() + refetch_interval).unwrap();
+
+    let current_game_ids: HashSet<GameId> = state.current_game_ids.iter().copied().collect();
+    updater_state.details_refetch_times.retain(|game_id, _| current_game_ids.contains(game_id));
+    for game_id in current_game_ids {
+        let refetch_time = updater_state.details_refetch_times.entry(game_id).or_insert(next_refetch_time);
+        if *refetch_time <= time && state.get_game(game_id).are_details_fetched() {
+            sender_fetcher_get_game_details.send(Request { game_id, priority: Priority::Refetch }).unwrap();
+            *refetch_time = next_refetch_time;
+        }
+    }
+}
+
 fn update_or_create_games(
-    sender_fetcher_get_game_details: &mpsc::Sender<GameId>,
+    sender_fetcher_get_game_details: &mpsc::Sender<Request>,
     get_games_response: &mut Vec<api::Game>,
     time: TimeMinutes,
+    closing_time: TimeMinutes,
     state: &mut State,
 ) {
     for game_snapshot in get_games_response {
@@ -347,24 +633,35 @@ fn update_or_create_games(
         // can't use `if let Some(game) = games.get(...)`
         // because we have to pass `state` to `update_game`, and
         if state.games.contains_key(&game_id) {
-            update_game(game_snapshot, state, time);
+            update_game(game_snapshot, state, time, closing_time);
         } else {
             let game = convert_snapshot_to_game(game_snapshot, state, time);
             state.games.insert(game_id, game);
 
-            sender_fetcher_get_game_details.send(game_id).unwrap();
+            sender_fetcher_get_game_details.send(Request { game_id, priority: Priority::New }).unwrap();
         }
     }
 }
 
+// пропуск возникает когда fetcher_get_games пропустил минуты из-за ошибок api
+// или после перезапуска (первый ответ после загрузки State)
+fn record_observation_gap(state: &mut State, time: TimeMinutes) {
+    let gap_begin = state.get_closing_time(time);
+    if gap_begin < time {
+        println!("[info]  [updater] observation gap: minutes {}..{}", gap_begin.get(), time.get());
+        state.observation_gaps.push(ObservationGap { begin: gap_begin, end: time });
+    }
+}
+
 pub fn updater(
     updater_state_lock: Arc<RwLock<UpdaterState>>,
     state_lock: StateLock,
     receiver_fetcher_get_games: mpsc::Receiver<FetcherOutput>,
-    sender_fetcher_get_game_details: mpsc::Sender<GameId>,
+    sender_fetcher_get_game_details: mpsc::Sender<Request>,
 ) {
-    for (mut get_games_response, time) in receiver_fetcher_get_games {
-        if GLOBAL_CONFIG.lock().unwrap().pipeline == "create_state_from_saved_data" && time.get() == 2 {
+    for (iteration, (mut get_games_response, time)) in receiver_fetcher_get_games.into_iter().enumerate() {
+        let pipeline = GLOBAL_CONFIG.lock().unwrap().pipeline.clone();
+        if (pipeline == "create_state_from_saved_data" || pipeline == "replay") && iteration == 1 {
             // в начале второй итерации, чтобы fetcher_get_game_details успел обработать большое число игр, добавленных на первой итерации
             std::thread::sleep(Duration::from_millis(1000));
         }
@@ -375,20 +672,27 @@ pub fn updater(
 
         let mut updater_state = updater_state_lock.write();
         let mut state = state_lock.write();
-        update_or_create_games(&sender_fetcher_get_game_details, &mut get_games_response, time, &mut state);
+        let closing_time = state.get_closing_time(time);
+        record_observation_gap(&mut state, time);
+        update_or_create_games(&sender_fetcher_get_game_details, &mut get_games_response, time, closing_time, &mut state);
+        state.global_online.add_sample(&get_games_response, time);
 
         let curr_game_ids_all: HashSet<GameId> = get_games_response
             .iter().map(|game| game.game_id).collect();
         let prev_game_ids_all: HashSet<GameId> = state.current_game_ids
             .iter().copied().collect();
 
-        update_finished_games(&prev_game_ids_all, &curr_game_ids_all, &mut state, time);
+        update_finished_games(&prev_game_ids_all, &curr_game_ids_all, &mut state, closing_time);
 
         schedule_host_ids_merging(&prev_game_ids_all, &curr_game_ids_all, &mut updater_state, &mut state, time);
 
         state.current_game_ids = Vec::from_iter(curr_game_ids_all);
+        state.last_observed_time = Some(time);
+
+        update_hosts_history(&mut updater_state, &state, time);
+        schedule_details_refetch(&sender_fetcher_get_game_details, &mut updater_state, &state, time);
 
-        try_merge_host_ids(&mut updater_state, &mut state, time);
+        try_merge_host_ids(&sender_fetcher_get_game_details, &mut updater_state, &mut state, time);
     }
     println!("[info]  [updater] exit");
 }
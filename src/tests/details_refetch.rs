@@ -0,0 +1,164 @@
+// повторные запросы /get-game-details (см. `updater::schedule_details_refetch`)
+// fetcher_get_game_details заменён на fetcher с заданными ответами, updater получает следующий ответ /get-games
+// только после того как fetcher обработал нужный запрос, поэтому время изменения details детерминировано
+
+use std::num::NonZeroU32;
+use std::sync::{Arc, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use parking_lot::RwLock;
+
+use crate::{api, external_storage, fetcher_get_game_details, state};
+use crate::fetcher_get_game_details::Request;
+use crate::fetcher_get_games::FetcherOutput;
+use crate::state::{DetailsStatus, State, StateLock, TimeMinutes};
+
+use super::prepare_games;
+
+// (host_address, [(mod name, mod version)])
+type Details = (&'static str, Vec<(&'static str, &'static str)>);
+
+struct Pipeline {
+    sender_fetcher_get_games: mpsc::Sender<FetcherOutput>,
+    state_lock: StateLock,
+    updater_thread: JoinHandle<()>,
+    fetcher_thread: JoinHandle<()>,
+    next_time: u32,
+}
+
+fn prepare_game_details((host_address, mods): &Details) -> api::Game {
+    let mut game_snapshot = prepare_games(vec![(1, 1)]).remove(0);
+    game_snapshot.host_address = Some(host_address.to_string());
+    game_snapshot.mods = Some(mods.iter()
+        .map(|&(name, version)| api::Mod {
+            name: name.to_owned(),
+            version: version.to_owned(),
+            unknown_fields: api::UnknownFields::new(),
+        })
+        .collect());
+    game_snapshot
+}
+
+// ответы выдаются по порядку, после конца списка повторяется последний (как в `mock_api`)
+fn fetcher_get_game_details_scripted(receiver: mpsc::Receiver<Request>, state_lock: StateLock, details: Vec<Details>) {
+    for (index, request) in receiver.into_iter().enumerate() {
+        let game_snapshot = prepare_game_details(&details[index.min(details.len() - 1)]);
+        let mut state = state_lock.write();
+        let time = state.last_observed_time.unwrap();
+        fetcher_get_game_details::apply_game_details(&mut state, request, Some(game_snapshot), time);
+    }
+}
+
+impl Pipeline {
+    fn new(details: Vec<Details>) -> Self {
+        let (sender_fetcher_get_games, receiver_fetcher_get_games) = mpsc::channel();
+        let (sender_fetcher_get_game_details, receiver_fetcher_get_game_details) = mpsc::channel();
+
+        let whole_state = external_storage::get_empty_state();
+        let updater_state_lock = Arc::new(RwLock::new(whole_state.updater_state));
+        let state_lock = StateLock::new(whole_state.state);
+
+        let fetcher_thread = {
+            let state_lock = state_lock.clone();
+            thread::spawn(move || fetcher_get_game_details_scripted(receiver_fetcher_get_game_details, state_lock, details))
+        };
+        let updater_thread = {
+            let state_lock = state_lock.clone();
+            thread::spawn(move || state::updater::updater(updater_state_lock, state_lock, receiver_fetcher_get_games, sender_fetcher_get_game_details))
+        };
+        Pipeline { sender_fetcher_get_games, state_lock, updater_thread, fetcher_thread, next_time: 1 }
+    }
+
+    // game 1 of host 1 in each minute up to `time_end` (inclusive)
+    fn send_until(&mut self, time_end: u32) {
+        for time in self.next_time..=time_end {
+            let time = TimeMinutes::new(time).unwrap();
+            self.sender_fetcher_get_games.send((prepare_games(vec![(1, 1)]), time)).unwrap();
+        }
+        self.next_time = time_end + 1;
+    }
+
+    fn wait_until(&self, condition: impl Fn(&State) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !condition(&self.state_lock.read()) {
+            assert!(Instant::now() < deadline, "timeout");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn finish(self) -> StateLock {
+        drop(self.sender_fetcher_get_games);
+        self.updater_thread.join().unwrap();
+        self.fetcher_thread.join().unwrap();
+        self.state_lock
+    }
+}
+
+fn game_id() -> NonZeroU32 {
+    NonZeroU32::new(1).unwrap()
+}
+
+// default interval is 6 hours, game 1 appears at minute 1
+const FIRST_REFETCH_TIME: u32 = 1 + 6 * 60;
+const SECOND_REFETCH_TIME: u32 = 1 + 2 * 6 * 60;
+
+fn run_refetch(details: Vec<Details>) -> StateLock {
+    let mut pipeline = Pipeline::new(details);
+    pipeline.send_until(10);
+    pipeline.wait_until(|state| state.get_game(game_id()).details_status == DetailsStatus::Fetched);
+    pipeline.send_until(FIRST_REFETCH_TIME);
+    pipeline.wait_until(|state| !state.get_game_details_revisions(game_id()).is_empty());
+    // second refetch returns the same details
+    pipeline.send_until(SECOND_REFETCH_TIME);
+    pipeline.finish()
+}
+
+fn get_mods(state: &State, mods: &[state::Mod]) -> Vec<(String, String)> {
+    mods.iter()
+        .map(|mod_| (state.all_mod_names.get_str(mod_.name), state.all_versions.get_str(mod_.version)))
+        .collect()
+}
+
+#[test]
+fn refetch_mods_changed() {
+    let state_lock = run_refetch(vec![
+        ("1.2.3.4:34197", vec![("mod1", "1.0.0")]),
+        ("1.2.3.4:34197", vec![("mod1", "1.1.0"), ("mod2", "1.0.0")]),
+    ]);
+
+    let state = state_lock.read();
+    let revisions = state.get_game_details_revisions(game_id());
+    assert_eq!(revisions.len(), 1);
+    let revision = &revisions[0];
+    assert_eq!(revision.time, TimeMinutes::new(FIRST_REFETCH_TIME).unwrap());
+    assert!(revision.host_address.is_none());
+    let (old_mods, new_mods) = revision.mods.as_ref().unwrap();
+    let owned = |mods: &[(&str, &str)]| mods.iter().map(|&(name, version)| (name.to_owned(), version.to_owned())).collect::<Vec<_>>();
+    assert_eq!(get_mods(&state, old_mods), owned(&[("mod1", "1.0.0")]));
+    assert_eq!(get_mods(&state, new_mods), owned(&[("mod1", "1.1.0"), ("mod2", "1.0.0")]));
+
+    let game = state.get_game(game_id());
+    assert_eq!(get_mods(&state, game.get_mods(&state).as_ref().unwrap()), get_mods(&state, new_mods));
+}
+
+#[test]
+fn refetch_host_address_changed() {
+    let state_lock = run_refetch(vec![
+        ("1.2.3.4:34197", vec![("mod1", "1.0.0")]),
+        ("5.6.7.8:34197", vec![("mod1", "1.0.0")]),
+    ]);
+
+    let state = state_lock.read();
+    let revisions = state.get_game_details_revisions(game_id());
+    assert_eq!(revisions.len(), 1);
+    let revision = &revisions[0];
+    assert_eq!(revision.time, TimeMinutes::new(FIRST_REFETCH_TIME).unwrap());
+    assert!(revision.mods.is_none());
+    let (old_host_address, new_host_address) = revision.host_address.unwrap();
+    assert_eq!(state.all_host_addresses.get_str(old_host_address), "1.2.3.4:34197");
+    assert_eq!(state.all_host_addresses.get_str(new_host_address), "5.6.7.8:34197");
+
+    let game = state.get_game(game_id());
+    assert!(game.host_address == Some(new_host_address));
+}
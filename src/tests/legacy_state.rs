@@ -0,0 +1,211 @@
+// состояния, сохранённые до появления заголовка с версией формата (см. `external_storage::legacy`)
+// структуры `*V0` совпадают со структурами того времени, поэтому bincode даёт те же байты, что и старый код
+
+use std::collections::{BTreeMap, VecDeque};
+use std::num::NonZeroU32;
+
+use hashbrown::HashMap;
+
+use crate::external_storage::{self, WholeState};
+use crate::external_storage::legacy::{FetcherStateV0, GameV0, StateV0, UpdaterStateV0};
+use crate::state::{BigString, DetailsStatus, GameId, Mod, PlayerInterval, TimeMinutes};
+
+struct LegacyState {
+    updater_state: UpdaterStateV0,
+    state: StateV0<BTreeMap<GameId, GameV0>>,
+    fetcher_state: FetcherStateV0,
+}
+
+fn time(minutes: u32) -> TimeMinutes {
+    TimeMinutes::new(minutes).unwrap()
+}
+
+fn game_id(id: u32) -> GameId {
+    GameId::new(id).unwrap()
+}
+
+fn empty_legacy_state() -> LegacyState {
+    LegacyState {
+        updater_state: UpdaterStateV0 { scheduled_to_merge_host_ids: HashMap::new() },
+        state: StateV0 {
+            games: BTreeMap::new(),
+            game_ids: vec![NonZeroU32::new(std::u32::MAX).unwrap()],
+            current_game_ids: vec![],
+            all_game_names: BigString::new(),
+            all_game_descriptions: BigString::new(),
+            all_versions: BigString::new(),
+            all_tags: BigString::new(),
+            all_host_addresses: BigString::new(),
+            all_mod_names: BigString::new(),
+            all_player_names: BigString::new(),
+        },
+        fetcher_state: FetcherStateV0 { game_ids: VecDeque::new() },
+    }
+}
+
+impl LegacyState {
+    // (host_id, game_id, [time_begin, time_end), players with their intervals)
+    fn add_game(&mut self, host_index: u8, id: u32, time_begin: u32, time_end: Option<u32>, players: &[(&str, u32, Option<u32>)]) -> &mut GameV0 {
+        let state = &mut self.state;
+        let mut host_id = [0u8; 32];
+        host_id[0] = host_index;
+        let players_intervals = players.iter()
+            .map(|&(player, begin, end)| PlayerInterval {
+                player_index: state.all_player_names.add(player),
+                begin: time(begin),
+                end: end.map(time),
+            })
+            .collect();
+        let game = GameV0 {
+            game_id: game_id(id),
+            server_id: None,
+            prev_game_id: None,
+            next_game_id: None,
+            time_begin: time(time_begin),
+            time_end: time_end.map(time),
+            players_intervals,
+            host_id,
+            name: state.all_game_names.add(&format!("game{}", id)),
+            description: state.all_game_descriptions.add("description"),
+            max_players: 10,
+            game_version: state.all_versions.add("0.18.0"),
+            game_time_elapsed: 0,
+            has_password: false,
+            tags: state.all_tags.add(""),
+            mod_count: 0,
+            host_address: None,
+            mods: None,
+        };
+        if time_end.is_none() {
+            state.current_game_ids.push(game.game_id);
+        }
+        state.games.entry(game.game_id).or_insert(game)
+    }
+
+    fn set_details(&mut self, id: u32, host_address: &str, mods: &[(&str, &str)]) {
+        let state = &mut self.state;
+        let host_address = state.all_host_addresses.add(host_address);
+        let mods = mods.iter()
+            .map(|&(name, version)| Mod { name: state.all_mod_names.add(name), version: state.all_versions.add(version) })
+            .collect();
+        let game = state.games.get_mut(&game_id(id)).unwrap();
+        game.host_address = Some(host_address);
+        game.mods = Some(mods);
+    }
+
+    fn load(&self) -> WholeState {
+        let data = bincode::serialize(&(&self.updater_state, &self.state, &self.fetcher_state)).unwrap();
+        external_storage::load_state_from_reader(data.as_slice())
+    }
+}
+
+// host 1: game 1 [10, 50), then game 2 [48, ∞) which is still waiting for /get-game-details
+fn prepare_legacy_state() -> LegacyState {
+    let mut legacy_state = empty_legacy_state();
+    legacy_state.add_game(1, 1, 10, Some(50), &[("player1", 10, Some(30)), ("player2", 20, Some(50))]);
+    legacy_state.set_details(1, "1.2.3.4:34197", &[("base", "0.18.0"), ("mod1", "1.0.0")]);
+    legacy_state.add_game(1, 2, 48, None, &[("player1", 48, None)]);
+    legacy_state.fetcher_state.game_ids.push_back(game_id(2));
+    legacy_state
+}
+
+#[test]
+fn load_legacy_state() {
+    let whole_state = prepare_legacy_state().load();
+    let state = &whole_state.state;
+
+    assert_eq!(state.games.len(), 2);
+    assert_eq!(state.get_game_name(game_id(1)), "game1");
+    let game1 = state.get_game(game_id(1));
+    assert_eq!(game1.time_end, Some(time(50)));
+    assert_eq!(state.all_host_addresses.get_str(game1.host_address.unwrap()), "1.2.3.4:34197");
+    let mods = game1.get_mods(state).as_ref().unwrap();
+    assert_eq!(state.all_mod_names.get_str(mods[1].name), "mod1");
+    assert_eq!(game1.build, None);
+    assert!(state.get_game_details_extra(game_id(1)).is_none() && state.builds.is_empty());
+    assert_eq!(game1.details_status, DetailsStatus::Fetched);
+    assert_eq!(state.get_game(game_id(2)).details_status, DetailsStatus::NotFetched);
+    assert_eq!(state.current_game_ids, vec![game_id(2)]);
+
+    // последний ответ /get-games оценивается по играм
+    assert_eq!(state.last_observed_time, Some(time(50)));
+    assert!(state.observation_gaps.is_empty());
+    assert_eq!(state.get_player_sessions("player1").len(), 2);
+    assert_eq!(whole_state.fetcher_get_game_details_state.game_ids, vec![game_id(2)]);
+    assert!(state.server_overrides.is_empty());
+    assert!(state.metadata_changes.is_empty() && state.get_game_metadata_changes(game_id(2)).is_empty());
+    assert!(state.details_revisions.is_empty() && whole_state.updater_state.details_refetch_times.is_empty());
+    let fetcher_state = &whole_state.fetcher_get_game_details_state;
+    assert!(fetcher_state.refetch_game_ids.is_empty() && fetcher_state.merge_waiting_game_ids.is_empty() && fetcher_state.in_progress.is_empty());
+
+    // после сохранения в текущем формате загружается то же самое
+    let temp_file = std::env::temp_dir().join("legacy-state.bin");
+    let temp_file = temp_file.to_str().unwrap();
+    external_storage::save_state_to_file(whole_state.deref(), temp_file);
+    assert!(external_storage::load_state_from_file(temp_file) == whole_state);
+}
+
+#[test]
+fn legacy_hosts_history() {
+    let mut legacy_state = prepare_legacy_state();
+    legacy_state.add_game(2, 3, 20, Some(30), &[]);
+    let whole_state = legacy_state.load();
+
+    let hosts_history = &whole_state.updater_state.hosts_history;
+    let mut host_id = [0u8; 32];
+    host_id[0] = 1;
+    // games 1 and 2 overlap for less than HOST_ID_MERGE_DELAY
+    let host_history = &hosts_history[&host_id];
+    assert_eq!(host_history.last_game_id, game_id(2));
+    assert_eq!(host_history.single_game_since, Some(time(10)));
+    assert_eq!(host_history.multi_game_since, None);
+    assert_eq!(host_history.last_seen, time(50));
+
+    host_id[0] = 2;
+    let host_history = &hosts_history[&host_id];
+    assert_eq!(host_history.last_game_id, game_id(3));
+    assert_eq!(host_history.last_seen, time(29));
+}
+
+#[test]
+fn legacy_fetcher_queue() {
+    let mut legacy_state = prepare_legacy_state();
+    // game 3 was sent to fetcher_get_game_details but was not in its queue at the moment of saving
+    legacy_state.add_game(2, 3, 49, None, &[]);
+    let whole_state = legacy_state.load();
+
+    assert_eq!(whole_state.fetcher_get_game_details_state.game_ids, vec![game_id(2), game_id(3)]);
+}
+
+#[test]
+fn migrate_unknown_game_details() {
+    let mut legacy_state = prepare_legacy_state();
+    // /get-game-details returned 404
+    legacy_state.add_game(2, 3, 20, Some(30), &[]);
+    legacy_state.set_details(3, "unknown", &[("unknown", "unknown")]);
+    let whole_state = legacy_state.load();
+    let state = &whole_state.state;
+
+    let game3 = state.get_game(game_id(3));
+    assert_eq!(game3.details_status, DetailsStatus::Unavailable);
+    assert!(game3.host_address.is_none() && game3.get_mods(state).is_none());
+    assert_eq!(state.get_game(game_id(1)).details_status, DetailsStatus::Fetched);
+    assert_eq!(whole_state.fetcher_get_game_details_state.game_ids, vec![game_id(2)]);
+}
+
+#[test]
+fn legacy_global_online() {
+    let mut legacy_state = empty_legacy_state();
+    legacy_state.add_game(1, 1, 60, Some(180), &[("player1", 60, Some(120))]);
+    legacy_state.add_game(2, 2, 120, None, &[("player2", 120, None)]).has_password = true;
+    let whole_state = legacy_state.load();
+    let global_online = &whole_state.state.global_online;
+
+    // last observed minute is 180, so hour [180, 240) is not complete
+    assert_eq!(global_online.major_versions, vec!["0.18".to_owned()]);
+    let samples: Vec<_> = global_online.hourly_samples.iter()
+        .map(|sample| (sample.time, sample.number_games, sample.number_players, sample.number_players_with_password))
+        .collect();
+    assert_eq!(samples, vec![(time(60), 1, 1, 0), (time(120), 2, 1, 1)]);
+    assert!(global_online.recent_samples.is_empty());
+}
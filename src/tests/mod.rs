@@ -2,23 +2,29 @@ use std::num::NonZeroU32;
 use std::sync::{Arc, mpsc};
 use std::thread;
 
+use hashbrown::HashSet;
 use parking_lot::RwLock;
 
 use crate::{api, external_storage, state};
-use crate::state::{GameId, Mod, StateLock, TimeMinutes, updater};
+use crate::fetcher_get_game_details::Request;
+use crate::state::{DetailsStatus, Mod, StateLock, TimeMinutes, updater};
 
+mod details_refetch;
+mod legacy_state;
+mod mods_statistics;
 mod serialization;
 
-pub fn fetcher_get_game_details(receiver: mpsc::Receiver<GameId>, state_lock: StateLock) {
+pub fn fetcher_get_game_details(receiver: mpsc::Receiver<Request>, state_lock: StateLock) {
     let mut state = state_lock.write();
     let host_address = state.all_host_addresses.add("fake_host_address");
     let mod_name = state.all_mod_names.add("fake_mod_name");
     let mod_version = state.all_versions.add("fake_mod_version");
     drop(state);
 
-    for game_id in receiver {
+    for Request { game_id, .. } in receiver {
         let mut state = state_lock.write();
         let game = state.get_game_mut(game_id);
+        game.details_status = DetailsStatus::Fetched;
         game.host_address = Some(host_address);
         game.mods = Some(vec![Mod { name: mod_name, version: mod_version }]);
     }
@@ -43,6 +49,7 @@ fn prepare_games(games: Vec<(u8, u32) /* host_id, game_id */>) -> Vec<api::Game>
                     build_version: 0,
                     build_mode: "fake".to_string(),
                     platform: "fake".to_string(),
+                    unknown_fields: api::UnknownFields::new(),
                 },
                 game_time_elapsed: 0,
                 has_password: false,
@@ -56,13 +63,21 @@ fn prepare_games(games: Vec<(u8, u32) /* host_id, game_id */>) -> Vec<api::Game>
                 mods_crc: None,
                 steam_id: None,
                 require_user_verification: None,
+                unknown_fields: api::UnknownFields::new(),
             }
         })
         .collect()
 }
 
-#[test]
-fn merge() {
+// runs updater on given /get-games responses: (time, [(host_id, game_id)])
+fn run_updater(responses: Vec<(u32, Vec<(u8, u32)>)>) -> StateLock {
+    let responses = responses.into_iter()
+        .map(|(time, games)| (time, prepare_games(games)))
+        .collect();
+    run_updater_with_snapshots(responses)
+}
+
+fn run_updater_with_snapshots(responses: Vec<(u32, Vec<api::Game>)>) -> StateLock {
     let (sender_fetcher_get_games, receiver_fetcher_get_games) = mpsc::channel();
     let (sender_fetcher_get_game_details, receiver_fetcher_get_game_details) = mpsc::channel();
 
@@ -71,10 +86,10 @@ fn merge() {
     let state_lock = StateLock::new(whole_state.state);
 
     // fetcher_get_game_details
-    {
+    let fetcher_thread = {
         let state_lock = state_lock.clone();
-        thread::spawn(move || fetcher_get_game_details(receiver_fetcher_get_game_details, state_lock));
-    }
+        thread::spawn(move || fetcher_get_game_details(receiver_fetcher_get_game_details, state_lock))
+    };
 
     // updater
     let updater_thread = {
@@ -83,6 +98,20 @@ fn merge() {
         thread::spawn(move || state::updater::updater(updater_state_lock, state_lock, receiver_fetcher_get_games, sender_fetcher_get_game_details))
     };
 
+    for (time, games) in responses {
+        let time = TimeMinutes::new(time).unwrap();
+        sender_fetcher_get_games.send((games, time)).unwrap();
+    }
+    drop(sender_fetcher_get_games);
+
+    updater_thread.join().unwrap();
+    // все запросы updater обработаны, поэтому fetcher больше не изменит State
+    fetcher_thread.join().unwrap();
+    state_lock
+}
+
+#[test]
+fn merge() {
     const DELAY: u32 = 40;
     assert_eq!(updater::HOST_ID_MERGE_DELAY, 20);
     assert!(DELAY > updater::HOST_ID_MERGE_DELAY);
@@ -93,17 +122,250 @@ fn merge() {
     games.push(vec![(1, 1), (1, 2)]);
     for _ in 0..DELAY { games.push(vec![(1, 2)]); }
     for _ in 0..DELAY { games.push(vec![]); }
-    for (time, games) in games.into_iter().enumerate() {
-        let games = prepare_games(games);
-        let time = TimeMinutes::new(time as u32 + 1).unwrap();
-        sender_fetcher_get_games.send((games, time)).unwrap();
-    }
-    drop(sender_fetcher_get_games);
-
-    updater_thread.join().unwrap();
+    let responses = games.into_iter().enumerate()
+        .map(|(time, games)| (time as u32 + 1, games))
+        .collect();
+    let state_lock = run_updater(responses);
 
     let state = state_lock.read();
     assert_eq!(state.games.len(), 2);
     let game2 = state.get_game(NonZeroU32::new(2).unwrap());
     assert_eq!(game2.prev_game_id, Some(NonZeroU32::new(1).unwrap()));
 }
+
+#[test]
+fn observation_gap() {
+    let mut responses: Vec<(u32, Vec<(u8, u32)>)> = (1..=10).map(|time| (time, vec![(1, 1)])).collect();
+    // minutes 11..20 are missed
+    responses.push((20, vec![]));
+    let state_lock = run_updater(responses);
+
+    let state = state_lock.read();
+    let game1 = state.get_game(NonZeroU32::new(1).unwrap());
+    assert_eq!(game1.time_end, TimeMinutes::new(11));
+    let gap = state::ObservationGap { begin: TimeMinutes::new(11).unwrap(), end: TimeMinutes::new(20).unwrap() };
+    assert_eq!(state.observation_gaps, vec![gap]);
+    assert_eq!(state.last_observed_time, TimeMinutes::new(20));
+}
+
+#[test]
+fn suspension() {
+    let suspension_test = |number_minutes_online: u32, number_minutes_offline: u32| {
+        let mut responses: Vec<(u32, Vec<(u8, u32)>)> = (1..=number_minutes_online).map(|time| (time, vec![(1, 1)])).collect();
+        // host is offline for a long time (more than HOST_ID_MERGE_DELAY)
+        // (minutes without responses are recorded as observation gap)
+        responses.push((number_minutes_online + 1, vec![]));
+        let time_resume = number_minutes_online + number_minutes_offline;
+        responses.push((time_resume - 1, vec![]));
+        responses.extend((time_resume..time_resume + 40).map(|time| (time, vec![(1, 2)])));
+        let state_lock = run_updater(responses);
+
+        let state = state_lock.read();
+        state.get_game(NonZeroU32::new(2).unwrap()).prev_game_id
+    };
+
+    assert_eq!(updater::SUSPENSION_SINGLE_GAME_DURATION, 24 * 60);
+    assert_eq!(suspension_test(25 * 60, 200), Some(NonZeroU32::new(1).unwrap()));
+    assert_eq!(suspension_test(60, 200), None);
+    // hosts history is kept no matter how long the gap was
+    assert_eq!(suspension_test(25 * 60, 10 * 7 * 24 * 60), Some(NonZeroU32::new(1).unwrap()));
+}
+
+#[test]
+fn server_overrides() {
+    use crate::state::overrides::ServerOverride;
+
+    // two different hosts, so games are not merged by updater
+    let mut responses: Vec<(u32, Vec<(u8, u32)>)> = (1..=30).map(|time| (time, vec![(1, 1)])).collect();
+    responses.extend((31..=60).map(|time| (time, vec![(2, 2)])));
+    responses.extend((61..=90).map(|time| (time, vec![])));
+    let state_lock = run_updater(responses);
+    let mut state = state_lock.write();
+
+    let game_id1 = NonZeroU32::new(1).unwrap();
+    let game_id2 = NonZeroU32::new(2).unwrap();
+    let server_id1 = state.get_game(game_id1).server_id.unwrap();
+    let server_id2 = state.get_game(game_id2).server_id.unwrap();
+    assert_ne!(server_id1, server_id2);
+
+    let game_ids_before_merge = state.game_ids.clone();
+
+    state.add_server_override(ServerOverride::Merge { game_id_a: game_id2, game_id_b: game_id1 }).unwrap();
+    assert_eq!(state.get_game(game_id2).prev_game_id, Some(game_id1));
+    assert_eq!(state.get_game(game_id2).server_id, Some(server_id1));
+    assert_eq!(state.get_server_last_game_id(server_id1), game_id2);
+    // ServerId of the second server is retired and redirects to the merged server
+    assert_eq!(state.as_server_id(server_id2.to_string().parse().unwrap()), Some(server_id1));
+    assert_eq!(state.get_server_last_game_id(server_id2), game_id2);
+    assert_eq!(state.get_server_ids().collect::<Vec<_>>(), vec![server_id1]);
+    assert!(state.add_server_override(ServerOverride::Merge { game_id_a: game_id1, game_id_b: game_id2 }).is_err());
+
+    // split after merge restores original ServerIds
+    state.add_server_override(ServerOverride::Split { game_id: game_id2 }).unwrap();
+    assert_eq!(state.get_game(game_id1).next_game_id, None);
+    assert_eq!(state.get_game(game_id2).prev_game_id, None);
+    assert_eq!(state.get_game(game_id2).server_id, Some(server_id2));
+    assert_eq!(state.get_server_last_game_id(server_id1), game_id1);
+    assert_eq!(state.get_server_last_game_id(server_id2), game_id2);
+    assert_eq!(state.game_ids, game_ids_before_merge);
+
+    // ServerIds are not leaked by repeated merge/split
+    state.add_server_override(ServerOverride::Merge { game_id_a: game_id1, game_id_b: game_id2 }).unwrap();
+    state.add_server_override(ServerOverride::Split { game_id: game_id2 }).unwrap();
+    assert_eq!(state.game_ids, game_ids_before_merge);
+    assert_eq!(state.server_overrides.len(), 4);
+}
+
+#[test]
+fn metadata_history() {
+    let mut responses = Vec::new();
+    for time in 1..=10 {
+        responses.push((time, prepare_games(vec![(1, 1)])));
+    }
+    for time in 11..=20 {
+        let mut games = prepare_games(vec![(1, 1)]);
+        games[0].name = "renamed".to_owned();
+        games[0].max_players = 10;
+        responses.push((time, games));
+    }
+    let state_lock = run_updater_with_snapshots(responses);
+
+    let state = state_lock.read();
+    let game_id = NonZeroU32::new(1).unwrap();
+    assert_eq!(state.get_game_name(game_id), "renamed");
+    let changes = state.get_game_metadata_changes(game_id);
+    assert_eq!(changes.len(), 2);
+    assert!(changes.iter().all(|change| change.time == TimeMinutes::new(11).unwrap()));
+    assert!(changes[1].new_value == state::metadata::MetadataValue::MaxPlayers(10));
+}
+
+#[test]
+fn metadata_normalized_values() {
+    let mut responses = Vec::new();
+    for time in 1..=10 {
+        let mut games = prepare_games(vec![(1, 1)]);
+        // BigString хранит \x00 как \x01, а \x02 в тегах заменяется на \x01
+        games[0].name = "name\x00".to_owned();
+        games[0].tags = vec!["tag1".to_owned(), "tag\x022".to_owned()];
+        responses.push((time, games));
+    }
+    let state_lock = run_updater_with_snapshots(responses);
+
+    let state = state_lock.read();
+    let game_id = NonZeroU32::new(1).unwrap();
+    assert_eq!(state.get_game_name(game_id), "name\x01");
+    assert!(state.get_game_metadata_changes(game_id).is_empty());
+}
+
+#[test]
+fn metadata_rotating_values() {
+    use crate::state::metadata::MAX_METADATA_CHANGES_PER_FIELD;
+
+    // name changes every minute, alternating between two values
+    let mut responses = Vec::new();
+    for time in 1..=100 {
+        let mut games = prepare_games(vec![(1, 1)]);
+        games[0].name = if time % 2 == 0 { "even" } else { "odd" }.to_owned();
+        responses.push((time, games));
+    }
+    let state_lock = run_updater_with_snapshots(responses);
+
+    let state = state_lock.read();
+    let game_id = NonZeroU32::new(1).unwrap();
+    assert_eq!(state.get_game_name(game_id), "even");
+    let changes = state.get_game_metadata_changes(game_id);
+    assert!(changes.len() <= MAX_METADATA_CHANGES_PER_FIELD);
+    // oldest changes are merged, so initial value and the last change are kept
+    let initial_name = changes[0].old_value.get_big_string_part().unwrap();
+    assert_eq!(state.all_game_names.get_str(initial_name), "odd");
+    assert_eq!(changes.last().unwrap().time, TimeMinutes::new(100).unwrap());
+    // values are interned only once
+    let parts: HashSet<_> = changes.iter()
+        .flat_map(|change| change.old_value.get_big_string_part().into_iter().chain(change.new_value.get_big_string_part()))
+        .collect();
+    assert_eq!(parts.len(), 2);
+}
+
+#[test]
+fn online_by_minute() {
+    let mut responses = Vec::new();
+    for time in 1..=10 {
+        let mut games = prepare_games(vec![(1, 1)]);
+        games[0].players = vec!["player1".to_owned()];
+        if time > 5 {
+            games[0].players.push("player2".to_owned());
+        }
+        responses.push((time, games));
+    }
+    // minutes 11..20 are missed
+    responses.push((20, vec![]));
+    let state_lock = run_updater_with_snapshots(responses);
+
+    let state = state_lock.read();
+    let game_ids = vec![NonZeroU32::new(1).unwrap()];
+    let runs = state.get_online_runs(game_ids, TimeMinutes::new(1).unwrap(), TimeMinutes::new(22).unwrap());
+    let runs: Vec<_> = runs.iter().map(|run| (run.begin, run.end, run.online)).collect();
+    // minute 21 is not observed yet
+    assert_eq!(runs, vec![(1, 6, Some(1)), (6, 11, Some(2)), (11, 20, None), (20, 21, Some(0)), (21, 22, None)]);
+}
+
+#[test]
+fn players_index() {
+    let mut responses = Vec::new();
+    for time in 1..=10 {
+        let mut games = prepare_games(vec![(1, 1), (2, 2)]);
+        games[0].players = vec!["player1".to_owned(), "player\x00".to_owned()];
+        if time > 5 {
+            games[1].players = vec!["player1".to_owned()];
+        }
+        // player2 leaves at minute 9
+        if time > 5 && time < 9 {
+            games[1].players.push("player2".to_owned());
+        }
+        responses.push((time, games));
+    }
+    let state_lock = run_updater_with_snapshots(responses);
+
+    let mut state = state_lock.write();
+    let check_index = |state: &state::State| {
+        let sessions = state.get_player_sessions("player1");
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[1].game_id, NonZeroU32::new(2).unwrap());
+        assert_eq!(sessions[1].begin, TimeMinutes::new(6).unwrap());
+        assert_eq!(sessions[1].end, None);
+        let sessions = state.get_player_sessions("player2");
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].end, Some(TimeMinutes::new(9).unwrap()));
+        assert!(state.get_player_sessions("player3").is_empty());
+        // имя хранится с \x01 вместо \x00 (см. `BigString::add`)
+        assert_eq!(state.get_player_sessions("player\x00").len(), 1);
+        assert_eq!(state.get_player_sessions("player\x01").len(), 1);
+    };
+    check_index(&state);
+    state.rebuild_players_index();
+    check_index(&state);
+    state.compress();
+    check_index(&state);
+}
+
+#[test]
+fn player_minutes_in_interval() {
+    let mut responses = Vec::new();
+    for time in 1..=10 {
+        let mut games = prepare_games(vec![(1, 1)]);
+        games[0].players = vec!["player1".to_owned()];
+        if time <= 4 {
+            games[0].players.push("player2".to_owned());
+        }
+        responses.push((time, games));
+    }
+    let state_lock = run_updater_with_snapshots(responses);
+
+    let state = state_lock.read();
+    let game = state.get_game(NonZeroU32::new(1).unwrap());
+    let time = |minutes| TimeMinutes::new(minutes).unwrap();
+    // player1: [1, ∞), player2: [1, 5)
+    assert_eq!(game.player_minutes_in_interval(time(1), time(11)), 10 + 4);
+    assert_eq!(game.player_minutes_in_interval(time(4), time(6)), 2 + 1);
+    assert_eq!(game.player_minutes_in_interval(time(8), time(9)), 1);
+}
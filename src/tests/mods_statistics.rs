@@ -0,0 +1,68 @@
+// `ModsStatistics` по State, построенному updater'ом; моды задаются после его работы
+
+use std::num::NonZeroU32;
+
+use crate::mods_statistics::ModsStatistics;
+use crate::state::{Mod, State, TimeMinutes};
+
+use super::{prepare_games, run_updater_with_snapshots};
+
+const WEEK: u32 = TimeMinutes::WEEK;
+
+fn set_mods(state: &mut State, game_id: u32, mods: &[(&str, &str)]) {
+    let mods = mods.iter()
+        .map(|&(name, version)| Mod { name: state.all_mod_names.add(name), version: state.all_versions.add(version) })
+        .collect();
+    state.get_game_mut(NonZeroU32::new(game_id).unwrap()).mods = Some(mods);
+}
+
+fn versions(versions: &[(&str, usize)]) -> Vec<(String, usize)> {
+    versions.iter().map(|&(version, number_servers)| (version.to_owned(), number_servers)).collect()
+}
+
+#[test]
+fn mods_statistics() {
+    // minutes 1..=30 of the first week and 1..=30 of the second week, between them is observation gap
+    // game 1: first week only, 1 player; game 2: both weeks, no players; game 3: second week only, 2 players
+    let mut responses = Vec::new();
+    for time in 1..=30 {
+        let mut games = prepare_games(vec![(1, 1), (2, 2)]);
+        games[0].players = vec!["player1".to_owned()];
+        responses.push((time, games));
+    }
+    for time in WEEK + 1..=WEEK + 30 {
+        let mut games = prepare_games(vec![(2, 2), (3, 3)]);
+        games[1].players = vec!["player3".to_owned(), "player4".to_owned()];
+        responses.push((time, games));
+    }
+    let state_lock = run_updater_with_snapshots(responses);
+
+    let mut state = state_lock.write();
+    set_mods(&mut state, 1, &[("base", "0.18.0"), ("mod_a", "1.0.0")]);
+    set_mods(&mut state, 2, &[("base", "0.18.0"), ("mod_b", "1.0.0")]);
+    set_mods(&mut state, 3, &[("base", "0.18.0"), ("mod_a", "1.1.0"), ("mod_b", "1.0.0")]);
+    let server_id = |game_id| state.get_game(NonZeroU32::new(game_id).unwrap()).server_id.unwrap();
+    let (server_id1, server_id3) = (server_id(1), server_id(3));
+
+    let statistics = ModsStatistics::build(&state);
+    assert!(!statistics.mods.contains_key("base"));
+    assert_eq!(statistics.by_number_servers_now, vec!["mod_b".to_owned(), "mod_a".to_owned()]);
+    assert_eq!(statistics.by_player_minutes, vec!["mod_a".to_owned(), "mod_b".to_owned()]);
+
+    let mod_a = &statistics.mods["mod_a"];
+    assert_eq!((mod_a.number_servers_now, mod_a.number_servers_all), (1, 2));
+    // game 1: [1, 31), game 3: 2 players online until the minute after the last observation
+    assert_eq!(mod_a.player_minutes, 30 + 2 * 30);
+    assert_eq!(mod_a.top_servers, vec![(server_id3, 60), (server_id1, 30)]);
+    let weeks: Vec<(u32, Vec<(String, usize)>)> = mod_a.versions_by_week.iter()
+        .map(|(week_begin, versions)| (week_begin.get(), versions.clone()))
+        .collect();
+    let second_week_begin = WEEK - 3 * 24 * 60;
+    assert_eq!(weeks, vec![(1, versions(&[("1.0.0", 1)])), (second_week_begin, versions(&[("1.1.0", 1)]))]);
+
+    let mod_b = &statistics.mods["mod_b"];
+    assert_eq!((mod_b.number_servers_now, mod_b.number_servers_all), (2, 2));
+    assert_eq!(mod_b.player_minutes, 2 * 30);
+    let versions_by_week: Vec<_> = mod_b.versions_by_week.iter().map(|(_, versions)| versions.clone()).collect();
+    assert_eq!(versions_by_week, vec![versions(&[("1.0.0", 1)]), versions(&[("1.0.0", 2)])]);
+}
@@ -1,6 +1,9 @@
 use std::io::{BufReader, BufWriter, Read, Write};
 use std::time::{Duration, SystemTime};
 
+use lazy_static::lazy_static;
+use regex::Regex;
+
 pub mod games_map;
 pub mod map_deref;
 
@@ -15,6 +18,14 @@ pub fn basename(path: &str) -> &str {
     }
 }
 
+/// removes tags like `[color=red]`, `[/color]`, `[item=iron-plate]` (https://wiki.factorio.com/Rich_text)
+pub fn strip_rich_text(text: &str) -> String {
+    lazy_static! {
+        static ref RICH_TEXT_TAG: Regex = Regex::new(r"\[/?[a-z-]+(=[^\]]*)?\]").unwrap();
+    }
+    RICH_TEXT_TAG.replace_all(text, "").trim().to_owned()
+}
+
 pub fn print_heap_stats() {
     use jemalloc_ctl::{stats, epoch};
 
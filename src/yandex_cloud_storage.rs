@@ -105,6 +105,10 @@ fn upload(path: &str, filename: &Path, content_type: &str) -> Result<(), Box<dyn
 }
 
 pub fn upload_with_retries(path: &str, filename: &Path, content_type: &str, number_retries: usize) {
+    try_upload_with_retries(path, filename, content_type, number_retries).unwrap();
+}
+
+pub fn try_upload_with_retries(path: &str, filename: &Path, content_type: &str, number_retries: usize) -> Result<(), Box<dyn Error>> {
     util::run_with_retries(
         number_retries,
         || upload(path, filename, content_type),
@@ -112,7 +116,7 @@ pub fn upload_with_retries(path: &str, filename: &Path, content_type: &str, numb
             eprintln!("[warn]  [yandex_cloud] upload failed (retry_index = {}):\n\tpath: {}\n\terror message: {}",
                       retry_index, path, response);
         },
-    ).unwrap();
+    )
 }
 
 /// если создавать runtime внутри функции download,